@@ -0,0 +1,38 @@
+use std::error::Error;
+
+use micro_sp_emulation::*;
+
+// cargo run --bin campaign -- <scenario> [--runs N] [--seed S] [key=min..max | key=a,b,c]...
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    micro_sp::initialize_env_logger();
+    let mut args = std::env::args().skip(1);
+    let name = args
+        .next()
+        .ok_or("Usage: campaign <scenario> [--runs N] [--seed S] [key=min..max | key=a,b,c]...")?;
+    let scenario = find_scenario(&name).ok_or_else(|| {
+        format!(
+            "Unknown scenario '{}', one of: {}",
+            name,
+            scenario_names().join(", ")
+        )
+    })?;
+
+    let mut options = CampaignOptions {
+        time_scale: time_scale_from_env(),
+        ..Default::default()
+    };
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--runs" => options.runs = args.next().ok_or("--runs needs a number")?.parse()?,
+            "--seed" => options.seed = Some(args.next().ok_or("--seed needs a number")?.parse()?),
+            parameter => options
+                .parameters
+                .push(CampaignParameter::parse(parameter)?),
+        }
+    }
+
+    let report = run_campaign(scenario.as_ref(), &options).await;
+    println!("{}", report);
+    Ok(())
+}
@@ -0,0 +1,746 @@
+use micro_sp::{SPValue, ServiceRequestState, State, ToSPValue};
+use rand::Rng;
+use rand::prelude::SliceRandom;
+use serde_json::json;
+use std::collections::HashMap;
+use std::{sync::Arc, time::Duration};
+
+use crate::{
+    Degradation, Downtime, EMULATOR_TICK_INTERVAL, EmulatedInstant, Reservation, StateBackend,
+    arbitrate_degraded_mode, arbitrate_request, emulate_downtime, emulated_interval,
+    emulated_sleep, get_string_array, is_known_position, publish_measurement, publish_progress,
+    reject_in_maintenance, resource_capabilities, resource_online_change,
+};
+
+#[derive(Debug, Clone)]
+pub struct AgvRequest {
+    pub command: String,
+    pub position: String,
+    pub current_position: String,
+    pub battery_level: f64,
+    pub routes: Vec<String>,
+    pub dock_station: String,
+    pub low_battery_threshold: f64,
+    pub emulate_execution_time: i64,
+    pub emulated_execution_time: i64,
+    pub emulate_failure_rate: i64,
+    pub emulated_failure_rate: i64,
+    pub emulate_failure_cause: i64,
+    pub emulated_failure_cause: Vec<String>,
+    pub execution_time_factor: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct AgvResponse {
+    pub success: bool,
+    pub failure_cause: String,
+    pub info: String,
+    pub destination: String,
+    pub travel_time: u64,
+}
+
+pub async fn agv_emulator<B: StateBackend + ?Sized>(
+    name: &str,
+    connection_manager: &Arc<B>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut interval = emulated_interval(Duration::from_millis(EMULATOR_TICK_INTERVAL));
+    let log_target = format!("{name}_emulator");
+    log::info!(target: &log_target, "Online.");
+    let started = EmulatedInstant::now();
+    // What the previous tick made of the downtime windows, see resource_online_change.
+    let mut previous_downtime: Option<Downtime> = None;
+
+    let keys: Vec<String> = vec![
+        "request_trigger",
+        "request_state",
+        "total_fail_counter",
+        "subsequent_fail_counter",
+        "owner_command",
+        "owner_estimated",
+        "owner_queue_estimated",
+        "capabilities",
+        "command_command",
+        "position_command",
+        "position_estimated",
+        "docked_estimated",
+        "emulated_battery_level",
+        "emulated_routes",
+        "emulated_dock_station",
+        "emulated_battery_drain_rate",
+        "emulated_battery_charge_rate",
+        "emulated_low_battery_threshold",
+        "emulate_execution_time",
+        "emulated_execution_time",
+        "emulate_failure_rate",
+        "emulated_failure_rate",
+        "emulate_failure_cause",
+        "emulated_failure_cause",
+        "resource_online",
+        "downtime_estimated",
+        "emulate_downtime",
+        "emulated_downtime_windows",
+        "mode_estimated",
+        "emulated_degrading_causes",
+        "emulated_degraded_time_factor",
+        "emulated_degraded_refused_commands",
+    ]
+    .iter()
+    .map(|k| format!("{name}_{k}"))
+    .collect();
+
+    loop {
+        interval.tick().await;
+        if !connection_manager.check_health(&log_target).await {
+            continue;
+        }
+        let state = match connection_manager
+            .get_state_for_keys(&keys, &log_target)
+            .await
+        {
+            Some(s) => s,
+            None => continue,
+        };
+
+        let mut request_trigger =
+            state.get_bool_or_default_to_false(&format!("{name}_request_trigger"), &log_target);
+        let mut request_state =
+            state.get_string_or_default_to_unknown(&format!("{name}_request_state"), &log_target);
+
+        let mut total_fail_counter =
+            state.get_int_or_default_to_zero(&format!("{name}_total_fail_counter"), &log_target);
+        let mut subsequent_fail_counter = state
+            .get_int_or_default_to_zero(&format!("{name}_subsequent_fail_counter"), &log_target);
+        let mut agv_position_estimated = state
+            .get_string_or_default_to_unknown(&format!("{name}_position_estimated"), &log_target);
+        let mut agv_docked_estimated =
+            state.get_bool_or_default_to_false(&format!("{name}_docked_estimated"), &log_target);
+        // The true battery level, the measured one can be noisy or late, see measurement.rs
+        let mut battery_level = state
+            .get_float_or_default_to_zero(&format!("{name}_emulated_battery_level"), &log_target);
+
+        let drain_rate = state.get_float_or_default_to_zero(
+            &format!("{name}_emulated_battery_drain_rate"),
+            &log_target,
+        );
+        let charge_rate = state.get_float_or_default_to_zero(
+            &format!("{name}_emulated_battery_charge_rate"),
+            &log_target,
+        );
+
+        // The battery refills while the AGV is docked and idle
+        if agv_docked_estimated {
+            battery_level =
+                (battery_level + charge_rate * EMULATOR_TICK_INTERVAL as f64 / 1000.0).min(100.0);
+        }
+
+        let downtime = emulate_downtime(
+            name,
+            &state,
+            started.elapsed().as_millis() as u64,
+            &log_target,
+        );
+
+        let mut reservation = Reservation::from_state(name, &state, &log_target);
+        let mut degradation = Degradation::from_state(name, &state, &log_target);
+
+        // An offline resource leaves the request pending until it is back
+        if request_trigger && downtime != Downtime::Offline {
+            request_trigger = false;
+            if request_state == ServiceRequestState::Initial.to_string() {
+                let emulated_failure_cause_sp_value = state.get_array_or_default_to_empty(
+                    &format!("{name}_emulated_failure_cause"),
+                    &log_target,
+                );
+
+                let emulated_failure_cause: Vec<String> = emulated_failure_cause_sp_value
+                    .iter()
+                    .filter(|val| val.is_string())
+                    .map(|y| y.to_string())
+                    .collect();
+
+                let routes: Vec<String> = state
+                    .get_array_or_default_to_empty(&format!("{name}_emulated_routes"), &log_target)
+                    .iter()
+                    .filter(|val| val.is_string())
+                    .map(|y| y.to_string())
+                    .collect();
+
+                let request = AgvRequest {
+                    command: state.get_string_or_default_to_unknown(
+                        &format!("{name}_command_command"),
+                        &log_target,
+                    ),
+                    position: state.get_string_or_default_to_unknown(
+                        &format!("{name}_position_command"),
+                        &log_target,
+                    ),
+                    current_position: agv_position_estimated.clone(),
+                    battery_level,
+                    routes,
+                    dock_station: state.get_string_or_default_to_unknown(
+                        &format!("{name}_emulated_dock_station"),
+                        &log_target,
+                    ),
+                    low_battery_threshold: state.get_float_or_default_to_zero(
+                        &format!("{name}_emulated_low_battery_threshold"),
+                        &log_target,
+                    ),
+                    emulate_execution_time: state.get_int_or_default_to_zero(
+                        &format!("{name}_emulate_execution_time"),
+                        &log_target,
+                    ),
+                    emulated_execution_time: state.get_int_or_default_to_zero(
+                        &format!("{name}_emulated_execution_time"),
+                        &log_target,
+                    ),
+                    emulate_failure_rate: state.get_int_or_default_to_zero(
+                        &format!("{name}_emulate_failure_rate"),
+                        &log_target,
+                    ),
+                    emulated_failure_rate: state.get_int_or_default_to_zero(
+                        &format!("{name}_emulated_failure_rate"),
+                        &log_target,
+                    ),
+                    emulate_failure_cause: state.get_int_or_default_to_zero(
+                        &format!("{name}_emulate_failure_cause"),
+                        &log_target,
+                    ),
+                    emulated_failure_cause,
+                    execution_time_factor: degradation.execution_time_factor(),
+                };
+
+                let caller = state.get_string_or_default_to_unknown(
+                    &format!("{name}_owner_command"),
+                    &log_target,
+                );
+                let response = match reject_in_maintenance(&downtime, &request.command, &log_target)
+                    .or_else(|| {
+                        arbitrate_request(&request.command, &caller, &mut reservation, &log_target)
+                    })
+                    .or_else(|| {
+                        arbitrate_degraded_mode(&degradation, &request.command, &log_target)
+                    }) {
+                    Some(arbitration) => AgvResponse {
+                        success: arbitration.success,
+                        failure_cause: arbitration.failure_cause,
+                        info: arbitration.info,
+                        destination: agv_position_estimated.clone(),
+                        travel_time: 0,
+                    },
+                    None => {
+                        let mut response = emulate_agv_operation(&request, &log_target);
+
+                        if response.travel_time > 0 {
+                            // Leaving the dock, the battery drains while driving
+                            agv_docked_estimated = false;
+                            let (remaining_battery_level, depleted) = drive(
+                                name,
+                                connection_manager,
+                                response.travel_time,
+                                battery_level,
+                                drain_rate,
+                            )
+                            .await;
+                            battery_level = remaining_battery_level;
+                            if depleted && response.success {
+                                response.success = false;
+                                response.failure_cause = "battery_depleted".to_string();
+                                response.info = format!(
+                                    "Failed to reach {} due to battery_depleted.",
+                                    response.destination
+                                );
+                            }
+                        } else {
+                            publish_progress(name, connection_manager, 100, 0, &log_target).await;
+                        }
+
+                        if response.success {
+                            log::info!(target: &log_target, "{}", response.info);
+                        } else {
+                            log::error!(target: &log_target, "{}", response.info);
+                        }
+                        response
+                    }
+                };
+                degradation.update(
+                    &request.command,
+                    response.success,
+                    &response.failure_cause,
+                    &log_target,
+                );
+
+                request_state = if response.success {
+                    subsequent_fail_counter = 0;
+                    match request.command.as_str() {
+                        "go_to" => {
+                            agv_position_estimated = response.destination;
+                            agv_docked_estimated = false;
+                        }
+                        "dock" => {
+                            agv_position_estimated = response.destination;
+                            agv_docked_estimated = true;
+                        }
+                        _ => (),
+                    }
+                    ServiceRequestState::Succeeded.to_string()
+                } else {
+                    subsequent_fail_counter += 1;
+                    total_fail_counter += 1;
+                    ServiceRequestState::Failed.to_string()
+                };
+            }
+        }
+        let new_state = state
+            .update(
+                &format!("{name}_request_trigger"),
+                request_trigger.to_spvalue(),
+            )
+            .update(&format!("{name}_request_state"), request_state.to_spvalue())
+            .update(
+                &format!("{name}_total_fail_counter"),
+                total_fail_counter.to_spvalue(),
+            )
+            .update(
+                &format!("{name}_subsequent_fail_counter"),
+                subsequent_fail_counter.to_spvalue(),
+            )
+            .update(
+                &format!("{name}_owner_estimated"),
+                reservation.owner_to_spvalue(),
+            )
+            .update(
+                &format!("{name}_owner_queue_estimated"),
+                reservation.queue_to_spvalue(),
+            )
+            .update(
+                &format!("{name}_downtime_estimated"),
+                (downtime != Downtime::Up).to_spvalue(),
+            )
+            .update(
+                &format!("{name}_mode_estimated"),
+                degradation.mode_to_spvalue(),
+            )
+            .update(
+                &format!("{name}_capabilities"),
+                agv_capabilities(name, &state, &log_target),
+            )
+            .update(
+                &format!("{name}_position_estimated"),
+                agv_position_estimated.to_spvalue(),
+            )
+            .update(
+                &format!("{name}_docked_estimated"),
+                agv_docked_estimated.to_spvalue(),
+            )
+            .update(
+                &format!("{name}_emulated_battery_level"),
+                battery_level.to_spvalue(),
+            );
+
+        let new_state = match resource_online_change(previous_downtime.as_ref(), &downtime) {
+            Some(online) => {
+                new_state.update(&format!("{name}_resource_online"), online.to_spvalue())
+            }
+            None => new_state,
+        };
+        previous_downtime = Some(downtime);
+
+        let modified_state = state.get_diff_partial_state(&new_state);
+        connection_manager.set_state(&modified_state).await;
+
+        publish_measurement(
+            connection_manager,
+            &format!("{name}_battery_level_measured"),
+            battery_level.to_spvalue(),
+            &log_target,
+        )
+        .await;
+    }
+}
+
+// Published in "{name}_capabilities", see capabilities.rs.
+// The AGV can go to every station of its route graph and docks at its dock station.
+pub fn agv_capabilities(name: &str, state: &State, log_target: &str) -> SPValue {
+    let stations = agv_stations(&get_string_array(
+        state,
+        &format!("{name}_emulated_routes"),
+        log_target,
+    ));
+    resource_capabilities(
+        vec![
+            ("go_to", json!({"position_command": stations})),
+            ("dock", json!({})),
+        ],
+        &[
+            "unknown_position",
+            "low_battery",
+            "no_route",
+            "battery_depleted",
+        ],
+        &get_string_array(state, &format!("{name}_emulated_failure_cause"), log_target),
+        vec![],
+        vec![("stations", json!(stations))],
+    )
+}
+
+// Drive for the travel time, draining and publishing the battery level and progress every tick.
+// Returns the remaining battery level and whether the battery ran out on the way.
+async fn drive<B: StateBackend + ?Sized>(
+    name: &str,
+    connection_manager: &Arc<B>,
+    travel_time: u64,
+    battery_level: f64,
+    drain_rate: f64,
+) -> (f64, bool) {
+    let mut battery_level = battery_level;
+    let mut driven = 0;
+    publish_progress(
+        name,
+        connection_manager,
+        0,
+        travel_time,
+        &format!("{name}_emulator"),
+    )
+    .await;
+    while driven < travel_time {
+        let step = EMULATOR_TICK_INTERVAL.min(travel_time - driven);
+        emulated_sleep(Duration::from_millis(step)).await;
+        driven += step;
+        battery_level = (battery_level - drain_rate * step as f64 / 1000.0).max(0.0);
+
+        publish_progress(
+            name,
+            connection_manager,
+            driven * 100 / travel_time,
+            travel_time - driven,
+            &format!("{name}_emulator"),
+        )
+        .await;
+
+        publish_measurement(
+            connection_manager,
+            &format!("{name}_battery_level_measured"),
+            battery_level.to_spvalue(),
+            &format!("{name}_emulator"),
+        )
+        .await;
+
+        if battery_level <= 0.0 {
+            return (battery_level, true);
+        }
+    }
+    (battery_level, false)
+}
+
+// Routes are given as "from-to:travel_time_ms" and can be driven in both directions.
+fn parse_routes(routes: &[String]) -> HashMap<String, Vec<(String, u64)>> {
+    let mut graph: HashMap<String, Vec<(String, u64)>> = HashMap::new();
+    for route in routes {
+        let Some((stations, travel_time)) = route.split_once(':') else {
+            log::warn!(target: "agv_emulator", "Route '{}' is not of the form 'from-to:ms'.", route);
+            continue;
+        };
+        let Some((from, to)) = stations.split_once('-') else {
+            log::warn!(target: "agv_emulator", "Route '{}' is not of the form 'from-to:ms'.", route);
+            continue;
+        };
+        let Ok(travel_time) = travel_time.trim().parse::<u64>() else {
+            log::warn!(target: "agv_emulator", "Route '{}' has an invalid travel time.", route);
+            continue;
+        };
+        let (from, to) = (from.trim().to_string(), to.trim().to_string());
+        graph
+            .entry(from.clone())
+            .or_default()
+            .push((to.clone(), travel_time));
+        graph.entry(to).or_default().push((from, travel_time));
+    }
+    graph
+}
+
+// All stations in the route graph, sorted by name.
+pub fn agv_stations(routes: &[String]) -> Vec<String> {
+    let mut stations: Vec<String> = parse_routes(routes).into_keys().collect();
+    stations.sort();
+    stations
+}
+
+// Shortest travel time between two stations in the route graph.
+pub fn agv_travel_time(routes: &[String], from: &str, to: &str) -> Option<u64> {
+    if from == to {
+        return Some(0);
+    }
+    let graph = parse_routes(routes);
+    let mut best: HashMap<String, u64> = HashMap::from([(from.to_string(), 0)]);
+    let mut unvisited: Vec<String> = vec![from.to_string()];
+    while let Some((index, _)) = unvisited
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, station)| best[*station])
+    {
+        let station = unvisited.swap_remove(index);
+        if station == to {
+            return Some(best[&station]);
+        }
+        for (next, travel_time) in graph.get(&station).cloned().unwrap_or_default() {
+            let candidate = best[&station] + travel_time;
+            if best.get(&next).map_or(true, |known| candidate < *known) {
+                best.insert(next.clone(), candidate);
+                if !unvisited.contains(&next) {
+                    unvisited.push(next);
+                }
+            }
+        }
+    }
+    None
+}
+
+pub fn emulate_agv_operation(request: &AgvRequest, log_target: &str) -> AgvResponse {
+    let mut fail = match request.emulate_failure_rate {
+        0 => false,
+        1 => true,
+        2 => crate::emulator_rng().gen_range(0..=100) <= request.emulated_failure_rate as u64,
+        _ => false,
+    };
+    let mut forced_cause: Option<String> = None;
+
+    let destination = match request.command.as_str() {
+        "go_to" => {
+            log::info!(target: log_target, "Got request to go to {}.", request.position);
+            request.position.clone()
+        }
+        "dock" => {
+            log::info!(target: log_target, "Got request to dock at {}.", request.dock_station);
+            request.dock_station.clone()
+        }
+        _ => {
+            log::warn!(target: log_target, "Unknown command: '{}'", request.command);
+            fail = true;
+            request.current_position.clone()
+        }
+    };
+
+    // The stations of the route graph are the position catalog of the AGV
+    if !fail && !is_known_position(&agv_stations(&request.routes), &destination) {
+        fail = true;
+        forced_cause = Some("unknown_position".to_string());
+    }
+
+    // Docking is always allowed, otherwise an AGV with a low battery could never recharge
+    if request.command == "go_to"
+        && request.battery_level < request.low_battery_threshold
+        && forced_cause.is_none()
+    {
+        fail = true;
+        forced_cause = Some("low_battery".to_string());
+    }
+
+    let route_travel_time =
+        agv_travel_time(&request.routes, &request.current_position, &destination);
+    if route_travel_time.is_none() && !fail {
+        fail = true;
+        forced_cause = Some("no_route".to_string());
+    }
+
+    // The route graph gives the travel time. Only an explicit execution time emulation
+    // changes it: an exact execution time replaces it and a random one adds traffic on top.
+    let travel_time: u64 = if fail {
+        0
+    } else {
+        let route_travel_time = route_travel_time.unwrap_or_default();
+        let emulated_execution_time = request.emulated_execution_time.max(0) as u64;
+        match request.emulate_execution_time {
+            1 => emulated_execution_time,
+            2 => route_travel_time + crate::emulator_rng().gen_range(0..=emulated_execution_time),
+            _ => route_travel_time,
+        }
+    };
+    // Everything takes longer in degraded mode
+    let travel_time = (travel_time as f64 * request.execution_time_factor) as u64;
+
+    let cause = if fail {
+        match forced_cause {
+            Some(cause) => cause,
+            None => match request.emulate_failure_cause {
+                0 => "generic_failure".to_string(),
+                1 => request
+                    .emulated_failure_cause
+                    .get(0)
+                    .cloned()
+                    .unwrap_or_else(|| "config_error".to_string()),
+                2 => request
+                    .emulated_failure_cause
+                    .choose(&mut crate::emulator_rng())
+                    .cloned()
+                    .unwrap_or_else(|| "random_error".to_string()),
+                _ => "generic_failure".to_string(),
+            },
+        }
+    } else {
+        "".to_string()
+    };
+
+    let (success_info, failure_info) = match request.command.as_str() {
+        "go_to" => (
+            format!("Succeeded to go to {}.", destination),
+            format!("Failed to go to {} due to {}.", destination, cause),
+        ),
+        "dock" => (
+            format!("Succeeded to dock at {}.", destination),
+            format!("Failed to dock at {} due to {}.", destination, cause),
+        ),
+        _ => (
+            "Failed, unknown command".to_string(),
+            "Failed, unknown command".to_string(),
+        ),
+    };
+
+    // The outcome is logged by the emulator once the AGV has actually arrived
+    if !fail {
+        AgvResponse {
+            success: true,
+            failure_cause: "".to_string(),
+            info: success_info,
+            destination,
+            travel_time,
+        }
+    } else {
+        AgvResponse {
+            success: false,
+            failure_cause: cause,
+            info: failure_info,
+            destination,
+            travel_time,
+        }
+    }
+}
+
+#[test]
+fn test_agv_travel_time() {
+    let routes: Vec<String> = vec![
+        "charger-a:4000",
+        "a-b:3000",
+        "b-c:3000",
+        "c-charger:5000",
+        "d-e:1000",
+    ]
+    .iter()
+    .map(|route| route.to_string())
+    .collect();
+    assert_eq!(agv_travel_time(&routes, "charger", "charger"), Some(0));
+    // Routes are driven in both directions, along the shortest path
+    assert_eq!(agv_travel_time(&routes, "a", "charger"), Some(4000));
+    assert_eq!(agv_travel_time(&routes, "charger", "b"), Some(7000));
+    assert_eq!(agv_travel_time(&routes, "charger", "c"), Some(5000));
+    assert_eq!(agv_travel_time(&routes, "charger", "d"), None);
+    assert_eq!(agv_travel_time(&routes, "charger", "x"), None);
+    assert_eq!(
+        agv_stations(&routes),
+        vec!["a", "b", "c", "charger", "d", "e"]
+    );
+}
+
+#[test]
+fn test_emulate_agv_operation() {
+    let request = |command: &str, position: &str, battery_level: f64| AgvRequest {
+        command: command.to_string(),
+        position: position.to_string(),
+        current_position: "charger".to_string(),
+        battery_level,
+        routes: vec!["charger-a:4000", "a-b:3000", "d-e:1000"]
+            .iter()
+            .map(|route| route.to_string())
+            .collect(),
+        dock_station: "charger".to_string(),
+        low_battery_threshold: 20.0,
+        emulate_execution_time: 0,
+        emulated_execution_time: 0,
+        emulate_failure_rate: 0,
+        emulated_failure_rate: 0,
+        emulate_failure_cause: 0,
+        emulated_failure_cause: vec![],
+        execution_time_factor: 1.0,
+    };
+
+    let response = emulate_agv_operation(&request("go_to", "b", 50.0), "test_agv");
+    assert!(response.success);
+    assert_eq!(response.destination, "b");
+    assert_eq!(response.travel_time, 7000);
+
+    let response = emulate_agv_operation(&request("go_to", "b", 10.0), "test_agv");
+    assert!(!response.success);
+    assert_eq!(response.failure_cause, "low_battery");
+    assert_eq!(response.travel_time, 0);
+
+    // Docking is accepted with a low battery, and the AGV is already at its dock station
+    let response = emulate_agv_operation(&request("dock", "b", 10.0), "test_agv");
+    assert!(response.success);
+    assert_eq!(response.destination, "charger");
+    assert_eq!(response.travel_time, 0);
+
+    let response = emulate_agv_operation(&request("go_to", "x", 50.0), "test_agv");
+    assert_eq!(response.failure_cause, "unknown_position");
+
+    let response = emulate_agv_operation(&request("go_to", "d", 50.0), "test_agv");
+    assert_eq!(response.failure_cause, "no_route");
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_agv_emulator_battery() {
+    let log_target = "test_agv_emulator_battery";
+    let state = crate::model::state::state();
+    let backend: Arc<dyn StateBackend> = Arc::new(crate::InMemoryBackend::new(&state));
+    let emulator =
+        crate::spawn_emulator(&crate::ScenarioEmulator::Agv("agv".to_string()), &backend);
+
+    let started = EmulatedInstant::now();
+    let state = crate::complete_request(
+        &backend,
+        "agv",
+        &[
+            ("command_command", "go_to".to_spvalue()),
+            ("position_command", "a".to_spvalue()),
+        ],
+    )
+    .await;
+    assert!(started.elapsed() >= Duration::from_millis(4000));
+    assert_eq!(
+        state.get_string_or_default_to_unknown("agv_request_state", log_target),
+        "succeeded"
+    );
+    assert_eq!(
+        state.get_string_or_default_to_unknown("agv_position_estimated", log_target),
+        "a"
+    );
+    assert!(!state.get_bool_or_default_to_false("agv_docked_estimated", log_target));
+    // 4 seconds of driving at 1 percent per second
+    let battery_level =
+        state.get_float_or_default_to_zero("agv_emulated_battery_level", log_target);
+    assert!((battery_level - 96.0).abs() < 1e-6, "{battery_level}");
+    let measured = state.get_float_or_default_to_zero("agv_battery_level_measured", log_target);
+    assert!((measured - 96.0).abs() < 1e-6, "{measured}");
+
+    // The emulator picks up a battery level set from outside on its next tick
+    let new_state = state.update("agv_emulated_battery_level", 10.0.to_spvalue());
+    backend
+        .set_state(&state.get_diff_partial_state(&new_state))
+        .await;
+    let state = crate::complete_request(
+        &backend,
+        "agv",
+        &[
+            ("command_command", "go_to".to_spvalue()),
+            ("position_command", "b".to_spvalue()),
+        ],
+    )
+    .await;
+    assert_eq!(
+        state.get_string_or_default_to_unknown("agv_request_state", log_target),
+        "failed"
+    );
+    assert_eq!(
+        state.get_string_or_default_to_unknown("agv_position_estimated", log_target),
+        "a"
+    );
+    emulator.abort();
+}
@@ -0,0 +1,127 @@
+use futures::future::BoxFuture;
+use micro_sp::{ConnectionManager, SPValue, State, StateManager};
+use std::sync::RwLock;
+
+// Where the emulators and the scenario harness read and write the state. Redis, through
+// the ConnectionManager of micro_sp, or an in-process state that needs neither Redis nor
+// Docker. The runner itself (main_runner) always talks Redis, so the in-memory backend is
+// for emulators and tooling on their own. To run the runner without Docker, point the
+// ConnectionManager at an InProcessRedis instead, as the scenario harness does.
+pub trait StateBackend: Send + Sync + 'static {
+    fn check_health<'a>(&'a self, log_target: &'a str) -> BoxFuture<'a, bool>;
+
+    fn get_state_for_keys<'a>(
+        &'a self,
+        keys: &'a [String],
+        log_target: &'a str,
+    ) -> BoxFuture<'a, Option<State>>;
+
+    fn set_state<'a>(&'a self, state: &'a State) -> BoxFuture<'a, ()>;
+
+    fn get_full_state(&self) -> BoxFuture<'_, Option<State>>;
+
+    fn get_sp_value<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Option<SPValue>>;
+}
+
+impl StateBackend for ConnectionManager {
+    fn check_health<'a>(&'a self, log_target: &'a str) -> BoxFuture<'a, bool> {
+        Box::pin(async move { self.check_redis_health(log_target).await.is_ok() })
+    }
+
+    fn get_state_for_keys<'a>(
+        &'a self,
+        keys: &'a [String],
+        log_target: &'a str,
+    ) -> BoxFuture<'a, Option<State>> {
+        Box::pin(async move {
+            let mut con = self.get_connection().await;
+            StateManager::get_state_for_keys(&mut con, &keys.to_vec(), log_target).await
+        })
+    }
+
+    fn set_state<'a>(&'a self, state: &'a State) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            let mut con = self.get_connection().await;
+            StateManager::set_state(&mut con, state).await
+        })
+    }
+
+    fn get_full_state(&self) -> BoxFuture<'_, Option<State>> {
+        Box::pin(async move {
+            let mut con = self.get_connection().await;
+            StateManager::get_full_state(&mut con).await
+        })
+    }
+
+    fn get_sp_value<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Option<SPValue>> {
+        Box::pin(async move {
+            let mut con = self.get_connection().await;
+            StateManager::get_sp_value(&mut con, key).await
+        })
+    }
+}
+
+// The whole state in a map in memory. Setting a partial state overwrites the variables in
+// it, like with Redis, and reading a variable that was never set gives nothing.
+#[derive(Debug)]
+pub struct InMemoryBackend {
+    state: RwLock<State>,
+}
+
+impl InMemoryBackend {
+    pub fn new(state: &State) -> InMemoryBackend {
+        InMemoryBackend {
+            state: RwLock::new(state.clone()),
+        }
+    }
+}
+
+impl StateBackend for InMemoryBackend {
+    fn check_health<'a>(&'a self, _log_target: &'a str) -> BoxFuture<'a, bool> {
+        Box::pin(async { true })
+    }
+
+    fn get_state_for_keys<'a>(
+        &'a self,
+        keys: &'a [String],
+        log_target: &'a str,
+    ) -> BoxFuture<'a, Option<State>> {
+        Box::pin(async move {
+            let state = self.state.read().unwrap();
+            let mut partial_state = State::new();
+            for key in keys {
+                match state.state.get(key) {
+                    Some(assignment) => {
+                        partial_state.state.insert(key.clone(), assignment.clone());
+                    }
+                    None => log::warn!(target: log_target, "Key '{}' is not in the state.", key),
+                }
+            }
+            Some(partial_state)
+        })
+    }
+
+    fn set_state<'a>(&'a self, state: &'a State) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            let mut full_state = self.state.write().unwrap();
+            for (key, assignment) in &state.state {
+                full_state.state.insert(key.clone(), assignment.clone());
+            }
+        })
+    }
+
+    fn get_full_state(&self) -> BoxFuture<'_, Option<State>> {
+        Box::pin(async move { Some(self.state.read().unwrap().clone()) })
+    }
+
+    fn get_sp_value<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Option<SPValue>> {
+        Box::pin(async move {
+            self.state
+                .read()
+                .unwrap()
+                .state
+                .get(key)
+                .map(|assignment| assignment.val.clone())
+        })
+    }
+}
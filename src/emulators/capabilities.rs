@@ -0,0 +1,211 @@
+use micro_sp::{SPValue, State, ToSPValue};
+use serde_json::{Map, Value, json};
+
+use crate::{
+    DONT_EMULATE_DOWNTIME, DONT_EMULATE_EXECUTION_TIME, DONT_EMULATE_FAILURE,
+    DONT_EMULATE_FAILURE_CAUSE, EMULATE_DOWNTIME_MAINTENANCE, EMULATE_DOWNTIME_OFFLINE,
+    EMULATE_EXACT_EXECUTION_TIME, EMULATE_EXACT_FAILURE_CAUSE, EMULATE_FAILURE_ALWAYS,
+    EMULATE_FAILURE_RANDOM_RATE, EMULATE_RANDOM_EXECUTION_TIME, EMULATE_RANDOM_FAILURE_CAUSE,
+};
+
+// What a resource supports, published by its emulator in "{name}_capabilities" as a JSON
+// string, so that UIs, goal generators and model linters don't have to repeat the lists of
+// commands and positions of every resource. For example, for a gantry:
+// {
+//   "commands": {
+//     "move": {"position_command": ["home", "a", ...], "speed_command": {"type": "float", "min": 0.0, "max": 1.0}},
+//     "calibrate": {}, "lock": {}, "unlock": {},
+//     "reserve": {"owner_command": {"type": "string"}}, "release": {"owner_command": {"type": "string"}}
+//   },
+//   "failure_causes": ["generic_failure", "invalid_speed", ...],
+//   "emulation_modes": {"emulate_execution_time": [0, 1, 2], ...},
+//   "positions": ["home", "a", ...]
+// }
+// A parameter is either a list of its valid values or a description of its type and range.
+// Every resource can be reserved, released and reset, and has the same execution time, failure,
+// downtime and queue emulation modes, so these are added here. The catalogs, e.g. "positions",
+// "tools" or "stations", depend on the resource.
+pub fn resource_capabilities(
+    commands: Vec<(&str, Value)>,
+    failure_causes: &[&str],
+    emulated_failure_cause: &[String],
+    emulation_modes: Vec<(&str, Value)>,
+    catalogs: Vec<(&str, Value)>,
+) -> SPValue {
+    let mut all_commands = Map::new();
+    for (command, parameters) in commands {
+        all_commands.insert(command.to_string(), parameters);
+    }
+    all_commands.insert(
+        "reserve".to_string(),
+        json!({"owner_command": {"type": "string"}}),
+    );
+    all_commands.insert(
+        "release".to_string(),
+        json!({"owner_command": {"type": "string"}}),
+    );
+    all_commands.insert("reset".to_string(), json!({}));
+
+    let mut all_failure_causes: Vec<String> = failure_causes
+        .iter()
+        .chain(
+            [
+                "generic_failure",
+                "missing_owner",
+                "reserved",
+                "not_owner",
+                "maintenance",
+                "degraded",
+            ]
+            .iter(),
+        )
+        .map(|cause| cause.to_string())
+        .chain(emulated_failure_cause.iter().cloned())
+        .collect();
+    all_failure_causes.sort();
+    all_failure_causes.dedup();
+
+    let mut all_emulation_modes = Map::new();
+    all_emulation_modes.insert(
+        "emulate_execution_time".to_string(),
+        json!([
+            DONT_EMULATE_EXECUTION_TIME,
+            EMULATE_EXACT_EXECUTION_TIME,
+            EMULATE_RANDOM_EXECUTION_TIME
+        ]),
+    );
+    all_emulation_modes.insert(
+        "emulate_failure_rate".to_string(),
+        json!([
+            DONT_EMULATE_FAILURE,
+            EMULATE_FAILURE_ALWAYS,
+            EMULATE_FAILURE_RANDOM_RATE
+        ]),
+    );
+    all_emulation_modes.insert(
+        "emulate_failure_cause".to_string(),
+        json!([
+            DONT_EMULATE_FAILURE_CAUSE,
+            EMULATE_EXACT_FAILURE_CAUSE,
+            EMULATE_RANDOM_FAILURE_CAUSE
+        ]),
+    );
+    all_emulation_modes.insert(
+        "emulate_downtime".to_string(),
+        json!([
+            DONT_EMULATE_DOWNTIME,
+            EMULATE_DOWNTIME_MAINTENANCE,
+            EMULATE_DOWNTIME_OFFLINE
+        ]),
+    );
+    all_emulation_modes.insert("emulate_queue".to_string(), json!([false, true]));
+    for (mode, values) in emulation_modes {
+        all_emulation_modes.insert(mode.to_string(), values);
+    }
+
+    let mut capabilities = Map::new();
+    capabilities.insert("commands".to_string(), Value::Object(all_commands));
+    capabilities.insert("failure_causes".to_string(), json!(all_failure_causes));
+    capabilities.insert(
+        "emulation_modes".to_string(),
+        Value::Object(all_emulation_modes),
+    );
+    for (catalog, values) in catalogs {
+        capabilities.insert(catalog.to_string(), values);
+    }
+
+    Value::Object(capabilities).to_string().to_spvalue()
+}
+
+// The valid values of a parameter of a command, e.g. all positions a gantry can move to.
+// Empty if the resource doesn't have the command, or if the parameter is not an enumeration.
+pub fn capability_values(capabilities: &str, command: &str, parameter: &str) -> Vec<String> {
+    match serde_json::from_str::<Value>(capabilities) {
+        Ok(value) => match &value["commands"][command][parameter] {
+            Value::Array(values) => values
+                .iter()
+                .filter_map(|value| value.as_str())
+                .map(|value| value.to_string())
+                .collect(),
+            _ => vec![],
+        },
+        Err(_) => vec![],
+    }
+}
+
+// The strings of an array variable of the resource, e.g. its "emulated_positions".
+pub fn get_string_array(state: &State, key: &str, log_target: &str) -> Vec<String> {
+    state
+        .get_array_or_default_to_empty(key, log_target)
+        .iter()
+        .filter(|val| val.is_string())
+        .map(|y| y.to_string())
+        .collect()
+}
+
+#[test]
+fn test_resource_capabilities() {
+    let capabilities = match resource_capabilities(
+        vec![
+            (
+                "move",
+                json!({
+                    "position_command": ["a", "b"],
+                    "speed_command": {"type": "float", "min": 0.0, "max": 1.0}
+                }),
+            ),
+            ("calibrate", json!({})),
+        ],
+        &["invalid_speed"],
+        &["collision".to_string(), "generic_failure".to_string()],
+        vec![("emulate_queue", json!([false]))],
+        vec![("positions", json!(["a", "b"]))],
+    ) {
+        SPValue::String(micro_sp::StringOrUnknown::String(capabilities)) => capabilities,
+        value => panic!("Expected a JSON string, got {value:?}."),
+    };
+
+    // The catalogs of the resource, and nothing for parameters that aren't enumerations
+    assert_eq!(
+        capability_values(&capabilities, "move", "position_command"),
+        vec!["a", "b"]
+    );
+    assert!(capability_values(&capabilities, "move", "speed_command").is_empty());
+    assert!(capability_values(&capabilities, "fly", "position_command").is_empty());
+    assert!(capability_values("not json", "move", "position_command").is_empty());
+
+    let value: Value = serde_json::from_str(&capabilities).unwrap();
+    // Every resource can be reserved, released and reset
+    for command in ["move", "calibrate", "reserve", "release", "reset"] {
+        assert!(value["commands"][command].is_object(), "{command}");
+    }
+    // The causes of the resource, the common ones and the emulated ones, each once
+    let failure_causes: Vec<&str> = value["failure_causes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter_map(|cause| cause.as_str())
+        .collect();
+    assert_eq!(
+        failure_causes
+            .iter()
+            .filter(|cause| **cause == "generic_failure")
+            .count(),
+        1
+    );
+    assert!(failure_causes.contains(&"collision"));
+    assert!(failure_causes.contains(&"invalid_speed"));
+    assert!(failure_causes.contains(&"maintenance"));
+    assert!(failure_causes.is_sorted());
+    // The modes of the resource override the common ones
+    assert_eq!(value["emulation_modes"]["emulate_queue"], json!([false]));
+    assert_eq!(
+        value["emulation_modes"]["emulate_failure_rate"],
+        json!([
+            DONT_EMULATE_FAILURE,
+            EMULATE_FAILURE_ALWAYS,
+            EMULATE_FAILURE_RANDOM_RATE
+        ])
+    );
+    assert_eq!(value["positions"], json!(["a", "b"]));
+}
@@ -0,0 +1,123 @@
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tokio::time::{Instant, Interval};
+
+// Emulated time. Everything the emulators wait for goes through tokio's clock, so tests
+// can pause it with #[tokio::test(start_paused = true)] and let it jump ahead whenever
+// all tasks are waiting, instead of sleeping for real. The runner ticks on tokio's clock
+// too, but checks operation timeouts against the system time, which pausing doesn't
+// touch. On top of that, the time scale makes emulated time run faster (or slower) than
+// real time, e.g. with a time scale of 10.0 a 6000 ms robot move takes 600 ms.
+//
+// The time scale belongs to the tasks of one harness, campaign or binary, so runs next
+// to each other can have different ones. Only the emulators, the injections and the
+// harness are scaled, the runner of micro_sp waits and times out in real time.
+tokio::task_local! {
+    static TIME_SCALE: f64;
+}
+
+// Environment variable with the time scale of a run, e.g. EMULATION_TIME_SCALE=10 to run
+// the emulators ten times faster. The runner still ticks in real time and sets the pace
+// once the emulated times get shorter than its ticks.
+pub static EMULATION_TIME_SCALE: &str = "EMULATION_TIME_SCALE";
+
+// The time scale of the current task, real time outside of with_time_scale.
+pub fn time_scale() -> f64 {
+    TIME_SCALE.try_with(|time_scale| *time_scale).unwrap_or(1.0)
+}
+
+// The time scale if it is positive and finite, real time otherwise.
+pub fn valid_time_scale(time_scale: f64) -> f64 {
+    if time_scale.is_finite() && time_scale > 0.0 {
+        time_scale
+    } else {
+        log::warn!(target: "clock", "Invalid time scale: {}, running in real time.", time_scale);
+        1.0
+    }
+}
+
+// Runs the future, and the tasks it spawns with spawn_emulated, at the time scale.
+pub async fn with_time_scale<F: Future>(time_scale: f64, future: F) -> F::Output {
+    TIME_SCALE.scope(valid_time_scale(time_scale), future).await
+}
+
+// Spawns a task at the time scale and with the random generator of the caller,
+// tokio::task::spawn starts it in real time and unseeded.
+pub fn spawn_emulated<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    let future = TIME_SCALE.scope(time_scale(), future);
+    match crate::emulators::random::seeded_rng() {
+        Some(rng) => tokio::task::spawn(crate::emulators::random::with_rng(rng, future)),
+        None => tokio::task::spawn(future),
+    }
+}
+
+// The time scale in EMULATION_TIME_SCALE, real time if it is not set.
+pub fn time_scale_from_env() -> f64 {
+    match std::env::var(EMULATION_TIME_SCALE) {
+        Ok(value) => match value.parse::<f64>() {
+            Ok(time_scale) => valid_time_scale(time_scale),
+            Err(_) => {
+                log::warn!(target: "clock", "Invalid {}: '{}', running in real time.", EMULATION_TIME_SCALE, value);
+                1.0
+            }
+        },
+        Err(_) => 1.0,
+    }
+}
+
+// How long an emulated duration takes in real time.
+pub fn to_real_duration(duration: Duration) -> Duration {
+    duration.div_f64(time_scale())
+}
+
+pub async fn emulated_sleep(duration: Duration) {
+    tokio::time::sleep(to_real_duration(duration)).await
+}
+
+pub fn emulated_interval(period: Duration) -> Interval {
+    tokio::time::interval(to_real_duration(period))
+}
+
+// Keeps the time scale it was taken at, so it can be read from any task.
+#[derive(Debug, Clone, Copy)]
+pub struct EmulatedInstant {
+    instant: Instant,
+    time_scale: f64,
+}
+
+impl EmulatedInstant {
+    pub fn now() -> EmulatedInstant {
+        EmulatedInstant::at_time_scale(time_scale())
+    }
+
+    pub fn at_time_scale(time_scale: f64) -> EmulatedInstant {
+        EmulatedInstant {
+            instant: Instant::now(),
+            time_scale,
+        }
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.instant.elapsed().mul_f64(self.time_scale)
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_time_scale() {
+    assert_eq!(time_scale(), 1.0);
+    let fast = with_time_scale(10.0, async {
+        let started = EmulatedInstant::now();
+        assert_eq!(spawn_emulated(async { time_scale() }).await.unwrap(), 10.0);
+        emulated_sleep(Duration::from_secs(10)).await;
+        (time_scale(), started)
+    });
+    let slow = with_time_scale(-1.0, async { time_scale() });
+    let ((fast_scale, started), slow_scale) = tokio::join!(fast, slow);
+    assert_eq!((fast_scale, slow_scale), (10.0, 1.0));
+    assert_eq!(started.elapsed(), Duration::from_secs(10));
+    assert_eq!(time_scale(), 1.0);
+}
@@ -0,0 +1,146 @@
+use micro_sp::{SPValue, State, ToSPValue};
+
+use crate::{ReservationResponse, get_string_array};
+
+// After some failures, real equipment continues in a reduced mode instead of failing
+// for good. A failure with one of the "{name}_emulated_degrading_causes" puts the
+// resource in degraded mode, where execution times are multiplied by
+// "{name}_emulated_degraded_time_factor" and the "{name}_emulated_degraded_refused_commands"
+// fail with "degraded". A successful reset (or calibrate, for resources that have it)
+// restores the normal mode. The mode is published in "{name}_mode_estimated".
+#[derive(Debug, Clone)]
+pub struct Degradation {
+    pub degraded: bool,
+    pub degrading_causes: Vec<String>,
+    pub time_factor: f64,
+    pub refused_commands: Vec<String>,
+}
+
+impl Degradation {
+    pub fn from_state(name: &str, state: &State, log_target: &str) -> Degradation {
+        Degradation {
+            degraded: state
+                .get_string_or_default_to_unknown(&format!("{name}_mode_estimated"), log_target)
+                == "degraded",
+            degrading_causes: get_string_array(
+                state,
+                &format!("{name}_emulated_degrading_causes"),
+                log_target,
+            ),
+            time_factor: state.get_float_or_default_to_zero(
+                &format!("{name}_emulated_degraded_time_factor"),
+                log_target,
+            ),
+            refused_commands: get_string_array(
+                state,
+                &format!("{name}_emulated_degraded_refused_commands"),
+                log_target,
+            ),
+        }
+    }
+
+    // What the execution time of a command is multiplied with in the current mode.
+    pub fn execution_time_factor(&self) -> f64 {
+        match self.degraded && self.time_factor > 0.0 {
+            true => self.time_factor,
+            false => 1.0,
+        }
+    }
+
+    // Enter or leave the degraded mode depending on how a command went.
+    pub fn update(&mut self, command: &str, success: bool, failure_cause: &str, log_target: &str) {
+        if success && (command == "reset" || command == "calibrate") {
+            if self.degraded {
+                log::info!(target: log_target, "Back to normal mode after {}.", command);
+            }
+            self.degraded = false;
+        } else if !success
+            && !self.degraded
+            && self
+                .degrading_causes
+                .iter()
+                .any(|cause| cause == failure_cause)
+        {
+            log::warn!(target: log_target, "Degraded mode after failing with {}.", failure_cause);
+            self.degraded = true;
+        }
+    }
+
+    pub fn mode_to_spvalue(&self) -> SPValue {
+        match self.degraded {
+            true => "degraded".to_spvalue(),
+            false => "normal".to_spvalue(),
+        }
+    }
+}
+
+// Every resource can be reset, which does nothing but restore the normal mode. In degraded
+// mode, the refused commands fail before they are emulated.
+pub fn arbitrate_degraded_mode(
+    degradation: &Degradation,
+    command: &str,
+    log_target: &str,
+) -> Option<ReservationResponse> {
+    if command == "reset" {
+        let info = "Succeeded to reset.".to_string();
+        log::info!(target: log_target, "{}", info);
+        return Some(ReservationResponse {
+            success: true,
+            failure_cause: "".to_string(),
+            info,
+        });
+    }
+    if degradation.degraded && degradation.refused_commands.iter().any(|c| c == command) {
+        let info = format!("Failed to {}, resource is in degraded mode.", command);
+        log::error!(target: log_target, "{}", info);
+        return Some(ReservationResponse {
+            success: false,
+            failure_cause: "degraded".to_string(),
+            info,
+        });
+    }
+    None
+}
+
+#[test]
+fn test_degradation() {
+    let log_target = "test_degradation";
+    let mut degradation = Degradation {
+        degraded: false,
+        degrading_causes: vec!["overload".to_string()],
+        time_factor: 2.0,
+        refused_commands: vec!["lock".to_string()],
+    };
+    assert_eq!(degradation.execution_time_factor(), 1.0);
+    assert!(arbitrate_degraded_mode(&degradation, "lock", log_target).is_none());
+
+    // Only the degrading causes lead to the degraded mode
+    degradation.update("move", false, "collision", log_target);
+    assert!(!degradation.degraded);
+    degradation.update("move", false, "overload", log_target);
+    assert!(degradation.degraded);
+    assert_eq!(degradation.mode_to_spvalue(), "degraded".to_spvalue());
+    assert_eq!(degradation.execution_time_factor(), 2.0);
+    let response = arbitrate_degraded_mode(&degradation, "lock", log_target).unwrap();
+    assert!(!response.success);
+    assert_eq!(response.failure_cause, "degraded");
+    assert!(arbitrate_degraded_mode(&degradation, "move", log_target).is_none());
+
+    // A failed calibrate changes nothing, a successful one restores the normal mode
+    degradation.update("calibrate", false, "generic_failure", log_target);
+    assert!(degradation.degraded);
+    degradation.update("calibrate", true, "", log_target);
+    assert!(!degradation.degraded);
+    assert_eq!(degradation.mode_to_spvalue(), "normal".to_spvalue());
+    assert_eq!(degradation.execution_time_factor(), 1.0);
+
+    // Reset is always accepted, and restores the normal mode as well
+    degradation.update("move", false, "overload", log_target);
+    assert!(
+        arbitrate_degraded_mode(&degradation, "reset", log_target)
+            .unwrap()
+            .success
+    );
+    degradation.update("reset", true, "", log_target);
+    assert!(!degradation.degraded);
+}
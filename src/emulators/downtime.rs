@@ -0,0 +1,186 @@
+use micro_sp::State;
+
+use crate::{EMULATE_DOWNTIME_MAINTENANCE, EMULATE_DOWNTIME_OFFLINE, ReservationResponse};
+
+// Planned downtime of a resource, like a maintenance calendar. The windows are given in
+// "{name}_emulated_downtime_windows", in milliseconds since the emulator started, as:
+// "30000-60000"  # down from t=30s to t=60s
+// "600000/60000" # down every 10 min for 1 min, i.e. from t=10min to t=11min, t=20min to t=21min, ...
+// What the resource does during a window depends on "{name}_emulate_downtime", see lib.rs.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DowntimeWindow {
+    Once { from: u64, to: u64 },
+    Every { period: u64, duration: u64 },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Downtime {
+    Up,
+    Maintenance,
+    Offline,
+}
+
+pub fn parse_downtime_window(window: &str) -> Option<DowntimeWindow> {
+    if let Some((from, to)) = window.split_once('-') {
+        let from = from.trim().parse::<u64>().ok()?;
+        let to = to.trim().parse::<u64>().ok()?;
+        return (from < to).then_some(DowntimeWindow::Once { from, to });
+    }
+    if let Some((period, duration)) = window.split_once('/') {
+        let period = period.trim().parse::<u64>().ok()?;
+        let duration = duration.trim().parse::<u64>().ok()?;
+        return (period > 0).then_some(DowntimeWindow::Every { period, duration });
+    }
+    None
+}
+
+pub fn is_in_downtime_window(windows: &[String], elapsed_ms: u64, log_target: &str) -> bool {
+    windows
+        .iter()
+        .any(|window| match parse_downtime_window(window) {
+            Some(DowntimeWindow::Once { from, to }) => from <= elapsed_ms && elapsed_ms < to,
+            Some(DowntimeWindow::Every { period, duration }) => {
+                elapsed_ms >= period && elapsed_ms % period < duration
+            }
+            None => {
+                log::warn!(target: log_target, "Invalid downtime window: '{}'", window);
+                false
+            }
+        })
+}
+
+pub fn emulate_downtime(name: &str, state: &State, elapsed_ms: u64, log_target: &str) -> Downtime {
+    let windows = crate::get_string_array(
+        state,
+        &format!("{name}_emulated_downtime_windows"),
+        log_target,
+    );
+    if !is_in_downtime_window(&windows, elapsed_ms, log_target) {
+        return Downtime::Up;
+    }
+    match state.get_int_or_default_to_zero(&format!("{name}_emulate_downtime"), log_target) {
+        x if x == EMULATE_DOWNTIME_MAINTENANCE => Downtime::Maintenance,
+        x if x == EMULATE_DOWNTIME_OFFLINE => Downtime::Offline,
+        _ => Downtime::Up,
+    }
+}
+
+// Whether "{name}_resource_online" has to be written, and to what: when the emulator comes up
+// and when an offline window starts or ends. In between it is left alone, so that something
+// else, e.g. an injection script, can take the resource offline for a while.
+pub fn resource_online_change(previous: Option<&Downtime>, downtime: &Downtime) -> Option<bool> {
+    let online = *downtime != Downtime::Offline;
+    match previous {
+        Some(previous) if (*previous != Downtime::Offline) == online => None,
+        _ => Some(online),
+    }
+}
+
+// During maintenance every command is rejected, before the reservation is even looked at.
+pub fn reject_in_maintenance(
+    downtime: &Downtime,
+    command: &str,
+    log_target: &str,
+) -> Option<ReservationResponse> {
+    if *downtime != Downtime::Maintenance {
+        return None;
+    }
+    let info = format!("Failed to {}, resource is in maintenance.", command);
+    log::error!(target: log_target, "{}", info);
+    Some(ReservationResponse {
+        success: false,
+        failure_cause: "maintenance".to_string(),
+        info,
+    })
+}
+
+#[test]
+fn test_resource_online_change() {
+    assert_eq!(resource_online_change(None, &Downtime::Up), Some(true));
+    assert_eq!(
+        resource_online_change(None, &Downtime::Offline),
+        Some(false)
+    );
+    assert_eq!(
+        resource_online_change(Some(&Downtime::Up), &Downtime::Up),
+        None
+    );
+    assert_eq!(
+        resource_online_change(Some(&Downtime::Up), &Downtime::Maintenance),
+        None
+    );
+    assert_eq!(
+        resource_online_change(Some(&Downtime::Up), &Downtime::Offline),
+        Some(false)
+    );
+    assert_eq!(
+        resource_online_change(Some(&Downtime::Offline), &Downtime::Offline),
+        None
+    );
+    assert_eq!(
+        resource_online_change(Some(&Downtime::Offline), &Downtime::Up),
+        Some(true)
+    );
+}
+
+#[test]
+fn test_downtime_windows() {
+    assert_eq!(
+        parse_downtime_window("30000-60000"),
+        Some(DowntimeWindow::Once {
+            from: 30000,
+            to: 60000
+        })
+    );
+    assert_eq!(
+        parse_downtime_window(" 600000 / 60000 "),
+        Some(DowntimeWindow::Every {
+            period: 600000,
+            duration: 60000
+        })
+    );
+    assert_eq!(parse_downtime_window("60000-30000"), None);
+    assert_eq!(parse_downtime_window("0/1000"), None);
+    assert_eq!(parse_downtime_window("tomorrow"), None);
+
+    let log_target = "test_downtime_windows";
+    let windows = vec!["1000-2000".to_string(), "10000/500".to_string()];
+    assert!(!is_in_downtime_window(&windows, 999, log_target));
+    assert!(is_in_downtime_window(&windows, 1000, log_target));
+    assert!(!is_in_downtime_window(&windows, 2000, log_target));
+    // Periodic windows start after the first period, not at the start
+    assert!(!is_in_downtime_window(&windows, 200, log_target));
+    assert!(is_in_downtime_window(&windows, 20200, log_target));
+    assert!(!is_in_downtime_window(&windows, 20500, log_target));
+}
+
+#[test]
+fn test_emulate_downtime() {
+    use micro_sp::ToSPValue;
+
+    let log_target = "test_emulate_downtime";
+    let state = crate::model::state::state().update(
+        "gantry_emulated_downtime_windows",
+        vec!["0-3000"].to_spvalue(),
+    );
+    let downtime = |mode: i64, elapsed_ms: u64| {
+        let state = state.update("gantry_emulate_downtime", mode.to_spvalue());
+        emulate_downtime("gantry", &state, elapsed_ms, log_target)
+    };
+
+    // The windows only matter when downtime is emulated
+    assert_eq!(downtime(crate::DONT_EMULATE_DOWNTIME, 1000), Downtime::Up);
+    assert_eq!(
+        downtime(EMULATE_DOWNTIME_MAINTENANCE, 1000),
+        Downtime::Maintenance
+    );
+    assert_eq!(downtime(EMULATE_DOWNTIME_MAINTENANCE, 3000), Downtime::Up);
+    assert_eq!(downtime(EMULATE_DOWNTIME_OFFLINE, 1000), Downtime::Offline);
+
+    // In maintenance every command is rejected, otherwise nothing is decided here
+    let response = reject_in_maintenance(&Downtime::Maintenance, "move", log_target).unwrap();
+    assert!(!response.success);
+    assert_eq!(response.failure_cause, "maintenance");
+    assert!(reject_in_maintenance(&Downtime::Up, "move", log_target).is_none());
+    assert!(reject_in_maintenance(&Downtime::Offline, "move", log_target).is_none());
+}
@@ -1,22 +1,44 @@
-use micro_sp::{ConnectionManager, ServiceRequestState, StateManager, ToSPValue};
+use micro_sp::{SPValue, ServiceRequestState, State, ToSPValue};
 use rand::Rng;
 use rand::prelude::SliceRandom;
+use serde_json::json;
 use std::{sync::Arc, time::Duration};
-use tokio::time::interval;
 
-use crate::EMULATOR_TICK_INTERVAL;
+use crate::{
+    Degradation, Downtime, EMULATOR_TICK_INTERVAL, EmulatedInstant, MAX_SPEED, MotionEmulation,
+    NOMINAL_SPEED, Reservation, StateBackend, arbitrate_degraded_mode, arbitrate_request,
+    emulate_downtime, emulate_motion_failure, emulate_progress, emulated_interval,
+    get_string_array, is_known_position, motion_time_factor, reject_in_maintenance,
+    resource_capabilities, resource_online_change,
+};
+
+// The positions a gantry can move to, unless "emulated_positions" says otherwise.
+pub static GANTRY_POSITIONS: [&str; 7] = [
+    "home",
+    "a",
+    "b",
+    "c",
+    "d",
+    "pipe_blue_box",
+    "plate_pipe_box",
+];
 
 #[derive(Debug, Clone)]
 pub struct GantryRequest {
     pub command: String,
     pub speed: f64,
     pub position: String,
+    pub positions: Vec<String>,
+    pub payload: f64,
+    pub emulated_max_payload: f64,
+    pub emulated_motion_failure_rate: i64,
     pub emulate_execution_time: i64,
     pub emulated_execution_time: i64,
     pub emulate_failure_rate: i64,
     pub emulated_failure_rate: i64,
     pub emulate_failure_cause: i64,
     pub emulated_failure_cause: Vec<String>,
+    pub execution_time_factor: f64,
 }
 
 #[derive(Debug, Clone)]
@@ -26,67 +48,102 @@ pub struct GantryResponse {
     pub info: String,
 }
 
-pub async fn gantry_emulator(
-    connection_manager: &Arc<ConnectionManager>,
+pub async fn gantry_emulator<B: StateBackend + ?Sized>(
+    name: &str,
+    connection_manager: &Arc<B>,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let mut interval = interval(Duration::from_millis(EMULATOR_TICK_INTERVAL));
-    let log_target = "gantry_emulator";
+    let mut interval = emulated_interval(Duration::from_millis(EMULATOR_TICK_INTERVAL));
+    let log_target = format!("{name}_emulator");
     log::info!(target: &log_target, "Online.");
+    let started = EmulatedInstant::now();
+    // What the previous tick made of the downtime windows, see resource_online_change.
+    let mut previous_downtime: Option<Downtime> = None;
 
     let keys: Vec<String> = vec![
-        "gantry_request_trigger",
-        "gantry_request_state",
-        "gantry_total_fail_counter",
-        "gantry_subsequent_fail_counter",
-        "gantry_command_command",
-        "gantry_speed_command",
-        "gantry_position_command",
-        "gantry_position_estimated",
-        "gantry_calibrated_estimated",
-        "gantry_locked_estimated",
-        "gantry_emulate_execution_time",
-        "gantry_emulated_execution_time",
-        "gantry_emulate_failure_rate",
-        "gantry_emulated_failure_rate",
-        "gantry_emulate_failure_cause",
-        "gantry_emulated_failure_cause",
+        "request_trigger",
+        "request_state",
+        "total_fail_counter",
+        "subsequent_fail_counter",
+        "owner_command",
+        "owner_estimated",
+        "owner_queue_estimated",
+        "capabilities",
+        "command_command",
+        "speed_command",
+        "payload_estimated",
+        "emulated_max_payload",
+        "emulated_motion_failure_rate",
+        "position_command",
+        "position_estimated",
+        "emulated_positions",
+        "calibrated_estimated",
+        "locked_estimated",
+        "emulate_execution_time",
+        "emulated_execution_time",
+        "emulate_failure_rate",
+        "emulated_failure_rate",
+        "emulate_failure_cause",
+        "emulated_failure_cause",
+        "resource_online",
+        "downtime_estimated",
+        "emulate_downtime",
+        "emulated_downtime_windows",
+        "mode_estimated",
+        "emulated_degrading_causes",
+        "emulated_degraded_time_factor",
+        "emulated_degraded_refused_commands",
     ]
     .iter()
-    .map(|k| k.to_string())
+    .map(|k| format!("{name}_{k}"))
     .collect();
 
     loop {
         interval.tick().await;
-        if let Err(_) = connection_manager.check_redis_health(&log_target).await {
+        if !connection_manager.check_health(&log_target).await {
             continue;
         }
-        let mut con = connection_manager.get_connection().await;
-        let state = match StateManager::get_state_for_keys(&mut con, &keys, &log_target).await {
+        let state = match connection_manager
+            .get_state_for_keys(&keys, &log_target)
+            .await
+        {
             Some(s) => s,
             None => continue,
         };
 
         let mut request_trigger =
-            state.get_bool_or_default_to_false("gantry_request_trigger", &log_target);
+            state.get_bool_or_default_to_false(&format!("{name}_request_trigger"), &log_target);
         let mut request_state =
-            state.get_string_or_default_to_unknown("gantry_request_state", &log_target);
+            state.get_string_or_default_to_unknown(&format!("{name}_request_state"), &log_target);
 
         let mut total_fail_counter =
-            state.get_int_or_default_to_zero("gantry_total_fail_counter", &log_target);
-        let mut subsequent_fail_counter =
-            state.get_int_or_default_to_zero("gantry_subsequent_fail_counter", &log_target);
-        let mut gantry_position_estimated =
-            state.get_string_or_default_to_unknown("gantry_position_estimated", &log_target);
-        let mut gantry_calibrated_estimated =
-            state.get_bool_or_default_to_false("gantry_calibrated_estimated", &log_target);
+            state.get_int_or_default_to_zero(&format!("{name}_total_fail_counter"), &log_target);
+        let mut subsequent_fail_counter = state
+            .get_int_or_default_to_zero(&format!("{name}_subsequent_fail_counter"), &log_target);
+        let mut gantry_position_estimated = state
+            .get_string_or_default_to_unknown(&format!("{name}_position_estimated"), &log_target);
+        let mut gantry_calibrated_estimated = state
+            .get_bool_or_default_to_false(&format!("{name}_calibrated_estimated"), &log_target);
         let mut gantry_locked_estimated =
-            state.get_bool_or_default_to_false("gantry_locked_estimated", &log_target);
+            state.get_bool_or_default_to_false(&format!("{name}_locked_estimated"), &log_target);
+
+        let downtime = emulate_downtime(
+            name,
+            &state,
+            started.elapsed().as_millis() as u64,
+            &log_target,
+        );
+
+        let mut reservation = Reservation::from_state(name, &state, &log_target);
+        let mut degradation = Degradation::from_state(name, &state, &log_target);
 
-        if request_trigger {
+        // An offline resource leaves the request pending until it is back
+        if request_trigger && downtime != Downtime::Offline {
             request_trigger = false;
             if request_state == ServiceRequestState::Initial.to_string() {
-                let emulated_failure_cause_sp_value = state
-                    .get_array_or_default_to_empty("gantry_emulated_failure_cause", &log_target);
+                let emulated_failure_cause_sp_value = state.get_array_or_default_to_empty(
+                    &format!("{name}_emulated_failure_cause"),
+                    &log_target,
+                );
 
                 let emulated_failure_cause: Vec<String> = emulated_failure_cause_sp_value
                     .iter()
@@ -95,25 +152,86 @@ pub async fn gantry_emulator(
                     .collect();
 
                 let request = GantryRequest {
-                    command: state
-                        .get_string_or_default_to_unknown("gantry_command_command", &log_target),
-                    speed: state.get_float_or_default_to_zero("gantry_speed_command", &log_target),
-                    position: state
-                        .get_string_or_default_to_unknown("gantry_position_command", &log_target),
-                    emulate_execution_time: state
-                        .get_int_or_default_to_zero("gantry_emulate_execution_time", &log_target),
-                    emulated_execution_time: state
-                        .get_int_or_default_to_zero("gantry_emulated_execution_time", &log_target),
-                    emulate_failure_rate: state
-                        .get_int_or_default_to_zero("gantry_emulate_failure_rate", &log_target),
-                    emulated_failure_rate: state
-                        .get_int_or_default_to_zero("gantry_emulated_failure_rate", &log_target),
-                    emulate_failure_cause: state
-                        .get_int_or_default_to_zero("gantry_emulate_failure_cause", &log_target),
+                    command: state.get_string_or_default_to_unknown(
+                        &format!("{name}_command_command"),
+                        &log_target,
+                    ),
+                    speed: state.get_float_or_default_to_zero(
+                        &format!("{name}_speed_command"),
+                        &log_target,
+                    ),
+                    position: state.get_string_or_default_to_unknown(
+                        &format!("{name}_position_command"),
+                        &log_target,
+                    ),
+                    positions: get_string_array(
+                        &state,
+                        &format!("{name}_emulated_positions"),
+                        &log_target,
+                    ),
+                    payload: state.get_float_or_default_to_zero(
+                        &format!("{name}_payload_estimated"),
+                        &log_target,
+                    ),
+                    emulated_max_payload: state.get_float_or_default_to_zero(
+                        &format!("{name}_emulated_max_payload"),
+                        &log_target,
+                    ),
+                    emulated_motion_failure_rate: state.get_int_or_default_to_zero(
+                        &format!("{name}_emulated_motion_failure_rate"),
+                        &log_target,
+                    ),
+                    emulate_execution_time: state.get_int_or_default_to_zero(
+                        &format!("{name}_emulate_execution_time"),
+                        &log_target,
+                    ),
+                    emulated_execution_time: state.get_int_or_default_to_zero(
+                        &format!("{name}_emulated_execution_time"),
+                        &log_target,
+                    ),
+                    emulate_failure_rate: state.get_int_or_default_to_zero(
+                        &format!("{name}_emulate_failure_rate"),
+                        &log_target,
+                    ),
+                    emulated_failure_rate: state.get_int_or_default_to_zero(
+                        &format!("{name}_emulated_failure_rate"),
+                        &log_target,
+                    ),
+                    emulate_failure_cause: state.get_int_or_default_to_zero(
+                        &format!("{name}_emulate_failure_cause"),
+                        &log_target,
+                    ),
                     emulated_failure_cause,
+                    execution_time_factor: degradation.execution_time_factor(),
                 };
 
-                let response = emulate_gantry_operation(&request).await;
+                let caller = state.get_string_or_default_to_unknown(
+                    &format!("{name}_owner_command"),
+                    &log_target,
+                );
+                let response = match reject_in_maintenance(&downtime, &request.command, &log_target)
+                    .or_else(|| {
+                        arbitrate_request(&request.command, &caller, &mut reservation, &log_target)
+                    })
+                    .or_else(|| {
+                        arbitrate_degraded_mode(&degradation, &request.command, &log_target)
+                    }) {
+                    Some(arbitration) => GantryResponse {
+                        success: arbitration.success,
+                        failure_cause: arbitration.failure_cause,
+                        info: arbitration.info,
+                    },
+                    None => {
+                        emulate_gantry_operation(name, connection_manager, &request, &log_target)
+                            .await
+                    }
+                };
+                degradation.update(
+                    &request.command,
+                    response.success,
+                    &response.failure_cause,
+                    &log_target,
+                );
 
                 request_state = if response.success {
                     subsequent_fail_counter = 0;
@@ -133,49 +251,115 @@ pub async fn gantry_emulator(
             }
         }
         let new_state = state
-            .update("gantry_request_trigger", request_trigger.to_spvalue())
-            .update("gantry_request_state", request_state.to_spvalue())
-            .update("gantry_total_fail_counter", total_fail_counter.to_spvalue())
             .update(
-                "gantry_subsequent_fail_counter",
+                &format!("{name}_request_trigger"),
+                request_trigger.to_spvalue(),
+            )
+            .update(&format!("{name}_request_state"), request_state.to_spvalue())
+            .update(
+                &format!("{name}_total_fail_counter"),
+                total_fail_counter.to_spvalue(),
+            )
+            .update(
+                &format!("{name}_subsequent_fail_counter"),
                 subsequent_fail_counter.to_spvalue(),
             )
             .update(
-                "gantry_position_estimated",
+                &format!("{name}_owner_estimated"),
+                reservation.owner_to_spvalue(),
+            )
+            .update(
+                &format!("{name}_owner_queue_estimated"),
+                reservation.queue_to_spvalue(),
+            )
+            .update(
+                &format!("{name}_downtime_estimated"),
+                (downtime != Downtime::Up).to_spvalue(),
+            )
+            .update(
+                &format!("{name}_mode_estimated"),
+                degradation.mode_to_spvalue(),
+            )
+            .update(
+                &format!("{name}_capabilities"),
+                gantry_capabilities(name, &state, &log_target),
+            )
+            .update(
+                &format!("{name}_position_estimated"),
                 gantry_position_estimated.to_spvalue(),
             )
             .update(
-                "gantry_calibrated_estimated",
+                &format!("{name}_calibrated_estimated"),
                 gantry_calibrated_estimated.to_spvalue(),
             )
             .update(
-                "gantry_locked_estimated",
+                &format!("{name}_locked_estimated"),
                 gantry_locked_estimated.to_spvalue(),
             );
 
+        let new_state = match resource_online_change(previous_downtime.as_ref(), &downtime) {
+            Some(online) => {
+                new_state.update(&format!("{name}_resource_online"), online.to_spvalue())
+            }
+            None => new_state,
+        };
+        previous_downtime = Some(downtime);
+
         let modified_state = state.get_diff_partial_state(&new_state);
-        StateManager::set_state(&mut con, &modified_state).await;
+        connection_manager.set_state(&modified_state).await;
     }
 }
 
-pub async fn emulate_gantry_operation(request: &GantryRequest) -> GantryResponse {
+// Published in "{name}_capabilities", see capabilities.rs.
+pub fn gantry_capabilities(name: &str, state: &State, log_target: &str) -> SPValue {
+    let positions = get_string_array(state, &format!("{name}_emulated_positions"), log_target);
+    resource_capabilities(
+        vec![
+            (
+                "move",
+                json!({
+                    "position_command": positions,
+                    "speed_command": {"type": "float", "min": 0.0, "max": MAX_SPEED, "nominal": NOMINAL_SPEED}
+                }),
+            ),
+            ("calibrate", json!({})),
+            ("lock", json!({})),
+            ("unlock", json!({})),
+        ],
+        &[
+            "unknown_position",
+            "invalid_speed",
+            "high_speed",
+            "heavy_payload",
+        ],
+        &get_string_array(state, &format!("{name}_emulated_failure_cause"), log_target),
+        vec![],
+        vec![("positions", json!(positions))],
+    )
+}
 
+pub async fn emulate_gantry_operation<B: StateBackend + ?Sized>(
+    name: &str,
+    connection_manager: &Arc<B>,
+    request: &GantryRequest,
+    log_target: &str,
+) -> GantryResponse {
     let mut fail = match request.emulate_failure_rate {
         0 => false, // Never fail
         1 => true,  // Always fail
-        2 => rand::thread_rng().gen_range(0..=100) <= request.emulated_failure_rate as u64,
+        2 => crate::emulator_rng().gen_range(0..=100) <= request.emulated_failure_rate as u64,
         _ => false,
     };
 
     match request.command.as_str() {
         "move" => {
-            log::info!(target: "gantry_emulator", "Got request to move to {}.", request.position)
+            log::info!(target: log_target, "Got request to move to {}.", request.position)
         }
-        "calibrate" => log::info!(target: "gantry_emulator", "Got request to calibrate."),
-        "lock" => log::info!(target: "gantry_emulator", "Got request to lock."),
-        "unlock" => log::info!(target: "gantry_emulator", "Got request to unlock."),
+        "calibrate" => log::info!(target: log_target, "Got request to calibrate."),
+        "lock" => log::info!(target: log_target, "Got request to lock."),
+        "unlock" => log::info!(target: log_target, "Got request to unlock."),
         _ => {
-            log::warn!(target: "gantry_emulator", "Unknown command: '{}'", request.command);
+            log::warn!(target: log_target, "Unknown command: '{}'", request.command);
             fail = true;
         }
     };
@@ -183,14 +367,39 @@ pub async fn emulate_gantry_operation(request: &GantryRequest) -> GantryResponse
     let delay_ms: u64 = match request.emulate_execution_time {
         0 => 0,
         1 => request.emulated_execution_time as u64,
-        2 => rand::thread_rng().gen_range(0..=request.emulated_execution_time) as u64,
+        2 => crate::emulator_rng().gen_range(0..=request.emulated_execution_time) as u64,
         _ => 0,
     };
-    if delay_ms > 0 {
-        tokio::time::sleep(Duration::from_millis(delay_ms)).await;
+
+    // Only motions depend on the position catalog, speed and payload
+    let motion = MotionEmulation {
+        speed: request.speed,
+        payload: request.payload,
+        emulated_max_payload: request.emulated_max_payload,
+        emulated_motion_failure_rate: request.emulated_motion_failure_rate,
+    };
+    let motion_failure = match request.command.as_str() {
+        "move" if !is_known_position(&request.positions, &request.position) => {
+            Some("unknown_position".to_string())
+        }
+        "move" => emulate_motion_failure(&motion),
+        _ => None,
+    };
+    let delay_ms = match (request.command.as_str(), motion_failure.as_deref()) {
+        (_, Some("invalid_speed")) | (_, Some("unknown_position")) => 0,
+        ("move", _) => (delay_ms as f64 * motion_time_factor(&motion)) as u64,
+        _ => delay_ms,
+    };
+    if motion_failure.is_some() {
+        fail = true;
     }
+    // Everything takes longer in degraded mode
+    let delay_ms = (delay_ms as f64 * request.execution_time_factor) as u64;
+    emulate_progress(name, connection_manager, delay_ms, log_target).await;
 
-    let cause = if fail {
+    let cause = if let Some(cause) = motion_failure {
+        cause
+    } else if fail {
         match request.emulate_failure_cause {
             0 => "generic_failure".to_string(),
             1 => request
@@ -200,7 +409,7 @@ pub async fn emulate_gantry_operation(request: &GantryRequest) -> GantryResponse
                 .unwrap_or_else(|| "config_error".to_string()),
             2 => request
                 .emulated_failure_cause
-                .choose(&mut rand::thread_rng())
+                .choose(&mut crate::emulator_rng())
                 .cloned()
                 .unwrap_or_else(|| "random_error".to_string()),
             _ => "generic_failure".to_string(),
@@ -233,14 +442,14 @@ pub async fn emulate_gantry_operation(request: &GantryRequest) -> GantryResponse
     };
 
     if !fail {
-        log::info!(target: "gantry_emulator", "{}", success_info);
+        log::info!(target: log_target, "{}", success_info);
         GantryResponse {
             success: true,
             failure_cause: "".to_string(),
             info: success_info,
         }
     } else {
-        log::error!(target: "gantry_emulator", "{}", failure_info);
+        log::error!(target: log_target, "{}", failure_info);
         GantryResponse {
             success: false,
             failure_cause: cause,
@@ -248,3 +457,68 @@ pub async fn emulate_gantry_operation(request: &GantryRequest) -> GantryResponse
         }
     }
 }
+
+#[tokio::test(start_paused = true)]
+async fn test_gantry_emulator_speed_payload() {
+    let log_target = "test_gantry_emulator_speed_payload";
+    let state = crate::model::state::state()
+        .update(
+            "gantry_emulate_execution_time",
+            crate::EMULATE_EXACT_EXECUTION_TIME.to_spvalue(),
+        )
+        .update("gantry_emulated_execution_time", 2000.to_spvalue());
+    let backend: Arc<dyn StateBackend> = Arc::new(crate::InMemoryBackend::new(&state));
+    let emulator = crate::spawn_emulator(
+        &crate::ScenarioEmulator::Gantry("gantry".to_string()),
+        &backend,
+    );
+
+    let timed_move = |position: &'static str, speed: f64| {
+        let backend = backend.clone();
+        async move {
+            let started = EmulatedInstant::now();
+            let state = crate::complete_request(
+                &backend,
+                "gantry",
+                &[
+                    ("command_command", "move".to_spvalue()),
+                    ("position_command", position.to_spvalue()),
+                    ("speed_command", speed.to_spvalue()),
+                ],
+            )
+            .await;
+            assert_eq!(
+                state.get_string_or_default_to_unknown("gantry_request_state", log_target),
+                "succeeded"
+            );
+            started.elapsed()
+        }
+    };
+
+    // The emulated execution time at the nominal speed, half of it at twice the speed,
+    // with a couple of ticks on top for the request to be picked up and completed
+    let slack = Duration::from_millis(3 * EMULATOR_TICK_INTERVAL);
+    let elapsed = timed_move("a", NOMINAL_SPEED).await;
+    assert!(elapsed >= Duration::from_millis(2000), "{elapsed:?}");
+    assert!(
+        elapsed <= Duration::from_millis(2000) + slack,
+        "{elapsed:?}"
+    );
+    let elapsed = timed_move("b", 2.0 * NOMINAL_SPEED).await;
+    assert!(elapsed >= Duration::from_millis(1000), "{elapsed:?}");
+    assert!(
+        elapsed <= Duration::from_millis(1000) + slack,
+        "{elapsed:?}"
+    );
+
+    // A payload at the max payload doubles the time
+    let state = backend.get_full_state().await.unwrap();
+    let new_state = state.update("gantry_payload_estimated", 10.0.to_spvalue());
+    backend
+        .set_state(&state.get_diff_partial_state(&new_state))
+        .await;
+    let elapsed = timed_move("a", NOMINAL_SPEED).await;
+    assert!(elapsed >= Duration::from_millis(4000), "{elapsed:?}");
+
+    emulator.abort();
+}
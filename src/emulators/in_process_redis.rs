@@ -0,0 +1,780 @@
+use micro_sp::ConnectionManager;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, OnceLock};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::AbortHandle;
+
+// A Redis-compatible server inside the test process, so that everything that talks Redis,
+// including the runner of micro_sp, runs without Docker. It speaks RESP on a local port
+// and keeps the data in a map. It knows the commands that the runner, the StateManager
+// and redis-rs use (strings, hashes, lists, keys/scan, transactions), keys don't expire.
+//
+// Every listener has its own namespace: the keys of its clients are stored as
+// "{namespace}:{key}" in the shared store, and they only see the keys of their namespace.
+// So the runner, the injections and the emulators of one test all use the plain keys, like
+// "robot_request_state" or "{sp_id}_scheduled_goals", and still don't see other tests.
+#[derive(Clone, Default)]
+pub struct InProcessRedis {
+    store: Arc<Mutex<Store>>,
+}
+
+#[derive(Default)]
+struct Store {
+    entries: HashMap<Vec<u8>, Entry>,
+}
+
+enum Entry {
+    String(Vec<u8>),
+    Hash(HashMap<Vec<u8>, Vec<u8>>),
+    List(VecDeque<Vec<u8>>),
+}
+
+enum Reply {
+    Simple(&'static str),
+    Error(String),
+    Int(i64),
+    Bulk(Option<Vec<u8>>),
+    Array(Vec<Reply>),
+}
+
+// Stops accepting and drops the connections of the namespace when dropped.
+pub struct InProcessRedisListener {
+    pub port: u16,
+    pub namespace: String,
+    handle: AbortHandle,
+    connections: Arc<Mutex<Vec<AbortHandle>>>,
+}
+
+impl InProcessRedisListener {
+    // A ConnectionManager of micro_sp for the namespace, for the runner and the injections.
+    pub async fn connection_manager(&self) -> ConnectionManager {
+        connection_manager_for("127.0.0.1", self.port, 0).await
+    }
+}
+
+// A ConnectionManager of micro_sp for a database of the Redis at the given address. The
+// address is given to it directly instead of through REDIS_HOST and REDIS_PORT, which belong
+// to the whole process and are read by other tests running next to this one.
+pub async fn connection_manager_for(host: &str, port: u16, database: u8) -> ConnectionManager {
+    ConnectionManager::from_url(&format!("redis://{host}:{port}/{database}")).await
+}
+
+impl Drop for InProcessRedisListener {
+    fn drop(&mut self) {
+        self.handle.abort();
+        for connection in self.connections.lock().unwrap().drain(..) {
+            connection.abort();
+        }
+    }
+}
+
+const WRONGTYPE: &str = "WRONGTYPE Operation against a key holding the wrong kind of value";
+
+impl InProcessRedis {
+    pub fn new() -> InProcessRedis {
+        InProcessRedis::default()
+    }
+
+    // One store for the whole process, the tests keep apart through their namespaces.
+    pub fn shared() -> InProcessRedis {
+        static SHARED: OnceLock<InProcessRedis> = OnceLock::new();
+        SHARED.get_or_init(InProcessRedis::new).clone()
+    }
+
+    // Accepts clients on a free local port, in the runtime of the caller. The namespace
+    // starts out empty, also when an earlier run left keys behind.
+    pub async fn listen(&self, namespace: &str) -> std::io::Result<InProcessRedisListener> {
+        let prefix = format!("{namespace}:").into_bytes();
+        self.store
+            .lock()
+            .unwrap()
+            .entries
+            .retain(|key, _| !key.starts_with(&prefix));
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let port = listener.local_addr()?.port();
+        let connections: Arc<Mutex<Vec<AbortHandle>>> = Arc::new(Mutex::new(vec![]));
+        let (server, connections_clone) = (self.clone(), connections.clone());
+        let handle = tokio::task::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let (server, prefix) = (server.clone(), prefix.clone());
+                let connection = tokio::task::spawn(async move {
+                    if let Err(e) = server.serve(stream, prefix).await {
+                        log::debug!(target: "in_process_redis", "Connection closed: {}", e);
+                    }
+                });
+                connections_clone
+                    .lock()
+                    .unwrap()
+                    .push(connection.abort_handle());
+            }
+        })
+        .abort_handle();
+        log::info!(target: "in_process_redis", "Namespace '{}' listening on port {}.", namespace, port);
+        Ok(InProcessRedisListener {
+            port,
+            namespace: namespace.to_string(),
+            handle,
+            connections,
+        })
+    }
+
+    // All keys of the namespace with their string values, e.g. to look at the state of a
+    // test after it failed.
+    pub fn dump(&self, namespace: &str) -> Vec<(String, String)> {
+        let prefix = format!("{namespace}:").into_bytes();
+        let store = self.store.lock().unwrap();
+        let mut dump: Vec<(String, String)> = store
+            .entries
+            .iter()
+            .filter_map(
+                |(key, entry)| match (key.strip_prefix(&prefix[..]), entry) {
+                    (Some(key), Entry::String(value)) => Some((
+                        String::from_utf8_lossy(key).to_string(),
+                        String::from_utf8_lossy(value).to_string(),
+                    )),
+                    _ => None,
+                },
+            )
+            .collect();
+        dump.sort();
+        dump
+    }
+
+    async fn serve(&self, stream: TcpStream, prefix: Vec<u8>) -> std::io::Result<()> {
+        stream.set_nodelay(true)?;
+        let (reader, mut writer) = stream.into_split();
+        let mut reader = BufReader::new(reader);
+        // The commands between MULTI and EXEC.
+        let mut transaction: Option<Vec<Vec<Vec<u8>>>> = None;
+        let mut out = vec![];
+        loop {
+            let Some(command) = read_command(&mut reader).await? else {
+                return Ok(());
+            };
+            if command.is_empty() {
+                continue;
+            }
+            let name = String::from_utf8_lossy(&command[0]).to_uppercase();
+            let reply = match (name.as_str(), transaction.as_mut()) {
+                ("MULTI", None) => {
+                    transaction = Some(vec![]);
+                    Reply::Simple("OK")
+                }
+                ("MULTI", Some(_)) => Reply::Error("ERR MULTI calls can not be nested".into()),
+                ("EXEC", Some(_)) => {
+                    let queued = transaction.take().unwrap_or_default();
+                    let mut store = self.store.lock().unwrap();
+                    Reply::Array(
+                        queued
+                            .iter()
+                            .map(|command| store.execute(&prefix, command))
+                            .collect(),
+                    )
+                }
+                ("DISCARD", Some(_)) => {
+                    transaction = None;
+                    Reply::Simple("OK")
+                }
+                ("EXEC", None) | ("DISCARD", None) => {
+                    Reply::Error(format!("ERR {name} without MULTI"))
+                }
+                ("WATCH", _) | ("UNWATCH", _) => Reply::Simple("OK"),
+                (_, Some(queued)) => {
+                    queued.push(command);
+                    Reply::Simple("QUEUED")
+                }
+                (_, None) => self.store.lock().unwrap().execute(&prefix, &command),
+            };
+            out.clear();
+            write_reply(&mut out, &reply);
+            writer.write_all(&out).await?;
+        }
+    }
+}
+
+async fn read_line<R: AsyncBufReadExt + Unpin>(reader: &mut R) -> std::io::Result<Option<Vec<u8>>> {
+    let mut line = vec![];
+    if reader.read_until(b'\n', &mut line).await? == 0 {
+        return Ok(None);
+    }
+    while line
+        .last()
+        .is_some_and(|byte| *byte == b'\n' || *byte == b'\r')
+    {
+        line.pop();
+    }
+    Ok(Some(line))
+}
+
+fn invalid(message: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn parse_length(bytes: &[u8]) -> std::io::Result<i64> {
+    std::str::from_utf8(bytes)
+        .ok()
+        .and_then(|length| length.parse().ok())
+        .ok_or_else(|| invalid("invalid length"))
+}
+
+// A command is either a RESP array of bulk strings or an inline line of words.
+async fn read_command<R: AsyncBufReadExt + Unpin>(
+    reader: &mut R,
+) -> std::io::Result<Option<Vec<Vec<u8>>>> {
+    let Some(line) = read_line(reader).await? else {
+        return Ok(None);
+    };
+    if line.first() != Some(&b'*') {
+        return Ok(Some(
+            line.split(|byte| byte.is_ascii_whitespace())
+                .filter(|word| !word.is_empty())
+                .map(|word| word.to_vec())
+                .collect(),
+        ));
+    }
+    let count = parse_length(&line[1..])?;
+    let mut command = vec![];
+    for _ in 0..count.max(0) {
+        let header = read_line(reader)
+            .await?
+            .ok_or_else(|| invalid("unexpected end"))?;
+        if header.first() != Some(&b'$') {
+            return Err(invalid("expected a bulk string"));
+        }
+        let length = parse_length(&header[1..])?;
+        let mut argument = vec![0; length.max(0) as usize + 2];
+        reader.read_exact(&mut argument).await?;
+        argument.truncate(length.max(0) as usize);
+        command.push(argument);
+    }
+    Ok(Some(command))
+}
+
+fn write_reply(out: &mut Vec<u8>, reply: &Reply) {
+    match reply {
+        Reply::Simple(message) => out.extend_from_slice(format!("+{message}\r\n").as_bytes()),
+        Reply::Error(message) => out.extend_from_slice(format!("-{message}\r\n").as_bytes()),
+        Reply::Int(value) => out.extend_from_slice(format!(":{value}\r\n").as_bytes()),
+        Reply::Bulk(None) => out.extend_from_slice(b"$-1\r\n"),
+        Reply::Bulk(Some(value)) => {
+            out.extend_from_slice(format!("${}\r\n", value.len()).as_bytes());
+            out.extend_from_slice(value);
+            out.extend_from_slice(b"\r\n");
+        }
+        Reply::Array(replies) => {
+            out.extend_from_slice(format!("*{}\r\n", replies.len()).as_bytes());
+            for reply in replies {
+                write_reply(out, reply);
+            }
+        }
+    }
+}
+
+// Redis glob patterns: *, ?, [abc], [a-z], [^a] and \ to escape.
+fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern.first() {
+        None => text.is_empty(),
+        Some(b'*') => (0..=text.len()).any(|skip| glob_match(&pattern[1..], &text[skip..])),
+        Some(b'?') => !text.is_empty() && glob_match(&pattern[1..], &text[1..]),
+        Some(b'[') => {
+            let Some(close) = pattern.iter().skip(2).position(|byte| *byte == b']') else {
+                return text.first() == Some(&b'[') && glob_match(&pattern[1..], &text[1..]);
+            };
+            let class = &pattern[1..close + 2];
+            let (negated, class) = match class.first() {
+                Some(b'^') => (true, &class[1..]),
+                _ => (false, class),
+            };
+            let Some(character) = text.first() else {
+                return false;
+            };
+            let mut matched = false;
+            let mut i = 0;
+            while i < class.len() {
+                if i + 2 < class.len() && class[i + 1] == b'-' {
+                    matched |= class[i] <= *character && *character <= class[i + 2];
+                    i += 3;
+                } else {
+                    matched |= class[i] == *character;
+                    i += 1;
+                }
+            }
+            matched != negated && glob_match(&pattern[close + 3..], &text[1..])
+        }
+        Some(b'\\') if pattern.len() > 1 => {
+            text.first() == Some(&pattern[1]) && glob_match(&pattern[2..], &text[1..])
+        }
+        Some(byte) => text.first() == Some(byte) && glob_match(&pattern[1..], &text[1..]),
+    }
+}
+
+fn to_int(bytes: &[u8]) -> Option<i64> {
+    std::str::from_utf8(bytes).ok()?.parse().ok()
+}
+
+fn bulk(value: &[u8]) -> Reply {
+    Reply::Bulk(Some(value.to_vec()))
+}
+
+impl Store {
+    fn key(prefix: &[u8], key: &[u8]) -> Vec<u8> {
+        [prefix, key].concat()
+    }
+
+    fn keys(&self, prefix: &[u8], pattern: &[u8]) -> Vec<Reply> {
+        let mut keys: Vec<&[u8]> = self
+            .entries
+            .keys()
+            .filter_map(|key| key.strip_prefix(prefix))
+            .filter(|key| glob_match(pattern, key))
+            .collect();
+        keys.sort();
+        keys.into_iter().map(bulk).collect()
+    }
+
+    fn get_string(&self, key: &[u8]) -> Result<Option<&Vec<u8>>, Reply> {
+        match self.entries.get(key) {
+            None => Ok(None),
+            Some(Entry::String(value)) => Ok(Some(value)),
+            Some(_) => Err(Reply::Error(WRONGTYPE.into())),
+        }
+    }
+
+    fn hash(&mut self, key: Vec<u8>) -> Result<&mut HashMap<Vec<u8>, Vec<u8>>, Reply> {
+        match self
+            .entries
+            .entry(key)
+            .or_insert_with(|| Entry::Hash(HashMap::new()))
+        {
+            Entry::Hash(hash) => Ok(hash),
+            _ => Err(Reply::Error(WRONGTYPE.into())),
+        }
+    }
+
+    fn list(&mut self, key: Vec<u8>) -> Result<&mut VecDeque<Vec<u8>>, Reply> {
+        match self
+            .entries
+            .entry(key)
+            .or_insert_with(|| Entry::List(VecDeque::new()))
+        {
+            Entry::List(list) => Ok(list),
+            _ => Err(Reply::Error(WRONGTYPE.into())),
+        }
+    }
+
+    // Empty hashes and lists don't exist in Redis.
+    fn remove_if_empty(&mut self, key: &[u8]) {
+        let empty = match self.entries.get(key) {
+            Some(Entry::Hash(hash)) => hash.is_empty(),
+            Some(Entry::List(list)) => list.is_empty(),
+            _ => false,
+        };
+        if empty {
+            self.entries.remove(key);
+        }
+    }
+
+    fn execute(&mut self, prefix: &[u8], command: &[Vec<u8>]) -> Reply {
+        let name = String::from_utf8_lossy(&command[0]).to_uppercase();
+        let args = &command[1..];
+        let arity = |count: usize| -> Result<(), Reply> {
+            match args.len() >= count {
+                true => Ok(()),
+                false => Err(Reply::Error(format!(
+                    "ERR wrong number of arguments for '{}' command",
+                    name.to_lowercase()
+                ))),
+            }
+        };
+        let reply = (|| -> Result<Reply, Reply> {
+            Ok(match name.as_str() {
+                "PING" => match args.first() {
+                    Some(message) => bulk(message),
+                    None => Reply::Simple("PONG"),
+                },
+                "ECHO" => {
+                    arity(1)?;
+                    bulk(&args[0])
+                }
+                // Connection setup of redis-rs and clients, nothing to do.
+                "SELECT" | "CLIENT" | "READONLY" | "READWRITE" | "RESET" => Reply::Simple("OK"),
+                "QUIT" => Reply::Simple("OK"),
+                "COMMAND" => Reply::Array(vec![]),
+                "INFO" => bulk(b"# Server\r\nredis_version:7.0.0\r\nredis_mode:standalone\r\n"),
+                "HELLO" => Reply::Error("NOPROTO unsupported protocol version".into()),
+                "DBSIZE" => Reply::Int(self.keys(prefix, b"*").len() as i64),
+                "FLUSHDB" | "FLUSHALL" => {
+                    self.entries.retain(|key, _| !key.starts_with(prefix));
+                    Reply::Simple("OK")
+                }
+                "GET" => {
+                    arity(1)?;
+                    Reply::Bulk(self.get_string(&Store::key(prefix, &args[0]))?.cloned())
+                }
+                "SET" => {
+                    arity(2)?;
+                    let key = Store::key(prefix, &args[0]);
+                    let options: Vec<String> = args[2..]
+                        .iter()
+                        .map(|option| String::from_utf8_lossy(option).to_uppercase())
+                        .collect();
+                    let exists = self.entries.contains_key(&key);
+                    let old = match options.iter().any(|option| option == "GET") {
+                        true => Some(self.get_string(&key)?.cloned()),
+                        false => None,
+                    };
+                    let skip = (options.iter().any(|option| option == "NX") && exists)
+                        || (options.iter().any(|option| option == "XX") && !exists);
+                    if !skip {
+                        self.entries.insert(key, Entry::String(args[1].clone()));
+                    }
+                    match (old, skip) {
+                        (Some(old), _) => Reply::Bulk(old),
+                        (None, true) => Reply::Bulk(None),
+                        (None, false) => Reply::Simple("OK"),
+                    }
+                }
+                "SETNX" => {
+                    arity(2)?;
+                    let key = Store::key(prefix, &args[0]);
+                    match self.entries.contains_key(&key) {
+                        true => Reply::Int(0),
+                        false => {
+                            self.entries.insert(key, Entry::String(args[1].clone()));
+                            Reply::Int(1)
+                        }
+                    }
+                }
+                "GETSET" => {
+                    arity(2)?;
+                    let key = Store::key(prefix, &args[0]);
+                    let old = self.get_string(&key)?.cloned();
+                    self.entries.insert(key, Entry::String(args[1].clone()));
+                    Reply::Bulk(old)
+                }
+                "MGET" => {
+                    arity(1)?;
+                    Reply::Array(
+                        args.iter()
+                            .map(|key| match self.entries.get(&Store::key(prefix, key)) {
+                                Some(Entry::String(value)) => bulk(value),
+                                _ => Reply::Bulk(None),
+                            })
+                            .collect(),
+                    )
+                }
+                "MSET" => {
+                    arity(2)?;
+                    for pair in args.chunks(2) {
+                        if let [key, value] = pair {
+                            self.entries
+                                .insert(Store::key(prefix, key), Entry::String(value.clone()));
+                        }
+                    }
+                    Reply::Simple("OK")
+                }
+                "APPEND" => {
+                    arity(2)?;
+                    let key = Store::key(prefix, &args[0]);
+                    let mut value = self.get_string(&key)?.cloned().unwrap_or_default();
+                    value.extend_from_slice(&args[1]);
+                    let length = value.len();
+                    self.entries.insert(key, Entry::String(value));
+                    Reply::Int(length as i64)
+                }
+                "STRLEN" => {
+                    arity(1)?;
+                    let key = Store::key(prefix, &args[0]);
+                    Reply::Int(self.get_string(&key)?.map_or(0, |value| value.len()) as i64)
+                }
+                "INCR" | "DECR" | "INCRBY" | "DECRBY" => {
+                    arity(match name.as_str() {
+                        "INCRBY" | "DECRBY" => 2,
+                        _ => 1,
+                    })?;
+                    let key = Store::key(prefix, &args[0]);
+                    let by = match name.as_str() {
+                        "INCR" => 1,
+                        "DECR" => -1,
+                        _ => to_int(&args[1]).ok_or_else(|| {
+                            Reply::Error("ERR value is not an integer or out of range".into())
+                        })?,
+                    };
+                    let current = match self.get_string(&key)? {
+                        None => 0,
+                        Some(value) => to_int(value).ok_or_else(|| {
+                            Reply::Error("ERR value is not an integer or out of range".into())
+                        })?,
+                    };
+                    let by = match name.as_str() {
+                        "DECRBY" => by.checked_neg(),
+                        _ => Some(by),
+                    };
+                    let value = by.and_then(|by| current.checked_add(by)).ok_or_else(|| {
+                        Reply::Error("ERR increment or decrement would overflow".into())
+                    })?;
+                    self.entries
+                        .insert(key, Entry::String(value.to_string().into_bytes()));
+                    Reply::Int(value)
+                }
+                "DEL" | "UNLINK" => {
+                    arity(1)?;
+                    Reply::Int(
+                        args.iter()
+                            .filter(|key| self.entries.remove(&Store::key(prefix, key)).is_some())
+                            .count() as i64,
+                    )
+                }
+                "EXISTS" => {
+                    arity(1)?;
+                    Reply::Int(
+                        args.iter()
+                            .filter(|key| self.entries.contains_key(&Store::key(prefix, key)))
+                            .count() as i64,
+                    )
+                }
+                "TYPE" => {
+                    arity(1)?;
+                    Reply::Simple(match self.entries.get(&Store::key(prefix, &args[0])) {
+                        None => "none",
+                        Some(Entry::String(_)) => "string",
+                        Some(Entry::Hash(_)) => "hash",
+                        Some(Entry::List(_)) => "list",
+                    })
+                }
+                // Keys don't expire, but clients may still ask.
+                "EXPIRE" | "PEXPIRE" | "EXPIREAT" | "PEXPIREAT" | "PERSIST" => {
+                    arity(1)?;
+                    Reply::Int(self.entries.contains_key(&Store::key(prefix, &args[0])) as i64)
+                }
+                "TTL" | "PTTL" => {
+                    arity(1)?;
+                    match self.entries.contains_key(&Store::key(prefix, &args[0])) {
+                        true => Reply::Int(-1),
+                        false => Reply::Int(-2),
+                    }
+                }
+                "KEYS" => {
+                    arity(1)?;
+                    Reply::Array(self.keys(prefix, &args[0]))
+                }
+                // Everything in one go, the cursor is always done.
+                "SCAN" => {
+                    arity(1)?;
+                    let pattern = args
+                        .iter()
+                        .position(|arg| arg.eq_ignore_ascii_case(b"MATCH"))
+                        .and_then(|position| args.get(position + 1))
+                        .cloned()
+                        .unwrap_or_else(|| b"*".to_vec());
+                    Reply::Array(vec![bulk(b"0"), Reply::Array(self.keys(prefix, &pattern))])
+                }
+                "HSET" | "HMSET" => {
+                    arity(3)?;
+                    let hash = self.hash(Store::key(prefix, &args[0]))?;
+                    let mut added = 0;
+                    for pair in args[1..].chunks(2) {
+                        if let [field, value] = pair {
+                            added += hash.insert(field.clone(), value.clone()).is_none() as i64;
+                        }
+                    }
+                    match name.as_str() {
+                        "HMSET" => Reply::Simple("OK"),
+                        _ => Reply::Int(added),
+                    }
+                }
+                "HGET" => {
+                    arity(2)?;
+                    match self.entries.get(&Store::key(prefix, &args[0])) {
+                        None => Reply::Bulk(None),
+                        Some(Entry::Hash(hash)) => Reply::Bulk(hash.get(&args[1]).cloned()),
+                        Some(_) => return Err(Reply::Error(WRONGTYPE.into())),
+                    }
+                }
+                "HMGET" => {
+                    arity(2)?;
+                    match self.entries.get(&Store::key(prefix, &args[0])) {
+                        None => Reply::Array(args[1..].iter().map(|_| Reply::Bulk(None)).collect()),
+                        Some(Entry::Hash(hash)) => Reply::Array(
+                            args[1..]
+                                .iter()
+                                .map(|field| Reply::Bulk(hash.get(field).cloned()))
+                                .collect(),
+                        ),
+                        Some(_) => return Err(Reply::Error(WRONGTYPE.into())),
+                    }
+                }
+                "HGETALL" | "HKEYS" | "HVALS" | "HLEN" => {
+                    arity(1)?;
+                    let empty = HashMap::new();
+                    let hash = match self.entries.get(&Store::key(prefix, &args[0])) {
+                        None => &empty,
+                        Some(Entry::Hash(hash)) => hash,
+                        Some(_) => return Err(Reply::Error(WRONGTYPE.into())),
+                    };
+                    let mut fields: Vec<(&Vec<u8>, &Vec<u8>)> = hash.iter().collect();
+                    fields.sort();
+                    match name.as_str() {
+                        "HGETALL" => Reply::Array(
+                            fields
+                                .into_iter()
+                                .flat_map(|(field, value)| [bulk(field), bulk(value)])
+                                .collect(),
+                        ),
+                        "HKEYS" => Reply::Array(fields.into_iter().map(|(f, _)| bulk(f)).collect()),
+                        "HVALS" => Reply::Array(fields.into_iter().map(|(_, v)| bulk(v)).collect()),
+                        _ => Reply::Int(fields.len() as i64),
+                    }
+                }
+                "HDEL" => {
+                    arity(2)?;
+                    let key = Store::key(prefix, &args[0]);
+                    let removed = match self.entries.get_mut(&key) {
+                        None => 0,
+                        Some(Entry::Hash(hash)) => args[1..]
+                            .iter()
+                            .filter(|field| hash.remove(*field).is_some())
+                            .count(),
+                        Some(_) => return Err(Reply::Error(WRONGTYPE.into())),
+                    };
+                    self.remove_if_empty(&key);
+                    Reply::Int(removed as i64)
+                }
+                "HEXISTS" => {
+                    arity(2)?;
+                    match self.entries.get(&Store::key(prefix, &args[0])) {
+                        Some(Entry::Hash(hash)) => Reply::Int(hash.contains_key(&args[1]) as i64),
+                        None => Reply::Int(0),
+                        Some(_) => return Err(Reply::Error(WRONGTYPE.into())),
+                    }
+                }
+                "LPUSH" | "RPUSH" => {
+                    arity(2)?;
+                    let list = self.list(Store::key(prefix, &args[0]))?;
+                    for value in &args[1..] {
+                        match name.as_str() {
+                            "LPUSH" => list.push_front(value.clone()),
+                            _ => list.push_back(value.clone()),
+                        }
+                    }
+                    Reply::Int(list.len() as i64)
+                }
+                "LPOP" | "RPOP" => {
+                    arity(1)?;
+                    let key = Store::key(prefix, &args[0]);
+                    let popped = match self.entries.get_mut(&key) {
+                        None => None,
+                        Some(Entry::List(list)) => match name.as_str() {
+                            "LPOP" => list.pop_front(),
+                            _ => list.pop_back(),
+                        },
+                        Some(_) => return Err(Reply::Error(WRONGTYPE.into())),
+                    };
+                    self.remove_if_empty(&key);
+                    Reply::Bulk(popped)
+                }
+                "LLEN" => {
+                    arity(1)?;
+                    match self.entries.get(&Store::key(prefix, &args[0])) {
+                        None => Reply::Int(0),
+                        Some(Entry::List(list)) => Reply::Int(list.len() as i64),
+                        Some(_) => return Err(Reply::Error(WRONGTYPE.into())),
+                    }
+                }
+                "LRANGE" => {
+                    arity(3)?;
+                    let list = match self.entries.get(&Store::key(prefix, &args[0])) {
+                        None => return Ok(Reply::Array(vec![])),
+                        Some(Entry::List(list)) => list,
+                        Some(_) => return Err(Reply::Error(WRONGTYPE.into())),
+                    };
+                    let length = list.len() as i64;
+                    let index = |bytes: &[u8]| -> Result<i64, Reply> {
+                        let index = to_int(bytes).ok_or_else(|| {
+                            Reply::Error("ERR value is not an integer or out of range".into())
+                        })?;
+                        Ok(if index < 0 { length + index } else { index })
+                    };
+                    let (start, stop) = (index(&args[1])?.max(0), index(&args[2])?.min(length - 1));
+                    Reply::Array(match start <= stop {
+                        true => list
+                            .iter()
+                            .skip(start as usize)
+                            .take((stop - start + 1) as usize)
+                            .map(|value| bulk(value))
+                            .collect(),
+                        false => vec![],
+                    })
+                }
+                // Nobody subscribes in a test, so nobody receives anything.
+                "PUBLISH" => Reply::Int(0),
+                _ => Reply::Error(format!(
+                    "ERR unknown command '{}'",
+                    String::from_utf8_lossy(&command[0])
+                )),
+            })
+        })();
+        reply.unwrap_or_else(|error| error)
+    }
+}
+
+#[tokio::test]
+async fn test_in_process_redis() {
+    use redis::AsyncCommands;
+
+    let server = InProcessRedis::new();
+    let listener_1 = server.listen("test_1").await.unwrap();
+    let listener_2 = server.listen("test_2").await.unwrap();
+    let connect = |port: u16| async move {
+        redis::Client::open(format!("redis://127.0.0.1:{port}"))
+            .unwrap()
+            .get_multiplexed_async_connection()
+            .await
+            .unwrap()
+    };
+    let mut con_1 = connect(listener_1.port).await;
+    let mut con_2 = connect(listener_2.port).await;
+
+    let _: () = con_1.set("robot_request_state", "initial").await.unwrap();
+    let _: () = con_2.set("robot_request_state", "executing").await.unwrap();
+    let _: () = redis::pipe()
+        .atomic()
+        .set("robot_request_trigger", "true")
+        .set("gantry_request_trigger", "false")
+        .query_async(&mut con_1)
+        .await
+        .unwrap();
+
+    let value: String = con_1.get("robot_request_state").await.unwrap();
+    assert_eq!(value, "initial");
+    let values: Vec<Option<String>> = con_1
+        .mget(&["robot_request_trigger", "no_such_key"])
+        .await
+        .unwrap();
+    assert_eq!(values, vec![Some("true".to_string()), None]);
+    let mut keys: Vec<String> = con_1.keys("*_request_*").await.unwrap();
+    keys.sort();
+    assert_eq!(
+        keys,
+        vec![
+            "gantry_request_trigger",
+            "robot_request_state",
+            "robot_request_trigger"
+        ]
+    );
+    let counter: i64 = con_1.incr("counter", i64::MAX).await.unwrap();
+    assert_eq!(counter, i64::MAX);
+    let overflow: redis::RedisResult<i64> = con_1.incr("counter", 1).await;
+    assert!(overflow.is_err());
+    let counter: i64 = con_1.get("counter").await.unwrap();
+    assert_eq!(counter, i64::MAX);
+
+    let keys: Vec<String> = con_2.keys("*").await.unwrap();
+    assert_eq!(keys, vec!["robot_request_state"]);
+    assert_eq!(server.dump("test_2").len(), 1);
+
+    assert!(glob_match(b"op_*", b"op_gantry_lock"));
+    assert!(glob_match(b"robot_[ab]?", b"robot_b1"));
+    assert!(!glob_match(b"robot_[^ab]*", b"robot_a"));
+}
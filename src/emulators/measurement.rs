@@ -0,0 +1,253 @@
+use micro_sp::{
+    ArrayOrUnknown, BoolOrUnknown, FloatOrUnknown, IntOrUnknown, SPValue, StringOrUnknown,
+    ToSPValue,
+};
+use rand::Rng;
+use std::{sync::Arc, time::Duration};
+
+use crate::{StateBackend, emulated_sleep, spawn_emulated};
+
+#[derive(Debug, Clone)]
+pub struct MeasurementEmulation {
+    pub emulated_noise: f64,
+    pub emulated_delay: i64,
+    pub emulated_dropout_rate: i64,
+}
+
+// The value as the sensor would report it: with uniform noise in [-noise, noise] added to
+// numbers, or UNKNOWN altogether when the reading drops out.
+pub fn emulate_measurement(value: &SPValue, emulation: &MeasurementEmulation) -> SPValue {
+    let mut rng = crate::emulator_rng();
+    let dropout = emulation.emulated_dropout_rate > 0
+        && rng.gen_range(0..100) < emulation.emulated_dropout_rate as u64;
+
+    if dropout {
+        return match value {
+            SPValue::Bool(_) => SPValue::Bool(BoolOrUnknown::UNKNOWN),
+            SPValue::Int64(_) => SPValue::Int64(IntOrUnknown::UNKNOWN),
+            SPValue::Float64(_) => SPValue::Float64(FloatOrUnknown::UNKNOWN),
+            SPValue::String(_) => SPValue::String(StringOrUnknown::UNKNOWN),
+            SPValue::Array(_) => SPValue::Array(ArrayOrUnknown::UNKNOWN),
+            other => other.clone(),
+        };
+    }
+
+    let noise = emulation.emulated_noise.abs();
+    if noise == 0.0 {
+        return value.clone();
+    }
+
+    match value {
+        SPValue::Float64(FloatOrUnknown::Float64(x)) => {
+            (x.into_inner() + rng.gen_range(-noise..=noise)).to_spvalue()
+        }
+        SPValue::Int64(IntOrUnknown::Int64(x)) => {
+            (*x + rng.gen_range(-noise..=noise).round() as i64).to_spvalue()
+        }
+        SPValue::Array(ArrayOrUnknown::Array(values)) => {
+            // Samples of an array are noisy, but the array drops out as a whole
+            let sample_emulation = MeasurementEmulation {
+                emulated_dropout_rate: 0,
+                ..emulation.clone()
+            };
+            values
+                .iter()
+                .map(|x| emulate_measurement(x, &sample_emulation))
+                .collect::<Vec<SPValue>>()
+                .to_spvalue()
+        }
+        other => other.clone(),
+    }
+}
+
+// Publish a measured variable through the measurement layer. The emulation is configured
+// per variable with "{measured}_emulated_noise", "{measured}_emulated_delay" and
+// "{measured}_emulated_dropout_rate". Delayed readings are published in the background,
+// so the caller can already report that the request succeeded.
+pub async fn publish_measurement<B: StateBackend + ?Sized>(
+    connection_manager: &Arc<B>,
+    measured: &str,
+    value: SPValue,
+    log_target: &str,
+) {
+    let keys: Vec<String> = vec![
+        measured.to_string(),
+        format!("{measured}_emulated_noise"),
+        format!("{measured}_emulated_delay"),
+        format!("{measured}_emulated_dropout_rate"),
+    ];
+
+    let state = match connection_manager
+        .get_state_for_keys(&keys, log_target)
+        .await
+    {
+        Some(s) => s,
+        None => return,
+    };
+
+    let emulation = MeasurementEmulation {
+        emulated_noise: state
+            .get_float_or_default_to_zero(&format!("{measured}_emulated_noise"), log_target),
+        emulated_delay: state
+            .get_int_or_default_to_zero(&format!("{measured}_emulated_delay"), log_target),
+        emulated_dropout_rate: state
+            .get_int_or_default_to_zero(&format!("{measured}_emulated_dropout_rate"), log_target),
+    };
+
+    let reading = emulate_measurement(&value, &emulation);
+
+    if emulation.emulated_delay <= 0 {
+        let new_state = state.update(measured, reading);
+        let modified_state = state.get_diff_partial_state(&new_state);
+        connection_manager.set_state(&modified_state).await;
+        return;
+    }
+
+    let connection_manager = connection_manager.clone();
+    let measured = measured.to_string();
+    let log_target = log_target.to_string();
+    spawn_emulated(async move {
+        emulated_sleep(Duration::from_millis(emulation.emulated_delay as u64)).await;
+        if let Some(state) = connection_manager
+            .get_state_for_keys(&[measured.clone()], &log_target)
+            .await
+        {
+            let new_state = state.update(&measured, reading);
+            let modified_state = state.get_diff_partial_state(&new_state);
+            connection_manager.set_state(&modified_state).await;
+        }
+    });
+}
+
+#[tokio::test]
+async fn test_emulate_measurement_noise() {
+    let emulation = MeasurementEmulation {
+        emulated_noise: 0.5,
+        emulated_delay: 0,
+        emulated_dropout_rate: 0,
+    };
+    let readings: Vec<f64> = crate::with_seed(7, async {
+        (0..1000)
+            .map(
+                |_| match emulate_measurement(&10.0.to_spvalue(), &emulation) {
+                    SPValue::Float64(FloatOrUnknown::Float64(x)) => x.into_inner(),
+                    reading => panic!("Expected a float reading, got {reading:?}."),
+                },
+            )
+            .collect()
+    })
+    .await;
+    assert!(readings.iter().all(|x| (9.5..=10.5).contains(x)));
+    assert!(readings.iter().any(|x| *x != readings[0]));
+
+    let emulation = MeasurementEmulation {
+        emulated_noise: 2.0,
+        ..emulation
+    };
+    crate::with_seed(7, async {
+        for _ in 0..1000 {
+            match emulate_measurement(&10.to_spvalue(), &emulation) {
+                SPValue::Int64(IntOrUnknown::Int64(x)) => assert!((8..=12).contains(&x)),
+                reading => panic!("Expected an int reading, got {reading:?}."),
+            }
+            // Samples are noisy one by one, strings are never touched
+            let samples = vec![1.0.to_spvalue(), 2.0.to_spvalue()].to_spvalue();
+            match emulate_measurement(&samples, &emulation) {
+                SPValue::Array(ArrayOrUnknown::Array(samples)) => {
+                    assert_eq!(samples.len(), 2);
+                    for (sample, value) in samples.iter().zip([1.0, 2.0]) {
+                        match sample {
+                            SPValue::Float64(FloatOrUnknown::Float64(x)) => {
+                                assert!((x.into_inner() - value).abs() <= 2.0)
+                            }
+                            sample => panic!("Expected a float sample, got {sample:?}."),
+                        }
+                    }
+                }
+                reading => panic!("Expected an array reading, got {reading:?}."),
+            }
+            assert_eq!(
+                emulate_measurement(&"gripper_tool".to_spvalue(), &emulation),
+                "gripper_tool".to_spvalue()
+            );
+        }
+    })
+    .await;
+
+    let exact = MeasurementEmulation {
+        emulated_noise: 0.0,
+        ..emulation
+    };
+    assert_eq!(
+        emulate_measurement(&10.0.to_spvalue(), &exact),
+        10.0.to_spvalue()
+    );
+}
+
+#[tokio::test]
+async fn test_emulate_measurement_dropout() {
+    let emulation = MeasurementEmulation {
+        emulated_noise: 0.0,
+        emulated_delay: 0,
+        emulated_dropout_rate: 100,
+    };
+    assert_eq!(
+        emulate_measurement(&10.0.to_spvalue(), &emulation),
+        SPValue::Float64(FloatOrUnknown::UNKNOWN)
+    );
+    assert_eq!(
+        emulate_measurement(&"gripper_tool".to_spvalue(), &emulation),
+        SPValue::String(StringOrUnknown::UNKNOWN)
+    );
+    assert_eq!(
+        emulate_measurement(&true.to_spvalue(), &emulation),
+        SPValue::Bool(BoolOrUnknown::UNKNOWN)
+    );
+
+    let emulation = MeasurementEmulation {
+        emulated_dropout_rate: 30,
+        ..emulation
+    };
+    let dropouts = crate::with_seed(7, async {
+        (0..1000)
+            .filter(|_| {
+                emulate_measurement(&"gripper_tool".to_spvalue(), &emulation)
+                    == SPValue::String(StringOrUnknown::UNKNOWN)
+            })
+            .count()
+    })
+    .await;
+    assert!((200..400).contains(&dropouts), "{dropouts}");
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_publish_measurement_delay() {
+    let log_target = "test_publish_measurement_delay";
+    let state = crate::model::state::state();
+    let new_state = state.update(
+        "agv_battery_level_measured_emulated_delay",
+        1000.to_spvalue(),
+    );
+    let backend: Arc<dyn StateBackend> = Arc::new(crate::InMemoryBackend::new(&new_state));
+    let measured = || {
+        let backend = backend.clone();
+        async move {
+            let state = backend.get_full_state().await.unwrap();
+            state.get_float_or_default_to_zero("agv_battery_level_measured", log_target)
+        }
+    };
+
+    publish_measurement(
+        &backend,
+        "agv_battery_level_measured",
+        50.0.to_spvalue(),
+        log_target,
+    )
+    .await;
+    // The previous reading stays until the delayed one arrives
+    assert_eq!(measured().await, 100.0);
+    emulated_sleep(Duration::from_millis(900)).await;
+    assert_eq!(measured().await, 100.0);
+    emulated_sleep(Duration::from_millis(200)).await;
+    assert_eq!(measured().await, 50.0);
+}
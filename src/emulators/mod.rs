@@ -1,7 +1,22 @@
 // pub mod ticker;
 // pub mod scanner_client_ticker;
 // pub mod gripper_client_ticker;
+pub mod agv;
+pub mod backend;
+pub mod capabilities;
+pub mod clock;
+pub mod degraded;
+pub mod downtime;
 pub mod gantry;
+pub mod in_process_redis;
+pub mod measurement;
+pub mod motion;
+pub mod nutrunner;
+pub mod operator;
+pub mod progress;
+pub mod queue;
+pub mod random;
+pub mod reservation;
 pub mod robot;
 // pub mod set_state_server;
-// pub mod state_publisher;
\ No newline at end of file
+// pub mod state_publisher;
@@ -0,0 +1,93 @@
+use rand::Rng;
+
+use crate::{MAX_SPEED, NOMINAL_SPEED};
+
+// What a motion of the gantry or the robot depends on, besides the usual emulation.
+// The payload is only known if the model says so, 0.0 means no (or an unknown) payload.
+#[derive(Debug, Clone)]
+pub struct MotionEmulation {
+    pub speed: f64,
+    pub payload: f64,
+    pub emulated_max_payload: f64,
+    pub emulated_motion_failure_rate: i64,
+}
+
+// Positions have to be in the catalog of the resource, unless it has no catalog at all.
+pub fn is_known_position(positions: &[String], position: &str) -> bool {
+    positions.is_empty() || positions.iter().any(|known| known == position)
+}
+
+// Commands that don't set a speed, i.e. an unset or 0.0 speed_command, move at the
+// nominal speed, like the models written before speeds were emulated expect.
+pub fn commanded_speed(speed: f64) -> f64 {
+    match speed == 0.0 {
+        true => NOMINAL_SPEED,
+        false => speed,
+    }
+}
+
+pub fn is_valid_speed(speed: f64) -> bool {
+    let speed = commanded_speed(speed);
+    speed > 0.0 && speed <= MAX_SPEED
+}
+
+// How much longer a motion takes compared to moving at the nominal speed without payload.
+// Twice the speed halves the time, and a payload at the max payload doubles it.
+pub fn motion_time_factor(motion: &MotionEmulation) -> f64 {
+    if !is_valid_speed(motion.speed) {
+        return 1.0;
+    }
+    NOMINAL_SPEED / commanded_speed(motion.speed) * (1.0 + payload_strain(motion))
+}
+
+// Whether a motion fails because of how it was commanded. Invalid speeds always fail.
+// Otherwise, moving faster than the nominal speed or with a payload adds up to
+// "emulated_motion_failure_rate" percent of failure probability each, at full speed
+// and at the max payload respectively.
+pub fn emulate_motion_failure(motion: &MotionEmulation) -> Option<String> {
+    if !is_valid_speed(motion.speed) {
+        return Some("invalid_speed".to_string());
+    }
+
+    let speed = commanded_speed(motion.speed);
+    let speed_strain = ((speed - NOMINAL_SPEED) / (MAX_SPEED - NOMINAL_SPEED)).max(0.0);
+    let payload_strain = payload_strain(motion);
+    let rate = motion.emulated_motion_failure_rate as f64 * (speed_strain + payload_strain);
+    if rate <= 0.0 || crate::emulator_rng().gen_range(0.0..100.0) >= rate {
+        return None;
+    }
+
+    match speed_strain >= payload_strain {
+        true => Some("high_speed".to_string()),
+        false => Some("heavy_payload".to_string()),
+    }
+}
+
+fn payload_strain(motion: &MotionEmulation) -> f64 {
+    match motion.emulated_max_payload > 0.0 {
+        true => (motion.payload / motion.emulated_max_payload).max(0.0),
+        false => 0.0,
+    }
+}
+
+#[test]
+fn test_commanded_speed() {
+    let motion = |speed: f64| MotionEmulation {
+        speed,
+        payload: 0.0,
+        emulated_max_payload: 0.0,
+        emulated_motion_failure_rate: 100,
+    };
+    // An unset speed is the nominal speed, so it neither fails nor changes the time
+    assert_eq!(emulate_motion_failure(&motion(0.0)), None);
+    assert_eq!(motion_time_factor(&motion(0.0)), 1.0);
+    assert_eq!(motion_time_factor(&motion(2.0 * NOMINAL_SPEED)), 0.5);
+    assert_eq!(
+        emulate_motion_failure(&motion(-0.5)),
+        Some("invalid_speed".to_string())
+    );
+    assert_eq!(
+        emulate_motion_failure(&motion(MAX_SPEED + 0.1)),
+        Some("invalid_speed".to_string())
+    );
+}
@@ -0,0 +1,607 @@
+use micro_sp::{SPValue, ServiceRequestState, State, ToSPValue};
+use rand::Rng;
+use rand::prelude::SliceRandom;
+use serde_json::json;
+use std::{sync::Arc, time::Duration};
+
+use crate::{
+    DONT_EMULATE_NOK, Degradation, Downtime, EMULATE_NOK_ALWAYS, EMULATE_NOK_RANDOM_RATE,
+    EMULATOR_TICK_INTERVAL, EmulatedInstant, Reservation, StateBackend, arbitrate_degraded_mode,
+    arbitrate_request, emulate_downtime, emulate_progress, emulated_interval, get_string_array,
+    publish_measurement, reject_in_maintenance, resource_capabilities, resource_online_change,
+};
+
+#[derive(Debug, Clone)]
+pub struct NutrunnerRequest {
+    pub command: String,
+    pub target_torque: f64,
+    pub target_angle: f64,
+    pub emulate_execution_time: i64,
+    pub emulated_execution_time: i64,
+    pub emulate_failure_rate: i64,
+    pub emulated_failure_rate: i64,
+    pub emulate_failure_cause: i64,
+    pub emulated_failure_cause: Vec<String>,
+    pub execution_time_factor: f64,
+    pub emulate_quality: i64,
+    pub emulated_nok_rate: i64,
+    pub emulated_torque_tolerance: f64,
+    pub emulate_torque_curve: bool,
+    pub emulated_torque_curve_samples: i64,
+}
+
+#[derive(Debug, Clone)]
+pub struct NutrunnerResponse {
+    pub success: bool,
+    pub failure_cause: String,
+    pub info: String,
+    pub final_torque: f64,
+    pub final_angle: f64,
+    pub quality: String,
+    pub torque_curve: Vec<f64>,
+}
+
+pub async fn nutrunner_emulator<B: StateBackend + ?Sized>(
+    name: &str,
+    connection_manager: &Arc<B>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut interval = emulated_interval(Duration::from_millis(EMULATOR_TICK_INTERVAL));
+    let log_target = format!("{name}_emulator");
+    log::info!(target: &log_target, "Online.");
+    let started = EmulatedInstant::now();
+    // What the previous tick made of the downtime windows, see resource_online_change.
+    let mut previous_downtime: Option<Downtime> = None;
+
+    let keys: Vec<String> = vec![
+        "request_trigger",
+        "request_state",
+        "total_fail_counter",
+        "subsequent_fail_counter",
+        "owner_command",
+        "owner_estimated",
+        "owner_queue_estimated",
+        "capabilities",
+        "command_command",
+        "target_torque_command",
+        "target_angle_command",
+        "emulate_execution_time",
+        "emulated_execution_time",
+        "emulate_failure_rate",
+        "emulated_failure_rate",
+        "emulate_failure_cause",
+        "emulated_failure_cause",
+        "resource_online",
+        "downtime_estimated",
+        "emulate_downtime",
+        "emulated_downtime_windows",
+        "mode_estimated",
+        "emulated_degrading_causes",
+        "emulated_degraded_time_factor",
+        "emulated_degraded_refused_commands",
+        "emulate_quality",
+        "emulated_nok_rate",
+        "emulated_torque_tolerance",
+        "emulate_torque_curve",
+        "emulated_torque_curve_samples",
+    ]
+    .iter()
+    .map(|k| format!("{name}_{k}"))
+    .collect();
+
+    loop {
+        interval.tick().await;
+        if !connection_manager.check_health(&log_target).await {
+            continue;
+        }
+        let state = match connection_manager
+            .get_state_for_keys(&keys, &log_target)
+            .await
+        {
+            Some(s) => s,
+            None => continue,
+        };
+
+        let mut request_trigger =
+            state.get_bool_or_default_to_false(&format!("{name}_request_trigger"), &log_target);
+        let mut request_state =
+            state.get_string_or_default_to_unknown(&format!("{name}_request_state"), &log_target);
+
+        let mut total_fail_counter =
+            state.get_int_or_default_to_zero(&format!("{name}_total_fail_counter"), &log_target);
+        let mut subsequent_fail_counter = state
+            .get_int_or_default_to_zero(&format!("{name}_subsequent_fail_counter"), &log_target);
+
+        let downtime = emulate_downtime(
+            name,
+            &state,
+            started.elapsed().as_millis() as u64,
+            &log_target,
+        );
+
+        let mut reservation = Reservation::from_state(name, &state, &log_target);
+        let mut degradation = Degradation::from_state(name, &state, &log_target);
+
+        // An offline resource leaves the request pending until it is back
+        if request_trigger && downtime != Downtime::Offline {
+            request_trigger = false;
+            if request_state == ServiceRequestState::Initial.to_string() {
+                let emulated_failure_cause_sp_value = state.get_array_or_default_to_empty(
+                    &format!("{name}_emulated_failure_cause"),
+                    &log_target,
+                );
+
+                let emulated_failure_cause: Vec<String> = emulated_failure_cause_sp_value
+                    .iter()
+                    .filter(|val| val.is_string())
+                    .map(|y| y.to_string())
+                    .collect();
+
+                let request = NutrunnerRequest {
+                    command: state.get_string_or_default_to_unknown(
+                        &format!("{name}_command_command"),
+                        &log_target,
+                    ),
+                    target_torque: state.get_float_or_default_to_zero(
+                        &format!("{name}_target_torque_command"),
+                        &log_target,
+                    ),
+                    target_angle: state.get_float_or_default_to_zero(
+                        &format!("{name}_target_angle_command"),
+                        &log_target,
+                    ),
+                    emulate_execution_time: state.get_int_or_default_to_zero(
+                        &format!("{name}_emulate_execution_time"),
+                        &log_target,
+                    ),
+                    emulated_execution_time: state.get_int_or_default_to_zero(
+                        &format!("{name}_emulated_execution_time"),
+                        &log_target,
+                    ),
+                    emulate_failure_rate: state.get_int_or_default_to_zero(
+                        &format!("{name}_emulate_failure_rate"),
+                        &log_target,
+                    ),
+                    emulated_failure_rate: state.get_int_or_default_to_zero(
+                        &format!("{name}_emulated_failure_rate"),
+                        &log_target,
+                    ),
+                    emulate_failure_cause: state.get_int_or_default_to_zero(
+                        &format!("{name}_emulate_failure_cause"),
+                        &log_target,
+                    ),
+                    emulated_failure_cause,
+                    execution_time_factor: degradation.execution_time_factor(),
+                    emulate_quality: state.get_int_or_default_to_zero(
+                        &format!("{name}_emulate_quality"),
+                        &log_target,
+                    ),
+                    emulated_nok_rate: state.get_int_or_default_to_zero(
+                        &format!("{name}_emulated_nok_rate"),
+                        &log_target,
+                    ),
+                    emulated_torque_tolerance: state.get_float_or_default_to_zero(
+                        &format!("{name}_emulated_torque_tolerance"),
+                        &log_target,
+                    ),
+                    emulate_torque_curve: state.get_bool_or_default_to_false(
+                        &format!("{name}_emulate_torque_curve"),
+                        &log_target,
+                    ),
+                    emulated_torque_curve_samples: state.get_int_or_default_to_zero(
+                        &format!("{name}_emulated_torque_curve_samples"),
+                        &log_target,
+                    ),
+                };
+
+                let caller = state.get_string_or_default_to_unknown(
+                    &format!("{name}_owner_command"),
+                    &log_target,
+                );
+                let response = match reject_in_maintenance(&downtime, &request.command, &log_target)
+                    .or_else(|| {
+                        arbitrate_request(&request.command, &caller, &mut reservation, &log_target)
+                    })
+                    .or_else(|| {
+                        arbitrate_degraded_mode(&degradation, &request.command, &log_target)
+                    }) {
+                    Some(arbitration) => NutrunnerResponse {
+                        success: arbitration.success,
+                        failure_cause: arbitration.failure_cause,
+                        info: arbitration.info,
+                        final_torque: 0.0,
+                        final_angle: 0.0,
+                        quality: "".to_string(),
+                        torque_curve: vec![],
+                    },
+                    None => {
+                        emulate_nutrunner_operation(name, connection_manager, &request, &log_target)
+                            .await
+                    }
+                };
+                degradation.update(
+                    &request.command,
+                    response.success,
+                    &response.failure_cause,
+                    &log_target,
+                );
+
+                request_state = if response.success {
+                    subsequent_fail_counter = 0;
+                    // The result record of the latest tightening or loosening
+                    let torque_curve = response
+                        .torque_curve
+                        .iter()
+                        .map(|torque| torque.to_spvalue())
+                        .collect::<Vec<SPValue>>();
+                    for (measured, value) in [
+                        (
+                            &format!("{name}_final_torque_measured"),
+                            response.final_torque.to_spvalue(),
+                        ),
+                        (
+                            &format!("{name}_final_angle_measured"),
+                            response.final_angle.to_spvalue(),
+                        ),
+                        (
+                            &format!("{name}_quality_measured"),
+                            response.quality.to_spvalue(),
+                        ),
+                        (
+                            &format!("{name}_torque_curve_measured"),
+                            torque_curve.to_spvalue(),
+                        ),
+                    ] {
+                        publish_measurement(connection_manager, measured, value, &log_target).await;
+                    }
+                    ServiceRequestState::Succeeded.to_string()
+                } else {
+                    subsequent_fail_counter += 1;
+                    total_fail_counter += 1;
+                    ServiceRequestState::Failed.to_string()
+                };
+            }
+        }
+        let new_state = state
+            .update(
+                &format!("{name}_request_trigger"),
+                request_trigger.to_spvalue(),
+            )
+            .update(&format!("{name}_request_state"), request_state.to_spvalue())
+            .update(
+                &format!("{name}_total_fail_counter"),
+                total_fail_counter.to_spvalue(),
+            )
+            .update(
+                &format!("{name}_subsequent_fail_counter"),
+                subsequent_fail_counter.to_spvalue(),
+            )
+            .update(
+                &format!("{name}_owner_estimated"),
+                reservation.owner_to_spvalue(),
+            )
+            .update(
+                &format!("{name}_owner_queue_estimated"),
+                reservation.queue_to_spvalue(),
+            )
+            .update(
+                &format!("{name}_downtime_estimated"),
+                (downtime != Downtime::Up).to_spvalue(),
+            )
+            .update(
+                &format!("{name}_mode_estimated"),
+                degradation.mode_to_spvalue(),
+            )
+            .update(
+                &format!("{name}_capabilities"),
+                nutrunner_capabilities(name, &state, &log_target),
+            );
+
+        let new_state = match resource_online_change(previous_downtime.as_ref(), &downtime) {
+            Some(online) => {
+                new_state.update(&format!("{name}_resource_online"), online.to_spvalue())
+            }
+            None => new_state,
+        };
+        previous_downtime = Some(downtime);
+
+        let modified_state = state.get_diff_partial_state(&new_state);
+        connection_manager.set_state(&modified_state).await;
+    }
+}
+
+// Draw the final torque from the quality distribution. An ok result is within the tolerance band around the target torque, a nok result is
+// at least 0.1 Nm outside of it, also when the tolerance is 0.
+fn emulate_final_torque(target_torque: f64, tolerance: f64, ok: bool) -> f64 {
+    let band = target_torque.abs() * tolerance.max(0.0) / 100.0;
+    let mut rng = crate::emulator_rng();
+    if ok {
+        target_torque + rng.gen_range(-band..=band)
+    } else {
+        let deviation = band + rng.gen_range(0.1..=band.max(0.2));
+        if rng.gen_bool(0.5) {
+            target_torque - deviation
+        } else {
+            target_torque + deviation
+        }
+    }
+}
+
+// A tightening curve is flat until the screw head seats (snug point) and then rises
+// linearly up to the final torque. Loosening is the same curve in reverse.
+fn emulate_torque_curve(final_torque: f64, samples: i64, loosen: bool) -> Vec<f64> {
+    let samples = samples.max(2) as usize;
+    let snug_point = 0.7;
+    let mut curve: Vec<f64> = (0..samples)
+        .map(|i| {
+            let progress = i as f64 / (samples - 1) as f64;
+            if progress < snug_point {
+                final_torque * 0.05 * progress / snug_point
+            } else {
+                final_torque * (0.05 + 0.95 * (progress - snug_point) / (1.0 - snug_point))
+            }
+        })
+        .collect();
+    if loosen {
+        curve.reverse();
+    }
+    curve
+}
+
+// Published in "{name}_capabilities", see capabilities.rs.
+pub fn nutrunner_capabilities(name: &str, state: &State, log_target: &str) -> SPValue {
+    resource_capabilities(
+        vec![
+            (
+                "tighten",
+                json!({
+                    "target_torque_command": {"type": "float", "unit": "Nm"},
+                    "target_angle_command": {"type": "float", "unit": "deg"}
+                }),
+            ),
+            // The target torque of a loosening is the expected breakaway torque
+            (
+                "loosen",
+                json!({
+                    "target_torque_command": {"type": "float", "unit": "Nm"},
+                    "target_angle_command": {"type": "float", "unit": "deg"}
+                }),
+            ),
+        ],
+        &[],
+        &get_string_array(state, &format!("{name}_emulated_failure_cause"), log_target),
+        vec![
+            (
+                "emulate_quality",
+                json!([
+                    DONT_EMULATE_NOK,
+                    EMULATE_NOK_ALWAYS,
+                    EMULATE_NOK_RANDOM_RATE
+                ]),
+            ),
+            ("emulate_torque_curve", json!([false, true])),
+        ],
+        vec![],
+    )
+}
+
+pub async fn emulate_nutrunner_operation<B: StateBackend + ?Sized>(
+    name: &str,
+    connection_manager: &Arc<B>,
+    request: &NutrunnerRequest,
+    log_target: &str,
+) -> NutrunnerResponse {
+    let mut fail = match request.emulate_failure_rate {
+        0 => false,
+        1 => true,
+        2 => crate::emulator_rng().gen_range(0..=100) <= request.emulated_failure_rate as u64,
+        _ => false,
+    };
+
+    match request.command.as_str() {
+        "tighten" => log::info!(target: log_target,
+            "Got request to tighten to {} Nm and {} deg.", request.target_torque, request.target_angle
+        ),
+        "loosen" => log::info!(target: log_target,
+            "Got request to loosen from {} Nm by {} deg.", request.target_torque, request.target_angle
+        ),
+        _ => {
+            log::warn!(target: log_target, "Unknown command: '{}'", request.command);
+            fail = true;
+        }
+    };
+
+    let delay_ms: u64 = match request.emulate_execution_time {
+        0 => 0,
+        1 => request.emulated_execution_time as u64,
+        2 => crate::emulator_rng().gen_range(0..=request.emulated_execution_time) as u64,
+        _ => 0,
+    };
+    // Everything takes longer in degraded mode
+    let delay_ms = (delay_ms as f64 * request.execution_time_factor) as u64;
+    emulate_progress(name, connection_manager, delay_ms, log_target).await;
+
+    let cause = if fail {
+        match request.emulate_failure_cause {
+            0 => "generic_failure".to_string(),
+            1 => request
+                .emulated_failure_cause
+                .get(0)
+                .cloned()
+                .unwrap_or_else(|| "config_error".to_string()),
+            2 => request
+                .emulated_failure_cause
+                .choose(&mut crate::emulator_rng())
+                .cloned()
+                .unwrap_or_else(|| "random_error".to_string()),
+            _ => "generic_failure".to_string(),
+        }
+    } else {
+        "".to_string()
+    };
+
+    let nok = match request.emulate_quality {
+        0 => false,
+        1 => true,
+        2 => crate::emulator_rng().gen_range(0..100) < request.emulated_nok_rate,
+        _ => false,
+    };
+    let ok = !nok;
+
+    // When loosening, the result torque is the breakaway torque and the angle is reversed
+    let loosen = request.command == "loosen";
+    let final_torque =
+        emulate_final_torque(request.target_torque, request.emulated_torque_tolerance, ok);
+    let final_angle = if loosen {
+        -request.target_angle
+    } else {
+        request.target_angle
+    };
+    let quality = if ok { "ok" } else { "nok" }.to_string();
+
+    let torque_curve = if request.emulate_torque_curve {
+        emulate_torque_curve(final_torque, request.emulated_torque_curve_samples, loosen)
+    } else {
+        vec![]
+    };
+
+    let (success_info, failure_info) = match request.command.as_str() {
+        "tighten" => (
+            format!(
+                "Succeeded to tighten, result {:.2} Nm at {:.1} deg is {}.",
+                final_torque, final_angle, quality
+            ),
+            format!("Failed to tighten due to {}.", cause),
+        ),
+        "loosen" => (
+            format!(
+                "Succeeded to loosen, result {:.2} Nm at {:.1} deg is {}.",
+                final_torque, final_angle, quality
+            ),
+            format!("Failed to loosen due to {}.", cause),
+        ),
+        _ => (
+            "Failed, unknown command".to_string(),
+            "Failed, unknown command".to_string(),
+        ),
+    };
+
+    if !fail {
+        log::info!(target: log_target, "{}", success_info);
+        NutrunnerResponse {
+            success: true,
+            failure_cause: "".to_string(),
+            info: success_info,
+            final_torque,
+            final_angle,
+            quality,
+            torque_curve,
+        }
+    } else {
+        log::error!(target: log_target, "{}", failure_info);
+        NutrunnerResponse {
+            success: false,
+            failure_cause: cause,
+            info: failure_info,
+            final_torque: 0.0,
+            final_angle: 0.0,
+            quality: "UNKNOWN".to_string(),
+            torque_curve: vec![],
+        }
+    }
+}
+
+#[test]
+fn test_emulate_final_torque() {
+    for tolerance in [-5.0, 0.0, 10.0] {
+        let band = 50.0 * f64::max(tolerance, 0.0) / 100.0;
+        for _ in 0..1000 {
+            assert!((emulate_final_torque(50.0, tolerance, true) - 50.0).abs() <= band + 1e-9);
+            assert!((emulate_final_torque(50.0, tolerance, false) - 50.0).abs() > band);
+        }
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_nutrunner_emulator() {
+    use micro_sp::FloatOrUnknown;
+
+    let log_target = "test_nutrunner_emulator";
+    let state = crate::model::state::state()
+        .update("nutrunner_emulated_torque_tolerance", 10.0.to_spvalue())
+        .update("nutrunner_emulate_torque_curve", true.to_spvalue())
+        .update("nutrunner_emulated_torque_curve_samples", 10.to_spvalue());
+    let backend: Arc<dyn StateBackend> = Arc::new(crate::InMemoryBackend::new(&state));
+    let emulator = crate::spawn_emulator(
+        &crate::ScenarioEmulator::Nutrunner("nutrunner".to_string()),
+        &backend,
+    );
+
+    let run = |command: &'static str, emulate_quality: i64, nok_rate: i64| {
+        let backend = backend.clone();
+        async move {
+            let state = crate::complete_request(
+                &backend,
+                "nutrunner",
+                &[
+                    ("command_command", command.to_spvalue()),
+                    ("target_torque_command", 50.0.to_spvalue()),
+                    ("target_angle_command", 90.0.to_spvalue()),
+                    ("emulate_quality", emulate_quality.to_spvalue()),
+                    ("emulated_nok_rate", nok_rate.to_spvalue()),
+                ],
+            )
+            .await;
+            assert_eq!(
+                state.get_string_or_default_to_unknown("nutrunner_request_state", log_target),
+                "succeeded"
+            );
+            state
+        }
+    };
+    let result = |state: &State| {
+        let curve: Vec<f64> = state
+            .get_array_or_default_to_empty("nutrunner_torque_curve_measured", log_target)
+            .iter()
+            .map(|sample| match sample {
+                SPValue::Float64(FloatOrUnknown::Float64(x)) => x.into_inner(),
+                sample => panic!("Expected a torque, got {sample:?}."),
+            })
+            .collect();
+        (
+            state.get_string_or_default_to_unknown("nutrunner_quality_measured", log_target),
+            state.get_float_or_default_to_zero("nutrunner_final_torque_measured", log_target),
+            state.get_float_or_default_to_zero("nutrunner_final_angle_measured", log_target),
+            curve,
+        )
+    };
+
+    // An ok tightening lands within 10 % of the target, and the curve rises up to it
+    let (quality, torque, angle, curve) = result(&run("tighten", DONT_EMULATE_NOK, 0).await);
+    assert_eq!(quality, "ok");
+    assert!((torque - 50.0).abs() <= 5.0, "{torque}");
+    assert_eq!(angle, 90.0);
+    assert_eq!(curve.len(), 10);
+    assert!(curve.windows(2).all(|w| w[0] <= w[1]), "{curve:?}");
+    assert!((curve[9] - torque).abs() < 1e-9);
+
+    // A nok tightening still succeeds as a request, but lands outside of the band
+    let (quality, torque, _, _) = result(&run("tighten", EMULATE_NOK_ALWAYS, 0).await);
+    assert_eq!(quality, "nok");
+    assert!((torque - 50.0).abs() > 5.0, "{torque}");
+
+    // A random rate of 0 never gives a nok
+    for _ in 0..100 {
+        let (quality, _, _, _) = result(&run("tighten", EMULATE_NOK_RANDOM_RATE, 0).await);
+        assert_eq!(quality, "ok");
+    }
+
+    // Loosening starts at the breakaway torque and turns the other way
+    let (quality, torque, angle, curve) = result(&run("loosen", DONT_EMULATE_NOK, 0).await);
+    assert_eq!(quality, "ok");
+    assert!((torque - 50.0).abs() <= 5.0, "{torque}");
+    assert_eq!(angle, -90.0);
+    assert!(curve.windows(2).all(|w| w[0] >= w[1]), "{curve:?}");
+    assert!((curve[0] - torque).abs() < 1e-9);
+
+    emulator.abort();
+}
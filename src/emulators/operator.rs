@@ -0,0 +1,368 @@
+use micro_sp::{OperationState, ToSPValue};
+use rand::Rng;
+use rand::prelude::SliceRandom;
+use std::collections::HashMap;
+use std::{sync::Arc, time::Duration};
+
+use crate::{EMULATOR_TICK_INTERVAL, EmulatedInstant, StateBackend, emulated_interval};
+
+#[derive(Debug, Clone)]
+pub struct OperatorRequest {
+    pub operation: String,
+    pub operation_state: String,
+    pub policy: i64,
+    pub emulate_mistake_rate: i64,
+    pub emulated_mistake_rate: i64,
+}
+
+#[derive(Debug, Clone)]
+pub struct OperatorResponse {
+    pub action: String,
+    pub new_operation_state: Option<String>,
+    pub mistake: bool,
+    pub info: String,
+}
+
+// The operator waits in front of the cell and reacts to operations that need a human,
+// i.e. operations that failed, timed out or ended up in a fatal state.
+fn needs_operator(operation_state: &str) -> bool {
+    operation_state == OperationState::Failed.to_string()
+        || operation_state == OperationState::Timedout.to_string()
+        || operation_state == OperationState::Fatal.to_string()
+}
+
+pub async fn operator_emulator<B: StateBackend + ?Sized>(
+    connection_manager: &Arc<B>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut interval = emulated_interval(Duration::from_millis(EMULATOR_TICK_INTERVAL));
+    let log_target = "operator_emulator";
+    log::info!(target: &log_target, "Online.");
+
+    let keys: Vec<String> = vec![
+        "operator_watched_operations",
+        "operator_policy",
+        "operator_intervention_counter",
+        "operator_last_operation",
+        "operator_last_action",
+        "operator_emulate_reaction_time",
+        "operator_emulated_reaction_time",
+        "operator_emulate_mistake_rate",
+        "operator_emulated_mistake_rate",
+    ]
+    .iter()
+    .map(|k| k.to_string())
+    .collect();
+
+    // When did the operator notice that an operation needs attention, and
+    // how long will it take them to react to it.
+    let mut noticed: HashMap<String, (EmulatedInstant, u64)> = HashMap::new();
+    // Operations that have already been handled while still in the same state.
+    let mut handled: HashMap<String, String> = HashMap::new();
+
+    loop {
+        interval.tick().await;
+        if !connection_manager.check_health(&log_target).await {
+            continue;
+        }
+        let state = match connection_manager
+            .get_state_for_keys(&keys, &log_target)
+            .await
+        {
+            Some(s) => s,
+            None => continue,
+        };
+
+        let watched_operations: Vec<String> = state
+            .get_array_or_default_to_empty("operator_watched_operations", &log_target)
+            .iter()
+            .filter(|val| val.is_string())
+            .map(|y| y.to_string())
+            .collect();
+
+        if watched_operations.is_empty() {
+            continue;
+        }
+
+        let operations_state = match connection_manager
+            .get_state_for_keys(&watched_operations, &log_target)
+            .await
+        {
+            Some(s) => s,
+            None => continue,
+        };
+
+        let mut intervention_counter =
+            state.get_int_or_default_to_zero("operator_intervention_counter", &log_target);
+        let mut last_operation =
+            state.get_string_or_default_to_unknown("operator_last_operation", &log_target);
+        let mut last_action =
+            state.get_string_or_default_to_unknown("operator_last_action", &log_target);
+
+        let mut new_operations_state = operations_state.clone();
+
+        for operation in &watched_operations {
+            let operation_state =
+                operations_state.get_string_or_default_to_unknown(operation, &log_target);
+
+            if !needs_operator(&operation_state) {
+                noticed.remove(operation);
+                handled.remove(operation);
+                continue;
+            }
+
+            if handled.get(operation) == Some(&operation_state) {
+                continue;
+            }
+
+            let (noticed_at, reaction_time_ms) =
+                *noticed.entry(operation.clone()).or_insert_with(|| {
+                    let reaction_time_ms = match state
+                        .get_int_or_default_to_zero("operator_emulate_reaction_time", &log_target)
+                    {
+                        0 => 0,
+                        1 => state.get_int_or_default_to_zero(
+                            "operator_emulated_reaction_time",
+                            &log_target,
+                        ) as u64,
+                        2 => crate::emulator_rng().gen_range(
+                            0..=state.get_int_or_default_to_zero(
+                                "operator_emulated_reaction_time",
+                                &log_target,
+                            ),
+                        ) as u64,
+                        _ => 0,
+                    };
+                    log::info!(target: &log_target,
+                        "Noticed that {} is {}, reacting in {} ms.",
+                        operation, operation_state, reaction_time_ms
+                    );
+                    (EmulatedInstant::now(), reaction_time_ms)
+                });
+
+            if noticed_at.elapsed() < Duration::from_millis(reaction_time_ms) {
+                continue;
+            }
+
+            let request = OperatorRequest {
+                operation: operation.clone(),
+                operation_state: operation_state.clone(),
+                policy: state.get_int_or_default_to_zero("operator_policy", &log_target),
+                emulate_mistake_rate: state
+                    .get_int_or_default_to_zero("operator_emulate_mistake_rate", &log_target),
+                emulated_mistake_rate: state
+                    .get_int_or_default_to_zero("operator_emulated_mistake_rate", &log_target),
+            };
+
+            let response = emulate_operator_intervention(&request);
+
+            if let Some(new_operation_state) = &response.new_operation_state {
+                new_operations_state =
+                    new_operations_state.update(operation, new_operation_state.to_spvalue());
+            }
+
+            intervention_counter += 1;
+            last_operation = operation.clone();
+            last_action = response.action;
+            noticed.remove(operation);
+            handled.insert(
+                operation.clone(),
+                response
+                    .new_operation_state
+                    .unwrap_or_else(|| operation_state.clone()),
+            );
+        }
+
+        let new_state = state
+            .update(
+                "operator_intervention_counter",
+                intervention_counter.to_spvalue(),
+            )
+            .update("operator_last_operation", last_operation.to_spvalue())
+            .update("operator_last_action", last_action.to_spvalue());
+
+        // One write, so that the runner never sees an operation moved on by the operator
+        // without the intervention being counted, or the other way around
+        let modified_state = state.get_diff_partial_state(&new_state).extend(
+            operations_state.get_diff_partial_state(&new_operations_state),
+            true,
+        );
+        connection_manager.set_state(&modified_state).await;
+    }
+}
+
+pub fn emulate_operator_intervention(request: &OperatorRequest) -> OperatorResponse {
+    let mistake = match request.emulate_mistake_rate {
+        0 => false,
+        1 => true,
+        2 => crate::emulator_rng().gen_range(0..=100) <= request.emulated_mistake_rate as u64,
+        _ => false,
+    };
+
+    let intended_action = match request.policy {
+        0 => "acknowledge",
+        1 => "bypass",
+        2 => "retry",
+        3 => "abort",
+        _ => {
+            log::warn!(target: "operator_emulator", "Unknown policy: '{}', only acknowledging.", request.policy);
+            "acknowledge"
+        }
+    };
+
+    // A mistaken operator picks one of the other actions instead of the intended one
+    let action = if mistake {
+        vec!["acknowledge", "bypass", "retry", "abort"]
+            .into_iter()
+            .filter(|action| *action != intended_action)
+            .collect::<Vec<&str>>()
+            .choose(&mut crate::emulator_rng())
+            .unwrap()
+            .to_string()
+    } else {
+        intended_action.to_string()
+    };
+
+    let new_operation_state = match action.as_str() {
+        "bypass" => Some(OperationState::Bypassed.to_string()),
+        "retry" => Some(OperationState::Initial.to_string()),
+        "abort" => Some(OperationState::Fatal.to_string()),
+        _ => None,
+    };
+
+    let info = format!(
+        "Operator decided to {} {} which was {}.",
+        action, request.operation, request.operation_state
+    );
+
+    if mistake {
+        log::error!(target: "operator_emulator", "{} Intended to {}.", info, intended_action);
+    } else {
+        log::info!(target: "operator_emulator", "{}", info);
+    }
+
+    OperatorResponse {
+        action,
+        new_operation_state,
+        mistake,
+        info,
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_operator_emulator() {
+    use futures::future::BoxFuture;
+    use micro_sp::*;
+    use std::sync::Mutex;
+
+    // Keeps every write, to see what the runner could see in between
+    struct RecordingBackend {
+        backend: crate::InMemoryBackend,
+        writes: Mutex<Vec<State>>,
+    }
+
+    impl StateBackend for RecordingBackend {
+        fn check_health<'a>(&'a self, log_target: &'a str) -> BoxFuture<'a, bool> {
+            self.backend.check_health(log_target)
+        }
+
+        fn get_state_for_keys<'a>(
+            &'a self,
+            keys: &'a [String],
+            log_target: &'a str,
+        ) -> BoxFuture<'a, Option<State>> {
+            self.backend.get_state_for_keys(keys, log_target)
+        }
+
+        fn set_state<'a>(&'a self, state: &'a State) -> BoxFuture<'a, ()> {
+            self.writes.lock().unwrap().push(state.clone());
+            self.backend.set_state(state)
+        }
+
+        fn get_full_state(&self) -> BoxFuture<'_, Option<State>> {
+            self.backend.get_full_state()
+        }
+
+        fn get_sp_value<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Option<SPValue>> {
+            self.backend.get_sp_value(key)
+        }
+    }
+
+    let log_target = "test_operator_emulator";
+    let operations = [
+        ("op_gantry_unlock", OperationState::Failed),
+        ("op_gantry_lock", OperationState::Timedout),
+        ("op_gantry_calibrate", OperationState::Fatal),
+        ("op_robot_move_to_a", OperationState::Executing),
+    ];
+    let state = operations.iter().fold(
+        crate::model::state::state(),
+        |state, (operation, operation_state)| {
+            state.add(
+                assign!(v!(operation), operation_state.to_string().to_spvalue()),
+                "emulator",
+            )
+        },
+    );
+    let watched: Vec<SPValue> = operations
+        .iter()
+        .map(|(operation, _)| operation.to_spvalue())
+        .collect();
+    let state = state
+        .update("operator_watched_operations", watched.to_spvalue())
+        .update("operator_policy", crate::OPERATOR_BYPASS.to_spvalue());
+    let backend = Arc::new(RecordingBackend {
+        backend: crate::InMemoryBackend::new(&state),
+        writes: Mutex::new(vec![]),
+    });
+
+    let emulator = crate::spawn_emulated({
+        let backend = backend.clone();
+        async move { operator_emulator(&backend).await.unwrap() }
+    });
+    crate::emulated_sleep(Duration::from_millis(5 * EMULATOR_TICK_INTERVAL)).await;
+    emulator.abort();
+
+    let state = backend.get_full_state().await.unwrap();
+    let bypassed = OperationState::Bypassed.to_string();
+    for (operation, _) in &operations[..3] {
+        assert_eq!(
+            state.get_string_or_default_to_unknown(operation, log_target),
+            bypassed
+        );
+    }
+    assert_eq!(
+        state.get_string_or_default_to_unknown("op_robot_move_to_a", log_target),
+        OperationState::Executing.to_string()
+    );
+    assert_eq!(
+        state.get_int_or_default_to_zero("operator_intervention_counter", log_target),
+        3
+    );
+    assert_eq!(
+        state.get_string_or_default_to_unknown("operator_last_action", log_target),
+        "bypass"
+    );
+
+    // The operations and the intervention were written together
+    let writes = backend.writes.lock().unwrap();
+    let intervention = writes
+        .iter()
+        .find(|write| write.state.contains_key("op_gantry_unlock"))
+        .unwrap();
+    for key in [
+        "op_gantry_lock",
+        "op_gantry_calibrate",
+        "operator_intervention_counter",
+        "operator_last_operation",
+        "operator_last_action",
+    ] {
+        assert!(intervention.state.contains_key(key), "{key}");
+    }
+    assert_eq!(
+        writes
+            .iter()
+            .filter(|write| write.state.contains_key("operator_intervention_counter"))
+            .count(),
+        1
+    );
+}
@@ -0,0 +1,96 @@
+use micro_sp::ToSPValue;
+use std::{sync::Arc, time::Duration};
+
+use crate::{EMULATOR_TICK_INTERVAL, StateBackend, emulated_sleep};
+
+// Take the emulated execution time of a command, the way a ROS2 action would, and publish
+// "{name}_progress" (0..100) and "{name}_remaining_time" (milliseconds) every tick on the way.
+pub async fn emulate_progress<B: StateBackend + ?Sized>(
+    name: &str,
+    connection_manager: &Arc<B>,
+    execution_time: u64,
+    log_target: &str,
+) {
+    let mut elapsed = 0;
+    publish_progress(name, connection_manager, 0, execution_time, log_target).await;
+    while elapsed < execution_time {
+        let step = EMULATOR_TICK_INTERVAL.min(execution_time - elapsed);
+        emulated_sleep(Duration::from_millis(step)).await;
+        elapsed += step;
+        publish_progress(
+            name,
+            connection_manager,
+            elapsed * 100 / execution_time,
+            execution_time - elapsed,
+            log_target,
+        )
+        .await;
+    }
+    if execution_time == 0 {
+        publish_progress(name, connection_manager, 100, 0, log_target).await;
+    }
+}
+
+pub async fn publish_progress<B: StateBackend + ?Sized>(
+    name: &str,
+    connection_manager: &Arc<B>,
+    progress: u64,
+    remaining_time: u64,
+    log_target: &str,
+) {
+    let keys = vec![format!("{name}_progress"), format!("{name}_remaining_time")];
+
+    if let Some(state) = connection_manager
+        .get_state_for_keys(&keys, log_target)
+        .await
+    {
+        let new_state = state
+            .update(&format!("{name}_progress"), (progress as i64).to_spvalue())
+            .update(
+                &format!("{name}_remaining_time"),
+                (remaining_time as i64).to_spvalue(),
+            );
+        let modified_state = state.get_diff_partial_state(&new_state);
+        connection_manager.set_state(&modified_state).await;
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_emulate_progress() {
+    let log_target = "test_emulate_progress";
+    let state = crate::model::state::state();
+    let backend: Arc<dyn StateBackend> = Arc::new(crate::InMemoryBackend::new(&state));
+
+    // Look at the progress twice per tick while a 3 second command runs
+    let sampling = async {
+        let mut observed: Vec<(i64, i64)> = vec![];
+        for _ in 0..35 {
+            emulated_sleep(Duration::from_millis(EMULATOR_TICK_INTERVAL / 2)).await;
+            let state = backend.get_full_state().await.unwrap();
+            observed.push((
+                state.get_int_or_default_to_zero("gantry_progress", log_target),
+                state.get_int_or_default_to_zero("gantry_remaining_time", log_target),
+            ));
+        }
+        observed
+    };
+    let (_, observed) = tokio::join!(
+        emulate_progress("gantry", &backend, 3000, log_target),
+        sampling
+    );
+
+    // Reported along the way, not just at the end
+    assert!(
+        observed
+            .iter()
+            .any(|(progress, _)| *progress > 0 && *progress < 100)
+    );
+    // Progress only goes up, and the remaining time only goes down
+    assert!(
+        observed
+            .windows(2)
+            .all(|w| w[0].0 <= w[1].0 && w[0].1 >= w[1].1),
+        "{observed:?}"
+    );
+    assert_eq!(observed.last(), Some(&(100, 0)));
+}
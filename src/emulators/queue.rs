@@ -0,0 +1,387 @@
+use micro_sp::{SPValue, ServiceRequestState, State, ToSPValue};
+use std::{sync::Arc, time::Duration};
+
+use crate::{EMULATOR_TICK_INTERVAL, StateBackend, emulated_interval};
+
+// A job queue in front of a resource, like the job queue of a PLC. Callers append jobs to
+// "{name}_queue_command", and the jobs are fed one at a time, in order, through the usual
+// request slot of the resource. The list belongs to the caller, the emulator only counts
+// how many of its jobs it took over in "{name}_queue_consumed_estimated". A job is a JSON object with an "id" and the command
+// variables of the resource without the resource name, for example:
+// {"id": "job_1", "command_command": "move", "position_command": "a", "speed_command": 0.5}
+//
+// The status of every job is published in "{name}_queue_status_estimated" as "id:status",
+// where the status is queued, executing, succeeded, failed or rejected. Jobs that don't fit
+// into "{name}_emulated_queue_capacity" are rejected. The queue is only served while
+// "{name}_emulate_queue" is true, otherwise the resource is used one request at a time.
+#[derive(Debug, Clone)]
+pub struct QueuedJob {
+    pub id: String,
+    pub fields: Vec<(String, SPValue)>,
+}
+
+pub async fn queue_emulator<B: StateBackend + ?Sized>(
+    name: &str,
+    connection_manager: &Arc<B>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut interval = emulated_interval(Duration::from_millis(EMULATOR_TICK_INTERVAL));
+    let log_target = format!("{name}_queue_emulator");
+    log::info!(target: &log_target, "Online.");
+
+    let keys: Vec<String> = vec![
+        "request_trigger",
+        "request_state",
+        "queue_command",
+        "queue_consumed_estimated",
+        "queue_status_estimated",
+        "emulate_queue",
+        "emulated_queue_capacity",
+    ]
+    .iter()
+    .map(|k| format!("{name}_{k}"))
+    .collect();
+
+    // The id of the job that currently occupies the request slot
+    let mut executing: Option<String> = None;
+    // The jobs taken over from the caller that are waiting for the request slot
+    let mut pending: Vec<String> = vec![];
+    // The id of the last job taken over, to tell when the caller replaced the list
+    let mut last_consumed: Option<String> = None;
+
+    loop {
+        interval.tick().await;
+        if !connection_manager.check_health(&log_target).await {
+            continue;
+        }
+        let state = match connection_manager
+            .get_state_for_keys(&keys, &log_target)
+            .await
+        {
+            Some(s) => s,
+            None => continue,
+        };
+
+        if !state.get_bool_or_default_to_false(&format!("{name}_emulate_queue"), &log_target) {
+            continue;
+        }
+
+        let request_trigger =
+            state.get_bool_or_default_to_false(&format!("{name}_request_trigger"), &log_target);
+        let mut request_state =
+            state.get_string_or_default_to_unknown(&format!("{name}_request_state"), &log_target);
+
+        let capacity = state
+            .get_int_or_default_to_zero(&format!("{name}_emulated_queue_capacity"), &log_target)
+            .max(0) as usize;
+        let jobs: Vec<String> = state
+            .get_array_or_default_to_empty(&format!("{name}_queue_command"), &log_target)
+            .iter()
+            .filter(|val| val.is_string())
+            .map(|y| y.to_string())
+            .collect();
+        let mut consumed = state
+            .get_int_or_default_to_zero(&format!("{name}_queue_consumed_estimated"), &log_target)
+            .max(0) as usize;
+        let mut statuses: Vec<String> = state
+            .get_array_or_default_to_empty(&format!("{name}_queue_status_estimated"), &log_target)
+            .iter()
+            .filter(|val| val.is_string())
+            .map(|y| y.to_string())
+            .collect();
+        // The caller cleared the list or replaced it with a new one, even one that is at
+        // least as long, so start over and forget the jobs that are done
+        let replaced = consumed > 0
+            && last_consumed.is_some()
+            && jobs.get(consumed - 1).map(|job| job_id(job)) != last_consumed;
+        if consumed > jobs.len() || replaced {
+            log::info!(target: &log_target, "Job list replaced, starting over.");
+            consumed = 0;
+            statuses.retain(|status| !is_final(status));
+        }
+
+        // The job in the request slot counts towards the capacity as well
+        for job in &jobs[consumed..] {
+            let id = job_id(job);
+            if pending.len() + executing.iter().count() < capacity {
+                // Resubmitted ids start over
+                set_job_status(&mut statuses, &id, "queued");
+                pending.push(job.clone());
+            } else {
+                log::warn!(target: &log_target, "Queue is full, rejected job {}.", id);
+                set_job_status(&mut statuses, &id, "rejected");
+            }
+        }
+        consumed = jobs.len();
+        last_consumed = jobs.last().map(|job| job_id(job));
+
+        match &executing {
+            Some(id) => {
+                if !request_trigger
+                    && (request_state == ServiceRequestState::Succeeded.to_string()
+                        || request_state == ServiceRequestState::Failed.to_string())
+                {
+                    log::info!(target: &log_target, "Job {} {}.", id, request_state);
+                    set_job_status(&mut statuses, id, &request_state);
+                    request_state = ServiceRequestState::Initial.to_string();
+                    executing = None;
+                }
+            }
+            None => {
+                if !request_trigger
+                    && request_state == ServiceRequestState::Initial.to_string()
+                    && !pending.is_empty()
+                {
+                    let job = pending.remove(0);
+                    match parse_job(&job, &log_target) {
+                        Some(job) => {
+                            log::info!(target: &log_target, "Executing job {}.", job.id);
+                            start_job(name, connection_manager, &job, &log_target).await;
+                            set_job_status(&mut statuses, &job.id, "executing");
+                            executing = Some(job.id);
+                        }
+                        None => set_job_status(&mut statuses, &job_id(&job), "rejected"),
+                    }
+                }
+            }
+        }
+
+        let new_state = state
+            .update(&format!("{name}_request_state"), request_state.to_spvalue())
+            .update(
+                &format!("{name}_queue_consumed_estimated"),
+                (consumed as i64).to_spvalue(),
+            )
+            .update(
+                &format!("{name}_queue_status_estimated"),
+                statuses
+                    .iter()
+                    .map(|status| status.to_spvalue())
+                    .collect::<Vec<SPValue>>()
+                    .to_spvalue(),
+            );
+
+        let modified_state = state.get_diff_partial_state(&new_state);
+        connection_manager.set_state(&modified_state).await;
+    }
+}
+
+// Write the command variables of the job and pull the trigger of the resource.
+async fn start_job<B: StateBackend + ?Sized>(
+    name: &str,
+    connection_manager: &Arc<B>,
+    job: &QueuedJob,
+    log_target: &str,
+) {
+    let mut keys: Vec<String> = job
+        .fields
+        .iter()
+        .map(|(field, _)| format!("{name}_{field}"))
+        .collect();
+    keys.push(format!("{name}_request_trigger"));
+
+    if let Some(state) = connection_manager
+        .get_state_for_keys(&keys, log_target)
+        .await
+    {
+        let new_state = job
+            .fields
+            .iter()
+            .fold(state.clone(), |new_state: State, (field, value)| {
+                new_state.update(&format!("{name}_{field}"), value.clone())
+            })
+            .update(&format!("{name}_request_trigger"), true.to_spvalue());
+        let modified_state = state.get_diff_partial_state(&new_state);
+        connection_manager.set_state(&modified_state).await;
+    }
+}
+
+pub fn parse_job(job: &str, log_target: &str) -> Option<QueuedJob> {
+    let object = match serde_json::from_str::<serde_json::Value>(job) {
+        Ok(serde_json::Value::Object(object)) => object,
+        _ => {
+            log::error!(target: log_target, "Failed to parse job: '{}'.", job);
+            return None;
+        }
+    };
+
+    let mut fields = vec![];
+    for (field, value) in object.iter().filter(|(field, _)| *field != "id") {
+        let value = match value {
+            serde_json::Value::Bool(x) => x.to_spvalue(),
+            serde_json::Value::String(x) => x.to_spvalue(),
+            serde_json::Value::Number(x) => match x.as_i64() {
+                Some(x) => x.to_spvalue(),
+                None => x.as_f64().unwrap_or_default().to_spvalue(),
+            },
+            serde_json::Value::Array(values) => values
+                .iter()
+                .filter_map(|x| x.as_str())
+                .map(|x| x.to_spvalue())
+                .collect::<Vec<SPValue>>()
+                .to_spvalue(),
+            _ => {
+                log::error!(target: log_target, "Unsupported value for '{}' in job: '{}'.", field, job);
+                return None;
+            }
+        };
+        fields.push((field.to_string(), value));
+    }
+
+    Some(QueuedJob {
+        id: job_id(job),
+        fields,
+    })
+}
+
+// Jobs without an id are known by their whole JSON string.
+pub fn job_id(job: &str) -> String {
+    serde_json::from_str::<serde_json::Value>(job)
+        .ok()
+        .and_then(|object| {
+            object
+                .get("id")
+                .map(|id| id.as_str().map(|x| x.to_string()).unwrap_or(id.to_string()))
+        })
+        .unwrap_or(job.to_string())
+}
+
+pub fn job_status(statuses: &[String], id: &str) -> Option<String> {
+    statuses
+        .iter()
+        .find_map(|status| status.strip_prefix(&format!("{id}:")))
+        .map(|status| status.to_string())
+}
+
+// Statuses that don't change anymore, the status comes after the last ':' since ids may
+// contain one as well.
+fn is_final(status: &str) -> bool {
+    matches!(
+        status.rsplit_once(':').map(|(_, status)| status),
+        Some("succeeded") | Some("failed") | Some("rejected")
+    )
+}
+
+fn set_job_status(statuses: &mut Vec<String>, id: &str, status: &str) {
+    let entry = format!("{id}:{status}");
+    match statuses
+        .iter()
+        .position(|status| status.starts_with(&format!("{id}:")))
+    {
+        Some(position) => statuses[position] = entry,
+        None => statuses.push(entry),
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_queue_emulator() {
+    async fn wait_for_status(backend: &Arc<dyn StateBackend>, id: &str, status: &str) -> State {
+        let waiting = async {
+            loop {
+                crate::emulated_sleep(Duration::from_millis(EMULATOR_TICK_INTERVAL)).await;
+                let state = backend.get_full_state().await.unwrap();
+                let statuses = crate::get_string_array(
+                    &state,
+                    "gantry_queue_status_estimated",
+                    "test_queue_emulator",
+                );
+                if job_status(&statuses, id).as_deref() == Some(status) {
+                    return state;
+                }
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(60), waiting)
+            .await
+            .unwrap_or_else(|_| panic!("Job {id} never got {status}."))
+    }
+
+    let log_target = "test_queue_emulator";
+    let state = crate::model::state::state();
+    let backend: Arc<dyn StateBackend> = Arc::new(crate::InMemoryBackend::new(&state));
+    let gantry = crate::spawn_emulator(
+        &crate::ScenarioEmulator::Gantry("gantry".to_string()),
+        &backend,
+    );
+    let queue = crate::spawn_emulator(
+        &crate::ScenarioEmulator::Queue("gantry".to_string()),
+        &backend,
+    );
+
+    // Three jobs, but only room for two
+    let jobs = vec![
+        r#"{"id": "job_1", "command_command": "move", "position_command": "a", "speed_command": 0.5}"#,
+        r#"{"id": "job_2", "command_command": "calibrate"}"#,
+        r#"{"id": "job_3", "command_command": "move", "position_command": "b", "speed_command": 0.5}"#,
+    ];
+    let state = backend.get_full_state().await.unwrap();
+    let new_state = state
+        .update(
+            "gantry_emulate_execution_time",
+            crate::EMULATE_EXACT_EXECUTION_TIME.to_spvalue(),
+        )
+        .update("gantry_emulated_execution_time", 300.to_spvalue())
+        .update(
+            "gantry_emulate_failure_rate",
+            crate::DONT_EMULATE_FAILURE.to_spvalue(),
+        )
+        .update("gantry_emulate_queue", true.to_spvalue())
+        .update("gantry_emulated_queue_capacity", 2.to_spvalue())
+        .update("gantry_queue_command", jobs.to_spvalue());
+    backend
+        .set_state(&state.get_diff_partial_state(&new_state))
+        .await;
+
+    let state = wait_for_status(&backend, "job_2", "succeeded").await;
+    let statuses = crate::get_string_array(&state, "gantry_queue_status_estimated", log_target);
+    assert_eq!(job_status(&statuses, "job_1").as_deref(), Some("succeeded"));
+    assert_eq!(job_status(&statuses, "job_3").as_deref(), Some("rejected"));
+    assert_eq!(
+        state.get_string_or_default_to_unknown("gantry_position_estimated", log_target),
+        "a"
+    );
+
+    // A new list of the same length is run as well, and the old statuses are dropped
+    let jobs = vec![
+        r#"{"id": "job_4", "command_command": "move", "position_command": "b", "speed_command": 0.5}"#,
+        r#"{"id": "job_5", "command_command": "calibrate"}"#,
+        r#"{"id": "job_6", "command_command": "move", "position_command": "a", "speed_command": 0.5}"#,
+    ];
+    let new_state = state.update("gantry_queue_command", jobs.to_spvalue());
+    backend
+        .set_state(&state.get_diff_partial_state(&new_state))
+        .await;
+
+    let state = wait_for_status(&backend, "job_5", "succeeded").await;
+    let statuses = crate::get_string_array(&state, "gantry_queue_status_estimated", log_target);
+    assert_eq!(job_status(&statuses, "job_4").as_deref(), Some("succeeded"));
+    assert_eq!(job_status(&statuses, "job_6").as_deref(), Some("rejected"));
+    assert_eq!(job_status(&statuses, "job_1"), None);
+    assert_eq!(
+        state.get_string_or_default_to_unknown("gantry_position_estimated", log_target),
+        "b"
+    );
+
+    // Clearing the list forgets the jobs that are done
+    let new_state = state.update("gantry_queue_command", Vec::<SPValue>::new().to_spvalue());
+    backend
+        .set_state(&state.get_diff_partial_state(&new_state))
+        .await;
+    crate::emulated_sleep(Duration::from_millis(2 * EMULATOR_TICK_INTERVAL)).await;
+    let state = backend.get_full_state().await.unwrap();
+    let statuses = crate::get_string_array(&state, "gantry_queue_status_estimated", log_target);
+    assert!(statuses.is_empty(), "{statuses:?}");
+    assert_eq!(
+        state.get_int_or_default_to_zero("gantry_queue_consumed_estimated", log_target),
+        0
+    );
+
+    gantry.abort();
+    queue.abort();
+}
+
+#[test]
+fn test_is_final() {
+    assert!(is_final("job_1:succeeded"));
+    assert!(is_final("plc:job_1:rejected"));
+    assert!(!is_final("job_1:executing"));
+    assert!(!is_final("job:failed:queued"));
+}
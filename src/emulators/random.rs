@@ -0,0 +1,83 @@
+use rand::rngs::{StdRng, ThreadRng};
+use rand::{RngCore, SeedableRng};
+use std::sync::{Arc, Mutex};
+
+// The randomness of the emulators: random failures, execution times, failure causes,
+// torques and operator reactions. Outside of with_seed it comes from rand::thread_rng,
+// inside it from a seeded generator, so a seeded run draws the same again. Tasks spawned
+// with spawn_emulated share the generator of their caller, so the draws are reproducible
+// as long as the tasks run in the same order, e.g. in paused time.
+tokio::task_local! {
+    static SEEDED_RNG: Arc<Mutex<StdRng>>;
+}
+
+pub enum EmulatorRng {
+    Thread(ThreadRng),
+    Seeded(Arc<Mutex<StdRng>>),
+}
+
+// The generator of the current task, use it instead of rand::thread_rng in the emulators.
+pub fn emulator_rng() -> EmulatorRng {
+    match SEEDED_RNG.try_with(|rng| rng.clone()) {
+        Ok(rng) => EmulatorRng::Seeded(rng),
+        Err(_) => EmulatorRng::Thread(rand::thread_rng()),
+    }
+}
+
+pub(crate) fn seeded_rng() -> Option<Arc<Mutex<StdRng>>> {
+    SEEDED_RNG.try_with(|rng| rng.clone()).ok()
+}
+
+pub(crate) async fn with_rng<F: Future>(rng: Arc<Mutex<StdRng>>, future: F) -> F::Output {
+    SEEDED_RNG.scope(rng, future).await
+}
+
+// Runs the future, and the tasks it spawns with spawn_emulated, with a seeded generator.
+pub async fn with_seed<F: Future>(seed: u64, future: F) -> F::Output {
+    with_rng(Arc::new(Mutex::new(StdRng::seed_from_u64(seed))), future).await
+}
+
+impl RngCore for EmulatorRng {
+    fn next_u32(&mut self) -> u32 {
+        match self {
+            EmulatorRng::Thread(rng) => rng.next_u32(),
+            EmulatorRng::Seeded(rng) => rng.lock().unwrap().next_u32(),
+        }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        match self {
+            EmulatorRng::Thread(rng) => rng.next_u64(),
+            EmulatorRng::Seeded(rng) => rng.lock().unwrap().next_u64(),
+        }
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        match self {
+            EmulatorRng::Thread(rng) => rng.fill_bytes(dest),
+            EmulatorRng::Seeded(rng) => rng.lock().unwrap().fill_bytes(dest),
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        match self {
+            EmulatorRng::Thread(rng) => rng.try_fill_bytes(dest),
+            EmulatorRng::Seeded(rng) => rng.lock().unwrap().try_fill_bytes(dest),
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_seeded_emulator_rng() {
+    use rand::Rng;
+
+    let draw = || async {
+        let first: Vec<u64> = (0..5).map(|_| emulator_rng().gen_range(0..=100)).collect();
+        let spawned = crate::spawn_emulated(async { emulator_rng().gen_range(0..=100u64) })
+            .await
+            .unwrap();
+        (first, spawned)
+    };
+    assert_eq!(with_seed(42, draw()).await, with_seed(42, draw()).await);
+    assert_ne!(with_seed(42, draw()).await, with_seed(43, draw()).await);
+}
@@ -0,0 +1,205 @@
+use micro_sp::{SPValue, State, StringOrUnknown, ToSPValue};
+
+// Who holds a shared resource, and who is waiting for it. The owner is "unknown" while
+// the resource is free, and then anyone can command it, just like before reservations.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reservation {
+    pub owner: String,
+    pub queue: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ReservationResponse {
+    pub success: bool,
+    pub failure_cause: String,
+    pub info: String,
+}
+
+impl Reservation {
+    pub fn from_state(name: &str, state: &State, log_target: &str) -> Reservation {
+        Reservation {
+            owner: state
+                .get_string_or_default_to_unknown(&format!("{name}_owner_estimated"), log_target),
+            queue: state
+                .get_array_or_default_to_empty(&format!("{name}_owner_queue_estimated"), log_target)
+                .iter()
+                .filter(|val| val.is_string())
+                .map(|y| y.to_string())
+                .collect(),
+        }
+    }
+
+    pub fn is_free(&self) -> bool {
+        self.owner == "unknown" || self.owner.is_empty()
+    }
+
+    pub fn owner_to_spvalue(&self) -> SPValue {
+        if self.is_free() {
+            SPValue::String(StringOrUnknown::UNKNOWN)
+        } else {
+            self.owner.to_spvalue()
+        }
+    }
+
+    pub fn queue_to_spvalue(&self) -> SPValue {
+        self.queue
+            .iter()
+            .map(|owner| owner.to_spvalue())
+            .collect::<Vec<SPValue>>()
+            .to_spvalue()
+    }
+}
+
+// Arbitrate a request before it reaches the resource. The "reserve" and "release" commands
+// are handled here entirely. Any other command gets None if the caller may go ahead, or a
+// rejection if someone else holds the reservation.
+//
+// A "reserve" on a taken resource is rejected, but the caller is put in the queue, and the
+// reservation is handed over to the head of the queue on "release".
+pub fn arbitrate_request(
+    command: &str,
+    caller: &str,
+    reservation: &mut Reservation,
+    log_target: &str,
+) -> Option<ReservationResponse> {
+    let response = match command {
+        "reserve" => {
+            log::info!(target: log_target, "Got request to reserve for {}.", caller);
+            if caller == "unknown" || caller.is_empty() {
+                rejected(
+                    "missing_owner",
+                    "Failed to reserve, no owner given.".to_string(),
+                )
+            } else if reservation.is_free() || reservation.owner == caller {
+                reservation.owner = caller.to_string();
+                reservation.queue.retain(|owner| owner != caller);
+                succeeded(format!("Succeeded to reserve for {}.", caller))
+            } else {
+                if !reservation.queue.iter().any(|owner| owner == caller) {
+                    reservation.queue.push(caller.to_string());
+                }
+                rejected(
+                    "reserved",
+                    format!(
+                        "Failed to reserve for {}, reserved by {}. Queued at {}.",
+                        caller,
+                        reservation.owner,
+                        reservation.queue.len()
+                    ),
+                )
+            }
+        }
+        "release" => {
+            log::info!(target: log_target, "Got request to release for {}.", caller);
+            if reservation.is_free() {
+                succeeded("Succeeded to release, resource was not reserved.".to_string())
+            } else if reservation.owner == caller {
+                reservation.owner = if reservation.queue.is_empty() {
+                    "unknown".to_string()
+                } else {
+                    reservation.queue.remove(0)
+                };
+                match reservation.is_free() {
+                    true => succeeded(format!("Succeeded to release for {}.", caller)),
+                    false => succeeded(format!(
+                        "Succeeded to release for {}, handed over to {}.",
+                        caller, reservation.owner
+                    )),
+                }
+            } else if reservation.queue.iter().any(|owner| owner == caller) {
+                // Giving up the place in the queue
+                reservation.queue.retain(|owner| owner != caller);
+                succeeded(format!("Succeeded to dequeue {}.", caller))
+            } else {
+                rejected(
+                    "not_owner",
+                    format!(
+                        "Failed to release for {}, reserved by {}.",
+                        caller, reservation.owner
+                    ),
+                )
+            }
+        }
+        _ => {
+            if reservation.is_free() || reservation.owner == caller {
+                return None;
+            }
+            rejected(
+                "not_owner",
+                format!(
+                    "Failed to {} for {}, reserved by {}.",
+                    command, caller, reservation.owner
+                ),
+            )
+        }
+    };
+
+    if response.success {
+        log::info!(target: log_target, "{}", response.info);
+    } else {
+        log::error!(target: log_target, "{}", response.info);
+    }
+
+    Some(response)
+}
+
+fn succeeded(info: String) -> ReservationResponse {
+    ReservationResponse {
+        success: true,
+        failure_cause: "".to_string(),
+        info,
+    }
+}
+
+fn rejected(cause: &str, info: String) -> ReservationResponse {
+    ReservationResponse {
+        success: false,
+        failure_cause: cause.to_string(),
+        info,
+    }
+}
+
+#[test]
+fn test_arbitrate_request() {
+    let log_target = "test_arbitrate_request";
+    let mut reservation = Reservation {
+        owner: "unknown".to_string(),
+        queue: vec![],
+    };
+    // Free for anyone, until someone reserves it
+    assert!(arbitrate_request("move", "sp_1", &mut reservation, log_target).is_none());
+    assert!(
+        arbitrate_request("reserve", "sp_1", &mut reservation, log_target)
+            .unwrap()
+            .success
+    );
+
+    // The others are rejected and queue up, once each
+    let response = arbitrate_request("reserve", "sp_2", &mut reservation, log_target).unwrap();
+    assert!(!response.success);
+    assert_eq!(response.failure_cause, "reserved");
+    arbitrate_request("reserve", "sp_3", &mut reservation, log_target);
+    arbitrate_request("reserve", "sp_2", &mut reservation, log_target);
+    assert_eq!(reservation.queue, vec!["sp_2", "sp_3"]);
+    let response = arbitrate_request("move", "sp_2", &mut reservation, log_target).unwrap();
+    assert_eq!(response.failure_cause, "not_owner");
+    let response = arbitrate_request("release", "sp_3", &mut reservation, log_target).unwrap();
+    assert!(response.success);
+    assert_eq!(reservation.queue, vec!["sp_2"]);
+
+    // Handed over to the head of the queue on release
+    assert!(arbitrate_request("move", "sp_1", &mut reservation, log_target).is_none());
+    assert!(
+        arbitrate_request("release", "sp_1", &mut reservation, log_target)
+            .unwrap()
+            .success
+    );
+    assert_eq!(reservation.owner, "sp_2");
+    assert!(reservation.queue.is_empty());
+    assert!(
+        arbitrate_request("release", "sp_2", &mut reservation, log_target)
+            .unwrap()
+            .success
+    );
+    assert!(reservation.is_free());
+}
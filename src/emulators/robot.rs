@@ -1,24 +1,60 @@
-use micro_sp::{ConnectionManager, ServiceRequestState, StateManager, ToSPValue};
+use micro_sp::{
+    MapOrUnknown, SPRotation, SPTransform, SPTransformStamped, SPTranslation, SPValue,
+    ServiceRequestState, State, StringOrUnknown, ToSPValue, TransformOrUnknown,
+};
+use ordered_float::OrderedFloat;
 use rand::Rng;
 use rand::prelude::SliceRandom;
+use serde_json::json;
+use std::time::SystemTime;
 use std::{sync::Arc, time::Duration};
-use tokio::time::interval;
 
-use crate::EMULATOR_TICK_INTERVAL;
+use crate::{
+    Degradation, Downtime, EMULATOR_TICK_INTERVAL, EmulatedInstant, MAX_SPEED, MotionEmulation,
+    NOMINAL_SPEED, Reservation, StateBackend, arbitrate_degraded_mode, arbitrate_request,
+    emulate_downtime, emulate_motion_failure, emulate_progress, emulated_interval,
+    get_string_array, is_known_position, motion_time_factor, publish_measurement,
+    reject_in_maintenance, resource_capabilities, resource_online_change,
+};
+
+// The positions a robot can move to, unless "emulated_positions" says otherwise.
+pub static ROBOT_POSITIONS: [&str; 12] = [
+    "home",
+    "a",
+    "b",
+    "c",
+    "d",
+    "e",
+    "f",
+    "g",
+    "pipe_blue_box",
+    "plate_pipe_box",
+    "gripper_tool_rack",
+    "suction_tool_rack",
+];
 
 #[derive(Debug, Clone)]
 pub struct RobotRequest {
     pub command: String,
     pub speed: f64,
     pub position: String,
+    pub positions: Vec<String>,
+    pub waypoints: Vec<String>,
+    pub from: String,
+    pub to: String,
+    pub payload: f64,
+    pub emulated_max_payload: f64,
+    pub emulated_motion_failure_rate: i64,
     pub emulate_execution_time: i64,
     pub emulated_execution_time: i64,
     pub emulate_failure_rate: i64,
     pub emulated_failure_rate: i64,
     pub emulate_failure_cause: i64,
     pub emulated_failure_cause: Vec<String>,
+    pub execution_time_factor: f64,
     pub emulate_mounted_tool: bool,
     pub emulated_mounted_tool: String,
+    pub mounted_tool: String,
 }
 
 #[derive(Debug, Clone)]
@@ -27,68 +63,118 @@ pub struct RobotResponse {
     pub failure_cause: String,
     pub info: String,
     pub checked_mounted_tool: String,
+    pub failed_step: String,
 }
 
-pub async fn robot_emulator(
-    connection_manager: &Arc<ConnectionManager>,
+pub async fn robot_emulator<B: StateBackend + ?Sized>(
+    name: &str,
+    connection_manager: &Arc<B>,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let mut interval = interval(Duration::from_millis(EMULATOR_TICK_INTERVAL));
-    let log_target = "robot_emulator";
+    let mut interval = emulated_interval(Duration::from_millis(EMULATOR_TICK_INTERVAL));
+    let log_target = format!("{name}_emulator");
     log::info!(target: &log_target, "Online.");
+    let started = EmulatedInstant::now();
+    // What the previous tick made of the downtime windows, see resource_online_change.
+    let mut previous_downtime: Option<Downtime> = None;
 
     let keys: Vec<String> = vec![
-        "robot_request_trigger",
-        "robot_request_state",
-        "robot_total_fail_counter",
-        "robot_subsequent_fail_counter",
-        "robot_command_command",
-        "robot_speed_command",
-        "robot_position_command",
-        "robot_position_estimated",
-        "robot_mounted_one_time_measured",
-        "robot_emulate_execution_time",
-        "robot_emulated_execution_time",
-        "robot_emulate_failure_rate",
-        "robot_emulated_failure_rate",
-        "robot_emulate_failure_cause",
-        "robot_emulated_failure_cause",
-        "robot_emulate_mounted_tool",
-        "robot_emulated_mounted_tool",
+        "request_trigger",
+        "request_state",
+        "total_fail_counter",
+        "subsequent_fail_counter",
+        "owner_command",
+        "owner_estimated",
+        "owner_queue_estimated",
+        "capabilities",
+        "command_command",
+        "speed_command",
+        "payload_estimated",
+        "emulated_max_payload",
+        "emulated_motion_failure_rate",
+        "position_command",
+        "position_estimated",
+        "emulated_positions",
+        "waypoints_command",
+        "from_command",
+        "to_command",
+        "failed_step_estimated",
+        "emulate_execution_time",
+        "emulated_execution_time",
+        "emulate_failure_rate",
+        "emulated_failure_rate",
+        "emulate_failure_cause",
+        "emulated_failure_cause",
+        "resource_online",
+        "downtime_estimated",
+        "emulate_downtime",
+        "emulated_downtime_windows",
+        "mode_estimated",
+        "emulated_degrading_causes",
+        "emulated_degraded_time_factor",
+        "emulated_degraded_refused_commands",
+        "emulate_mounted_tool",
+        "emulated_mounted_tool",
+        "mounted_tool",
+        "tcp_frame",
+        "tool_frame",
     ]
     .iter()
-    .map(|k| k.to_string())
+    .map(|k| format!("{name}_{k}"))
     .collect();
 
     loop {
         interval.tick().await;
-        if let Err(_) = connection_manager.check_redis_health(&log_target).await {
+        if !connection_manager.check_health(&log_target).await {
             continue;
         }
-        let mut con = connection_manager.get_connection().await;
-        let state = match StateManager::get_state_for_keys(&mut con, &keys, &log_target).await {
+        let state = match connection_manager
+            .get_state_for_keys(&keys, &log_target)
+            .await
+        {
             Some(s) => s,
             None => continue,
         };
 
         let mut request_trigger =
-            state.get_bool_or_default_to_false("robot_request_trigger", &log_target);
+            state.get_bool_or_default_to_false(&format!("{name}_request_trigger"), &log_target);
         let mut request_state =
-            state.get_string_or_default_to_unknown("robot_request_state", &log_target);
+            state.get_string_or_default_to_unknown(&format!("{name}_request_state"), &log_target);
 
         let mut total_fail_counter =
-            state.get_int_or_default_to_zero("robot_total_fail_counter", &log_target);
-        let mut subsequent_fail_counter =
-            state.get_int_or_default_to_zero("robot_subsequent_fail_counter", &log_target);
-        let mut robot_position_estimated =
-            state.get_string_or_default_to_unknown("robot_position_estimated", &log_target);
-        let mut robot_mounted_one_time_measured =
-            state.get_string_or_default_to_unknown("robot_mounted_one_time_measured", &log_target);
-
-        if request_trigger {
+            state.get_int_or_default_to_zero(&format!("{name}_total_fail_counter"), &log_target);
+        let mut subsequent_fail_counter = state
+            .get_int_or_default_to_zero(&format!("{name}_subsequent_fail_counter"), &log_target);
+        let mut robot_position_estimated = state
+            .get_string_or_default_to_unknown(&format!("{name}_position_estimated"), &log_target);
+        let mut robot_failed_step_estimated = state.get_string_or_default_to_unknown(
+            &format!("{name}_failed_step_estimated"),
+            &log_target,
+        );
+        let mut robot_mounted_tool =
+            state.get_string_or_default_to_unknown(&format!("{name}_mounted_tool"), &log_target);
+
+        // The frame tree is world -> base -> tcp -> tool, only tcp and tool change
+        let mut robot_tcp_frame: Option<SPValue> = None;
+        let mut robot_tool_frame: Option<SPValue> = None;
+
+        let downtime = emulate_downtime(
+            name,
+            &state,
+            started.elapsed().as_millis() as u64,
+            &log_target,
+        );
+
+        let mut reservation = Reservation::from_state(name, &state, &log_target);
+        let mut degradation = Degradation::from_state(name, &state, &log_target);
+
+        // An offline resource leaves the request pending until it is back
+        if request_trigger && downtime != Downtime::Offline {
             request_trigger = false;
             if request_state == ServiceRequestState::Initial.to_string() {
-                let emulated_failure_cause_sp_value = state
-                    .get_array_or_default_to_empty("robot_emulated_failure_cause", &log_target);
+                let emulated_failure_cause_sp_value = state.get_array_or_default_to_empty(
+                    &format!("{name}_emulated_failure_cause"),
+                    &log_target,
+                );
 
                 let emulated_failure_cause: Vec<String> = emulated_failure_cause_sp_value
                     .iter()
@@ -97,40 +183,172 @@ pub async fn robot_emulator(
                     .collect();
 
                 let request = RobotRequest {
-                    command: state
-                        .get_string_or_default_to_unknown("robot_command_command", &log_target),
-                    speed: state.get_float_or_default_to_zero("robot_speed_command", &log_target),
-                    position: state
-                        .get_string_or_default_to_unknown("robot_position_command", &log_target),
-                    emulate_execution_time: state
-                        .get_int_or_default_to_zero("robot_emulate_execution_time", &log_target),
-                    emulated_execution_time: state
-                        .get_int_or_default_to_zero("robot_emulated_execution_time", &log_target),
-                    emulate_failure_rate: state
-                        .get_int_or_default_to_zero("robot_emulate_failure_rate", &log_target),
-                    emulated_failure_rate: state
-                        .get_int_or_default_to_zero("robot_emulated_failure_rate", &log_target),
-                    emulate_mounted_tool: state
-                        .get_bool_or_default_to_false("robot_emulate_mounted_tool", &log_target),
+                    command: state.get_string_or_default_to_unknown(
+                        &format!("{name}_command_command"),
+                        &log_target,
+                    ),
+                    speed: state.get_float_or_default_to_zero(
+                        &format!("{name}_speed_command"),
+                        &log_target,
+                    ),
+                    position: state.get_string_or_default_to_unknown(
+                        &format!("{name}_position_command"),
+                        &log_target,
+                    ),
+                    positions: get_string_array(
+                        &state,
+                        &format!("{name}_emulated_positions"),
+                        &log_target,
+                    ),
+                    waypoints: state
+                        .get_array_or_default_to_empty(
+                            &format!("{name}_waypoints_command"),
+                            &log_target,
+                        )
+                        .iter()
+                        .filter(|val| val.is_string())
+                        .map(|y| y.to_string())
+                        .collect(),
+                    from: state.get_string_or_default_to_unknown(
+                        &format!("{name}_from_command"),
+                        &log_target,
+                    ),
+                    to: state.get_string_or_default_to_unknown(
+                        &format!("{name}_to_command"),
+                        &log_target,
+                    ),
+                    payload: state.get_float_or_default_to_zero(
+                        &format!("{name}_payload_estimated"),
+                        &log_target,
+                    ),
+                    emulated_max_payload: state.get_float_or_default_to_zero(
+                        &format!("{name}_emulated_max_payload"),
+                        &log_target,
+                    ),
+                    emulated_motion_failure_rate: state.get_int_or_default_to_zero(
+                        &format!("{name}_emulated_motion_failure_rate"),
+                        &log_target,
+                    ),
+                    emulate_execution_time: state.get_int_or_default_to_zero(
+                        &format!("{name}_emulate_execution_time"),
+                        &log_target,
+                    ),
+                    emulated_execution_time: state.get_int_or_default_to_zero(
+                        &format!("{name}_emulated_execution_time"),
+                        &log_target,
+                    ),
+                    emulate_failure_rate: state.get_int_or_default_to_zero(
+                        &format!("{name}_emulate_failure_rate"),
+                        &log_target,
+                    ),
+                    emulated_failure_rate: state.get_int_or_default_to_zero(
+                        &format!("{name}_emulated_failure_rate"),
+                        &log_target,
+                    ),
+                    emulate_mounted_tool: state.get_bool_or_default_to_false(
+                        &format!("{name}_emulate_mounted_tool"),
+                        &log_target,
+                    ),
                     emulated_mounted_tool: state.get_string_or_default_to_unknown(
-                        "robot_emulated_mounted_tool",
+                        &format!("{name}_emulated_mounted_tool"),
+                        &log_target,
+                    ),
+                    mounted_tool: robot_mounted_tool.clone(),
+                    emulate_failure_cause: state.get_int_or_default_to_zero(
+                        &format!("{name}_emulate_failure_cause"),
                         &log_target,
                     ),
-                    emulate_failure_cause: state
-                        .get_int_or_default_to_zero("robot_emulate_failure_cause", &log_target),
                     emulated_failure_cause,
+                    execution_time_factor: degradation.execution_time_factor(),
                 };
 
-                let response = emulate_robot_operation(&request).await;
+                let caller = state.get_string_or_default_to_unknown(
+                    &format!("{name}_owner_command"),
+                    &log_target,
+                );
+                let response = match reject_in_maintenance(&downtime, &request.command, &log_target)
+                    .or_else(|| {
+                        arbitrate_request(&request.command, &caller, &mut reservation, &log_target)
+                    })
+                    .or_else(|| {
+                        arbitrate_degraded_mode(&degradation, &request.command, &log_target)
+                    }) {
+                    Some(arbitration) => RobotResponse {
+                        success: arbitration.success,
+                        failure_cause: arbitration.failure_cause,
+                        info: arbitration.info,
+                        checked_mounted_tool: "".to_string(),
+                        failed_step: "".to_string(),
+                    },
+                    None => match request.command.as_str() {
+                        "move_through" | "pick_and_place" => {
+                            emulate_robot_composite_operation(
+                                name,
+                                connection_manager,
+                                &request,
+                                &log_target,
+                            )
+                            .await
+                        }
+                        _ => {
+                            emulate_robot_operation(name, connection_manager, &request, &log_target)
+                                .await
+                        }
+                    },
+                };
+                degradation.update(
+                    &request.command,
+                    response.success,
+                    &response.failure_cause,
+                    &log_target,
+                );
+
+                robot_failed_step_estimated = response.failed_step.clone();
 
                 request_state = if response.success {
                     subsequent_fail_counter = 0;
                     match request.command.as_str() {
-                        "move" => robot_position_estimated = request.position,
+                        "move" => {
+                            robot_tcp_frame = Some(robot_frame(
+                                &format!("{name}_base"),
+                                &format!("{name}_tcp"),
+                                robot_position_to_translation(&request.position),
+                                true,
+                            ));
+                            robot_position_estimated = request.position
+                        }
                         "check_mounted_tool" => {
-                            robot_mounted_one_time_measured = response.checked_mounted_tool
+                            publish_measurement(
+                                connection_manager,
+                                &format!("{name}_mounted_one_time_measured"),
+                                response.checked_mounted_tool.to_spvalue(),
+                                &log_target,
+                            )
+                            .await
+                        }
+                        // Tools are mounted and unmounted at their racks, i.e. "gripper_tool_rack"
+                        "mount" => {
+                            robot_mounted_tool = robot_position_estimated
+                                .strip_suffix("_rack")
+                                .unwrap_or("tool")
+                                .to_string();
+                            robot_tool_frame = Some(robot_frame(
+                                &format!("{name}_tcp"),
+                                &format!("{name}_tool"),
+                                (0.0, 0.0, 0.15),
+                                true,
+                            ));
+                        }
+                        "unmount" => {
+                            robot_mounted_tool = "none".to_string();
+                            robot_tool_frame = Some(robot_frame(
+                                &format!("{name}_tcp"),
+                                &format!("{name}_tool"),
+                                (0.0, 0.0, 0.15),
+                                false,
+                            ));
                         }
-                        "pick" | "place" | "mount" | "unmount" => (),
+                        "pick" | "place" => (),
                         _ => (),
                     }
                     ServiceRequestState::Succeeded.to_string()
@@ -142,76 +360,260 @@ pub async fn robot_emulator(
             }
         }
         let new_state = state
-            .update("robot_request_trigger", request_trigger.to_spvalue())
-            .update("robot_request_state", request_state.to_spvalue())
-            .update("robot_total_fail_counter", total_fail_counter.to_spvalue())
             .update(
-                "robot_subsequent_fail_counter",
+                &format!("{name}_request_trigger"),
+                request_trigger.to_spvalue(),
+            )
+            .update(&format!("{name}_request_state"), request_state.to_spvalue())
+            .update(
+                &format!("{name}_total_fail_counter"),
+                total_fail_counter.to_spvalue(),
+            )
+            .update(
+                &format!("{name}_subsequent_fail_counter"),
                 subsequent_fail_counter.to_spvalue(),
             )
             .update(
-                "robot_position_estimated",
+                &format!("{name}_owner_estimated"),
+                reservation.owner_to_spvalue(),
+            )
+            .update(
+                &format!("{name}_owner_queue_estimated"),
+                reservation.queue_to_spvalue(),
+            )
+            .update(
+                &format!("{name}_downtime_estimated"),
+                (downtime != Downtime::Up).to_spvalue(),
+            )
+            .update(
+                &format!("{name}_mode_estimated"),
+                degradation.mode_to_spvalue(),
+            )
+            .update(
+                &format!("{name}_capabilities"),
+                robot_capabilities(name, &state, &log_target),
+            )
+            .update(
+                &format!("{name}_position_estimated"),
                 robot_position_estimated.to_spvalue(),
             )
             .update(
-                "robot_mounted_one_time_measured",
-                robot_mounted_one_time_measured.to_spvalue(),
+                &format!("{name}_failed_step_estimated"),
+                match robot_failed_step_estimated.as_str() {
+                    "" | "unknown" => SPValue::String(StringOrUnknown::UNKNOWN),
+                    failed_step => failed_step.to_spvalue(),
+                },
+            )
+            .update(
+                &format!("{name}_mounted_tool"),
+                match robot_mounted_tool.as_str() {
+                    "unknown" => SPValue::String(StringOrUnknown::UNKNOWN),
+                    mounted_tool => mounted_tool.to_spvalue(),
+                },
             );
+        let new_state = match robot_tcp_frame {
+            Some(frame) => new_state.update(&format!("{name}_tcp_frame"), frame),
+            None => new_state,
+        };
+        let new_state = match robot_tool_frame {
+            Some(frame) => new_state.update(&format!("{name}_tool_frame"), frame),
+            None => new_state,
+        };
+
+        let new_state = match resource_online_change(previous_downtime.as_ref(), &downtime) {
+            Some(online) => {
+                new_state.update(&format!("{name}_resource_online"), online.to_spvalue())
+            }
+            None => new_state,
+        };
+        previous_downtime = Some(downtime);
 
         let modified_state = state.get_diff_partial_state(&new_state);
-        StateManager::set_state(&mut con, &modified_state).await;
+        connection_manager.set_state(&modified_state).await;
     }
 }
 
-pub async fn emulate_robot_operation(request: &RobotRequest) -> RobotResponse {
+// Where the tcp ends up relative to the robot base for each named position.
+// Positions that are not listed get a made up, but stable, pose.
+pub fn robot_position_to_translation(position: &str) -> (f64, f64, f64) {
+    match position {
+        "home" => (0.0, 0.0, 0.8),
+        "a" => (0.4, -0.3, 0.3),
+        "b" => (0.4, 0.3, 0.3),
+        "c" => (0.6, -0.3, 0.3),
+        "d" => (0.6, 0.3, 0.3),
+        "pipe_blue_box" => (0.5, -0.6, 0.2),
+        "plate_pipe_box" => (0.5, 0.6, 0.2),
+        "gripper_tool_rack" => (-0.4, -0.5, 0.25),
+        "suction_tool_rack" => (-0.4, 0.5, 0.25),
+        _ => {
+            let hash = position
+                .bytes()
+                .fold(0u64, |acc, b| acc.wrapping_mul(31).wrapping_add(b as u64));
+            (
+                (hash % 100) as f64 / 100.0,
+                ((hash / 100) % 100) as f64 / 100.0,
+                ((hash / 10000) % 100) as f64 / 100.0,
+            )
+        }
+    }
+}
+
+// An identity-rotation transform between two frames of the robot frame tree.
+pub fn robot_frame(
+    parent: &str,
+    child: &str,
+    translation: (f64, f64, f64),
+    active: bool,
+) -> SPValue {
+    SPValue::Transform(TransformOrUnknown::Transform(SPTransformStamped {
+        active_transform: active,
+        enable_transform: true,
+        time_stamp: SystemTime::now(),
+        parent_frame_id: parent.to_string(),
+        child_frame_id: child.to_string(),
+        transform: SPTransform {
+            translation: SPTranslation {
+                x: OrderedFloat::from(translation.0),
+                y: OrderedFloat::from(translation.1),
+                z: OrderedFloat::from(translation.2),
+            },
+            rotation: SPRotation {
+                x: OrderedFloat::from(0.0),
+                y: OrderedFloat::from(0.0),
+                z: OrderedFloat::from(0.0),
+                w: OrderedFloat::from(1.0),
+            },
+        },
+        metadata: MapOrUnknown::UNKNOWN,
+    }))
+}
+
+// Published in "{name}_capabilities", see capabilities.rs.
+// Tools are mounted at their racks, so the tools are the racks in the position catalog.
+pub fn robot_capabilities(name: &str, state: &State, log_target: &str) -> SPValue {
+    let positions = get_string_array(state, &format!("{name}_emulated_positions"), log_target);
+    let tools: Vec<&str> = positions
+        .iter()
+        .filter_map(|position| position.strip_suffix("_rack"))
+        .collect();
+    let speed = json!({"type": "float", "min": 0.0, "max": MAX_SPEED, "nominal": NOMINAL_SPEED});
+    resource_capabilities(
+        vec![
+            (
+                "move",
+                json!({"position_command": positions, "speed_command": speed}),
+            ),
+            ("pick", json!({})),
+            ("place", json!({})),
+            ("mount", json!({})),
+            ("unmount", json!({})),
+            ("check_mounted_tool", json!({})),
+            (
+                "move_through",
+                json!({"waypoints_command": positions, "speed_command": speed}),
+            ),
+            (
+                "pick_and_place",
+                json!({"from_command": positions, "to_command": positions, "speed_command": speed}),
+            ),
+        ],
+        &[
+            "unknown_position",
+            "invalid_speed",
+            "high_speed",
+            "heavy_payload",
+            "no_steps",
+        ],
+        &get_string_array(state, &format!("{name}_emulated_failure_cause"), log_target),
+        vec![("emulate_mounted_tool", json!([false, true]))],
+        vec![("positions", json!(positions)), ("tools", json!(tools))],
+    )
+}
+
+pub async fn emulate_robot_operation<B: StateBackend + ?Sized>(
+    name: &str,
+    connection_manager: &Arc<B>,
+    request: &RobotRequest,
+    log_target: &str,
+) -> RobotResponse {
     let mut fail = match request.emulate_failure_rate {
         0 => false,
         1 => true,
-        2 => rand::thread_rng().gen_range(0..=100) <= request.emulated_failure_rate as u64,
+        2 => crate::emulator_rng().gen_range(0..=100) <= request.emulated_failure_rate as u64,
         _ => false,
     };
 
     let mut checked_mounted_tool = "UNKNOWN".to_string();
     match request.command.as_str() {
         "move" => {
-            log::info!(target: "robot_emulator", "Got request to move to {}.", request.position)
+            log::info!(target: log_target, "Got request to move to {}.", request.position)
         }
-        "pick" => log::info!(target: "robot_emulator", "Got request to pick."),
-        "place" => log::info!(target: "robot_emulator", "Got request to place."),
-        "mount" => log::info!(target: "robot_emulator", "Got request to mount."),
-        "unmount" => log::info!(target: "robot_emulator", "Got request to unmount."),
+        "pick" => log::info!(target: log_target, "Got request to pick."),
+        "place" => log::info!(target: log_target, "Got request to place."),
+        "mount" => log::info!(target: log_target, "Got request to mount."),
+        "unmount" => log::info!(target: log_target, "Got request to unmount."),
         "check_mounted_tool" => {
-            log::info!(target: "robot_emulator", "Got request to check mounted tool.");
+            log::info!(target: log_target, "Got request to check mounted tool.");
             if !fail {
+                // The emulated tool forces the result, otherwise the robot reports what it
+                // actually mounted, or a random tool if it never mounted or unmounted one
                 if request.emulate_mounted_tool {
                     checked_mounted_tool = request.emulated_mounted_tool.clone()
+                } else if request.mounted_tool != "unknown" {
+                    checked_mounted_tool = request.mounted_tool.clone()
                 } else {
                     checked_mounted_tool = vec!["gripper_tool", "suction_tool", "none"]
-                        .choose(&mut rand::thread_rng())
+                        .choose(&mut crate::emulator_rng())
                         .unwrap()
                         .to_string();
                 }
             }
         }
         _ => {
-            log::warn!(target: "robot_emulator", "Unknown command: '{}'", request.command);
+            log::warn!(target: log_target, "Unknown command: '{}'", request.command);
             fail = true;
         }
     };
 
-    // log::warn!(target: "robot_emulator", "DELAY: '{}'", request.emulate_execution_time);
+    // log::warn!(target: log_target, "DELAY: '{}'", request.emulate_execution_time);
 
     let delay_ms: u64 = match request.emulate_execution_time {
         0 => 0,
         1 => request.emulated_execution_time as u64,
-        2 => rand::thread_rng().gen_range(0..=request.emulated_execution_time) as u64,
+        2 => crate::emulator_rng().gen_range(0..=request.emulated_execution_time) as u64,
         _ => 0,
     };
-    if delay_ms > 0 {
-        tokio::time::sleep(Duration::from_millis(delay_ms)).await;
+
+    // Only motions depend on the position catalog, speed and payload
+    let motion = MotionEmulation {
+        speed: request.speed,
+        payload: request.payload,
+        emulated_max_payload: request.emulated_max_payload,
+        emulated_motion_failure_rate: request.emulated_motion_failure_rate,
+    };
+    let motion_failure = match request.command.as_str() {
+        "move" if !is_known_position(&request.positions, &request.position) => {
+            Some("unknown_position".to_string())
+        }
+        "move" => emulate_motion_failure(&motion),
+        _ => None,
+    };
+    let delay_ms = match (request.command.as_str(), motion_failure.as_deref()) {
+        (_, Some("invalid_speed")) | (_, Some("unknown_position")) => 0,
+        ("move", _) => (delay_ms as f64 * motion_time_factor(&motion)) as u64,
+        _ => delay_ms,
+    };
+    if motion_failure.is_some() {
+        fail = true;
     }
+    // Everything takes longer in degraded mode
+    let delay_ms = (delay_ms as f64 * request.execution_time_factor) as u64;
+    emulate_progress(name, connection_manager, delay_ms, log_target).await;
 
-    let cause = if fail {
+    let cause = if let Some(cause) = motion_failure {
+        cause
+    } else if fail {
         match request.emulate_failure_cause {
             0 => "generic_failure".to_string(),
             1 => request
@@ -221,7 +623,7 @@ pub async fn emulate_robot_operation(request: &RobotRequest) -> RobotResponse {
                 .unwrap_or_else(|| "config_error".to_string()),
             2 => request
                 .emulated_failure_cause
-                .choose(&mut rand::thread_rng())
+                .choose(&mut crate::emulator_rng())
                 .cloned()
                 .unwrap_or_else(|| "random_error".to_string()),
             _ => "generic_failure".to_string(),
@@ -262,20 +664,416 @@ pub async fn emulate_robot_operation(request: &RobotRequest) -> RobotResponse {
     };
 
     if !fail {
-        log::info!(target: "robot_emulator", "{}", success_info);
+        log::info!(target: log_target, "{}", success_info);
         RobotResponse {
             success: true,
             failure_cause: "".to_string(),
             info: success_info,
             checked_mounted_tool,
+            failed_step: "".to_string(),
         }
     } else {
-        log::error!(target: "robot_emulator", "{}", failure_info);
+        log::error!(target: log_target, "{}", failure_info);
         RobotResponse {
             success: false,
             failure_cause: cause,
             info: failure_info,
             checked_mounted_tool,
+            failed_step: "".to_string(),
         }
     }
 }
+
+// Composite commands are split into single steps that are emulated one after the other,
+// each with its own execution time and chance to fail. The estimated position is published
+// as soon as a step reaches it, and the first failing step ends the command. The failed step
+// is reported as "index:command:position", i.e. "2:move:c".
+pub async fn emulate_robot_composite_operation<B: StateBackend + ?Sized>(
+    name: &str,
+    connection_manager: &Arc<B>,
+    request: &RobotRequest,
+    log_target: &str,
+) -> RobotResponse {
+    let steps: Vec<(&str, String)> = match request.command.as_str() {
+        "move_through" => request
+            .waypoints
+            .iter()
+            .map(|waypoint| ("move", waypoint.clone()))
+            .collect(),
+        "pick_and_place" => vec![
+            ("move", request.from.clone()),
+            ("pick", request.from.clone()),
+            ("move", request.to.clone()),
+            ("place", request.to.clone()),
+        ],
+        _ => vec![],
+    };
+
+    log::info!(target: log_target,
+        "Got request to {} in {} steps.", request.command, steps.len()
+    );
+
+    if steps.is_empty() {
+        let info = format!("Failed to {}, no steps given.", request.command);
+        log::error!(target: log_target, "{}", info);
+        return RobotResponse {
+            success: false,
+            failure_cause: "no_steps".to_string(),
+            info,
+            checked_mounted_tool: "".to_string(),
+            failed_step: "".to_string(),
+        };
+    }
+
+    for (index, (command, position)) in steps.iter().enumerate() {
+        let step = RobotRequest {
+            command: command.to_string(),
+            position: position.clone(),
+            ..request.clone()
+        };
+        let response = emulate_robot_operation(name, connection_manager, &step, log_target).await;
+
+        if !response.success {
+            let info = format!(
+                "Failed to {} at step {} ({} {}) due to {}.",
+                request.command, index, command, position, response.failure_cause
+            );
+            log::error!(target: log_target, "{}", info);
+            return RobotResponse {
+                success: false,
+                failure_cause: response.failure_cause,
+                info,
+                checked_mounted_tool: "".to_string(),
+                failed_step: format!("{index}:{command}:{position}"),
+            };
+        }
+
+        if *command == "move" {
+            publish_robot_position(name, connection_manager, position, log_target).await;
+        }
+    }
+
+    let info = format!("Succeeded to {} in {} steps.", request.command, steps.len());
+    log::info!(target: log_target, "{}", info);
+    RobotResponse {
+        success: true,
+        failure_cause: "".to_string(),
+        info,
+        checked_mounted_tool: "".to_string(),
+        failed_step: "".to_string(),
+    }
+}
+
+// Publish a position that was reached in the middle of a composite command.
+async fn publish_robot_position<B: StateBackend + ?Sized>(
+    name: &str,
+    connection_manager: &Arc<B>,
+    position: &str,
+    log_target: &str,
+) {
+    let keys = vec![
+        format!("{name}_position_estimated"),
+        format!("{name}_tcp_frame"),
+    ];
+
+    if let Some(state) = connection_manager
+        .get_state_for_keys(&keys, log_target)
+        .await
+    {
+        let new_state = state
+            .update(&format!("{name}_position_estimated"), position.to_spvalue())
+            .update(
+                &format!("{name}_tcp_frame"),
+                robot_frame(
+                    &format!("{name}_base"),
+                    &format!("{name}_tcp"),
+                    robot_position_to_translation(position),
+                    true,
+                ),
+            );
+        let modified_state = state.get_diff_partial_state(&new_state);
+        connection_manager.set_state(&modified_state).await;
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_robot_emulator_frames() {
+    let log_target = "test_robot_emulator_frames";
+    let state = crate::model::state::generate_robot_variables("robot_1", &State::new());
+    let backend: Arc<dyn StateBackend> = Arc::new(crate::InMemoryBackend::new(&state));
+    let emulator = crate::spawn_emulator(
+        &crate::ScenarioEmulator::Robot("robot_1".to_string()),
+        &backend,
+    );
+
+    let frame = |state: &State, key: &str| match state
+        .state
+        .get(key)
+        .map(|assignment| assignment.val.clone())
+    {
+        Some(SPValue::Transform(TransformOrUnknown::Transform(frame))) => frame,
+        value => panic!("Expected a transform in {key}, got {value:?}."),
+    };
+    let translation = |frame: &SPTransformStamped| {
+        let translation = &frame.transform.translation;
+        (
+            translation.x.into_inner(),
+            translation.y.into_inner(),
+            translation.z.into_inner(),
+        )
+    };
+
+    // The tcp follows the move, and the tool hangs under the tcp of this robot
+    let state = crate::complete_request(
+        &backend,
+        "robot_1",
+        &[
+            ("command_command", "move".to_spvalue()),
+            ("position_command", "gripper_tool_rack".to_spvalue()),
+        ],
+    )
+    .await;
+    let tcp_frame = frame(&state, "robot_1_tcp_frame");
+    assert_eq!(tcp_frame.parent_frame_id, "robot_1_base");
+    assert_eq!(tcp_frame.child_frame_id, "robot_1_tcp");
+    assert!(tcp_frame.active_transform);
+    assert_eq!(
+        translation(&tcp_frame),
+        robot_position_to_translation("gripper_tool_rack")
+    );
+
+    let state = crate::complete_request(
+        &backend,
+        "robot_1",
+        &[("command_command", "mount".to_spvalue())],
+    )
+    .await;
+    assert_eq!(
+        state.get_string_or_default_to_unknown("robot_1_mounted_tool", log_target),
+        "gripper_tool"
+    );
+    let tool_frame = frame(&state, "robot_1_tool_frame");
+    assert_eq!(tool_frame.parent_frame_id, "robot_1_tcp");
+    assert_eq!(tool_frame.child_frame_id, "robot_1_tool");
+    assert!(tool_frame.active_transform);
+
+    // Moving with the tool mounted moves the tcp, the tool stays where it is on the tcp
+    let state = crate::complete_request(
+        &backend,
+        "robot_1",
+        &[
+            ("command_command", "move".to_spvalue()),
+            ("position_command", "a".to_spvalue()),
+        ],
+    )
+    .await;
+    assert_eq!(
+        translation(&frame(&state, "robot_1_tcp_frame")),
+        robot_position_to_translation("a")
+    );
+    let moved_tool_frame = frame(&state, "robot_1_tool_frame");
+    assert_eq!(moved_tool_frame.parent_frame_id, "robot_1_tcp");
+    assert_eq!(translation(&moved_tool_frame), translation(&tool_frame));
+
+    crate::complete_request(
+        &backend,
+        "robot_1",
+        &[
+            ("command_command", "move".to_spvalue()),
+            ("position_command", "gripper_tool_rack".to_spvalue()),
+        ],
+    )
+    .await;
+    let state = crate::complete_request(
+        &backend,
+        "robot_1",
+        &[("command_command", "unmount".to_spvalue())],
+    )
+    .await;
+    assert_eq!(
+        state.get_string_or_default_to_unknown("robot_1_mounted_tool", log_target),
+        "none"
+    );
+    let tool_frame = frame(&state, "robot_1_tool_frame");
+    assert_eq!(tool_frame.parent_frame_id, "robot_1_tcp");
+    assert!(!tool_frame.active_transform);
+    emulator.abort();
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_robot_emulators_by_name() {
+    let log_target = "test_robot_emulators_by_name";
+    let state = crate::model::state::generate_robot_variables("robot_1", &State::new());
+    let state = crate::model::state::generate_robot_variables("robot_2", &state);
+    let backend: Arc<dyn StateBackend> = Arc::new(crate::InMemoryBackend::new(&state));
+    let emulators: Vec<_> = ["robot_1", "robot_2"]
+        .iter()
+        .map(|name| {
+            crate::spawn_emulator(&crate::ScenarioEmulator::Robot(name.to_string()), &backend)
+        })
+        .collect();
+
+    // Each robot only picks up the requests in its own variables
+    let state = crate::complete_request(
+        &backend,
+        "robot_1",
+        &[
+            ("command_command", "move".to_spvalue()),
+            ("position_command", "a".to_spvalue()),
+        ],
+    )
+    .await;
+    assert_eq!(
+        state.get_string_or_default_to_unknown("robot_1_position_estimated", log_target),
+        "a"
+    );
+    assert_eq!(
+        state.get_string_or_default_to_unknown("robot_2_position_estimated", log_target),
+        "unknown"
+    );
+    assert!(!state.get_bool_or_default_to_false("robot_2_request_trigger", log_target));
+
+    let state = crate::complete_request(
+        &backend,
+        "robot_2",
+        &[
+            ("command_command", "move".to_spvalue()),
+            ("position_command", "b".to_spvalue()),
+        ],
+    )
+    .await;
+    assert_eq!(
+        state.get_string_or_default_to_unknown("robot_1_position_estimated", log_target),
+        "a"
+    );
+    assert_eq!(
+        state.get_string_or_default_to_unknown("robot_2_position_estimated", log_target),
+        "b"
+    );
+    let tcp_frame = state
+        .state
+        .get("robot_2_tcp_frame")
+        .map(|assignment| &assignment.val);
+    assert!(matches!(
+        tcp_frame,
+        Some(SPValue::Transform(TransformOrUnknown::Transform(frame)))
+            if frame.child_frame_id == "robot_2_tcp"
+    ));
+    emulators.iter().for_each(|emulator| emulator.abort());
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_robot_emulator_move_through() {
+    let log_target = "test_robot_emulator_move_through";
+    let state = crate::model::state::generate_robot_variables("robot_1", &State::new());
+    let state = state
+        .update(
+            "robot_1_emulate_execution_time",
+            crate::EMULATE_EXACT_EXECUTION_TIME.to_spvalue(),
+        )
+        .update("robot_1_emulated_execution_time", 1000.to_spvalue());
+    let backend: Arc<dyn StateBackend> = Arc::new(crate::InMemoryBackend::new(&state));
+    let emulator = crate::spawn_emulator(
+        &crate::ScenarioEmulator::Robot("robot_1".to_string()),
+        &backend,
+    );
+
+    // Watch the position twice per tick while the robot moves through the waypoints
+    let watching = async {
+        let mut visited: Vec<String> = vec![];
+        for _ in 0..40 {
+            crate::emulated_sleep(Duration::from_millis(EMULATOR_TICK_INTERVAL / 2)).await;
+            let state = backend.get_full_state().await.unwrap();
+            let position =
+                state.get_string_or_default_to_unknown("robot_1_position_estimated", log_target);
+            if visited.last() != Some(&position) {
+                visited.push(position);
+            }
+        }
+        visited
+    };
+    let (state, visited) = tokio::join!(
+        crate::complete_request(
+            &backend,
+            "robot_1",
+            &[
+                ("command_command", "move_through".to_spvalue()),
+                ("waypoints_command", vec!["a", "b", "c"].to_spvalue()),
+            ],
+        ),
+        watching
+    );
+
+    assert_eq!(
+        state.get_string_or_default_to_unknown("robot_1_request_state", log_target),
+        "succeeded"
+    );
+    // Each waypoint was reported when the robot got there, not only the last one
+    let waypoints: Vec<&str> = visited
+        .iter()
+        .map(|position| position.as_str())
+        .filter(|position| ["a", "b", "c"].contains(position))
+        .collect();
+    assert_eq!(waypoints, vec!["a", "b", "c"]);
+
+    emulator.abort();
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_robot_emulator_position_catalog() {
+    let log_target = "test_robot_emulator_position_catalog";
+    let state = crate::model::state::generate_robot_variables("robot_1", &State::new());
+    let state = state
+        .update(
+            "robot_1_emulate_execution_time",
+            crate::EMULATE_EXACT_EXECUTION_TIME.to_spvalue(),
+        )
+        .update("robot_1_emulated_execution_time", 1000.to_spvalue())
+        .update("robot_1_emulated_positions", vec!["a", "b"].to_spvalue());
+    let backend: Arc<dyn StateBackend> = Arc::new(crate::InMemoryBackend::new(&state));
+    let emulator = crate::spawn_emulator(
+        &crate::ScenarioEmulator::Robot("robot_1".to_string()),
+        &backend,
+    );
+
+    let state = crate::complete_request(
+        &backend,
+        "robot_1",
+        &[
+            ("command_command", "move".to_spvalue()),
+            ("position_command", "a".to_spvalue()),
+        ],
+    )
+    .await;
+    assert_eq!(
+        state.get_string_or_default_to_unknown("robot_1_request_state", log_target),
+        "succeeded"
+    );
+
+    // Positions outside of the catalog are refused right away, and the robot stays put
+    let started = EmulatedInstant::now();
+    let state = crate::complete_request(
+        &backend,
+        "robot_1",
+        &[
+            ("command_command", "move".to_spvalue()),
+            ("position_command", "c".to_spvalue()),
+        ],
+    )
+    .await;
+    assert!(started.elapsed() < Duration::from_millis(1000));
+    assert_eq!(
+        state.get_string_or_default_to_unknown("robot_1_request_state", log_target),
+        "failed"
+    );
+    assert_eq!(
+        state.get_string_or_default_to_unknown("robot_1_position_estimated", log_target),
+        "a"
+    );
+    assert_eq!(
+        state.get_int_or_default_to_zero("robot_1_total_fail_counter", log_target),
+        1
+    );
+
+    emulator.abort();
+}
@@ -14,13 +14,53 @@ pub static DONT_EMULATE_FAILURE_CAUSE: i64 = 0; // If the action fails, it wil f
 pub static EMULATE_EXACT_FAILURE_CAUSE: i64 = 1; // Specify why the exact reason why the action fails (takes the first from the "emulated_failure_cause" list)
 pub static EMULATE_RANDOM_FAILURE_CAUSE: i64 = 2; // The action will fail and randomly choose a cause from the "emulated_failure_cause" list
 
+pub static DONT_EMULATE_DOWNTIME: i64 = 0; // The downtime windows are ignored, the resource is always available
+pub static EMULATE_DOWNTIME_MAINTENANCE: i64 = 1; // During a downtime window, every command fails with "maintenance"
+pub static EMULATE_DOWNTIME_OFFLINE: i64 = 2; // During a downtime window, the resource goes offline and leaves requests pending
+
+pub static NOMINAL_SPEED: f64 = 0.5; // The speed at which a motion takes "emulated_execution_time"
+pub static MAX_SPEED: f64 = 1.0; // Motions with a speed outside of (0.0, MAX_SPEED] fail with "invalid_speed", 0.0 is the nominal speed
+
+pub static DONT_EMULATE_NOK: i64 = 0; // Every tightening will be OK
+pub static EMULATE_NOK_ALWAYS: i64 = 1; // Every tightening will be NOK
+pub static EMULATE_NOK_RANDOM_RATE: i64 = 2; // The tightening will randomly be NOK with a "emulated_nok_rate" rate
+
+pub static OPERATOR_ACKNOWLEDGE: i64 = 0; // The operator only acknowledges the problem and leaves the operation as it is
+pub static OPERATOR_BYPASS: i64 = 1; // The operator bypasses the operation
+pub static OPERATOR_RETRY: i64 = 2; // The operator puts the operation back to initial so that it can be retried
+pub static OPERATOR_ABORT: i64 = 3; // The operator gives up and puts the operation in fatal
+
 pub mod emulators;
+pub use crate::emulators::agv::*;
+pub use crate::emulators::backend::*;
+pub use crate::emulators::capabilities::*;
+pub use crate::emulators::clock::*;
+pub use crate::emulators::degraded::*;
+pub use crate::emulators::downtime::*;
 pub use crate::emulators::gantry::*;
+pub use crate::emulators::in_process_redis::*;
+pub use crate::emulators::measurement::*;
+pub use crate::emulators::motion::*;
+pub use crate::emulators::nutrunner::*;
+pub use crate::emulators::operator::*;
+pub use crate::emulators::progress::*;
+pub use crate::emulators::queue::*;
+pub use crate::emulators::random::*;
+pub use crate::emulators::reservation::*;
 pub use crate::emulators::robot::*;
 
 pub mod model;
 pub use crate::model::*;
 
+pub mod scenarios;
+pub use crate::scenarios::campaign::*;
+pub use crate::scenarios::harness::*;
+pub use crate::scenarios::registry::*;
+pub use crate::scenarios::scenario::*;
+pub use crate::scenarios::script::*;
+pub use crate::scenarios::snapshot::*;
+pub use crate::scenarios::trace::*;
+
 // pub mod utils;
 // pub use crate::utils::state_publisher::*;
 // pub use crate::utils::env_logger::*;
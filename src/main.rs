@@ -8,6 +8,15 @@ use micro_sp_emulation::*;
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
     micro_sp::initialize_env_logger();
+
+    // Run the emulators faster than real time with EMULATION_TIME_SCALE, the runner of
+    // micro_sp keeps ticking in real time
+    let time_scale = time_scale_from_env();
+    log::info!(target: "micro_sp_emulator", "Emulators at {}x real time, the runner in real time.", time_scale);
+    with_time_scale(time_scale, emulate()).await
+}
+
+async fn emulate() -> Result<(), Box<dyn Error>> {
     let sp_id = "sp1".to_string();
 
     // Enable coverability tracking:
@@ -54,10 +63,26 @@ async fn main() -> Result<(), Box<dyn Error>> {
     log::info!(target: "micro_sp_emulator", "Spawning emulators.");
 
     let con_clone = con_arc.clone();
-    tokio::task::spawn(async move { robot_emulator(&con_clone).await.unwrap() });
+    spawn_emulated(async move { robot_emulator("robot", &con_clone).await.unwrap() });
+
+    let con_clone = con_arc.clone();
+    spawn_emulated(async move { gantry_emulator("gantry", &con_clone).await.unwrap() });
+
+    let con_clone = con_arc.clone();
+    spawn_emulated(async move { nutrunner_emulator("nutrunner", &con_clone).await.unwrap() });
+
+    let con_clone = con_arc.clone();
+    spawn_emulated(async move { agv_emulator("agv", &con_clone).await.unwrap() });
 
+    // Only steps in for the operations listed in "operator_watched_operations"
     let con_clone = con_arc.clone();
-    tokio::task::spawn(async move { gantry_emulator(&con_clone).await.unwrap() });
+    spawn_emulated(async move { operator_emulator(&con_clone).await.unwrap() });
+
+    // The job queues stay idle unless "{name}_emulate_queue" is set
+    for name in ["robot", "gantry", "nutrunner", "agv"] {
+        let con_clone = con_arc.clone();
+        spawn_emulated(async move { queue_emulator(name, &con_clone).await.unwrap() });
+    }
 
     log::info!(target: "micro_sp_emulator", "Spawning Micro SP.");
 
@@ -76,7 +101,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
     let con_clone = con_arc.clone(); // <-- Pass the Arc
     // let sp_id_clone = sp_id.clone();
-    tokio::task::spawn(async move {
+    spawn_emulated(async move {
         // Get a fresh connection *inside* the new task
         let con_local = con_clone.get_connection().await;
 
@@ -91,6 +116,6 @@ async fn main() -> Result<(), Box<dyn Error>> {
     log::info!(target: "micro_sp_emulator", "Node started.");
 
     loop {
-        tokio::time::sleep(Duration::from_millis(EMULATOR_TICK_INTERVAL)).await;
+        emulated_sleep(Duration::from_millis(EMULATOR_TICK_INTERVAL)).await;
     }
 }
@@ -42,17 +42,11 @@ pub fn model(sp_id: &str, state: &State) -> (Model, State) {
 }
 
 #[tokio::test]
-#[serial_test::serial]
 async fn test_auto_transitions() -> Result<(), Box<dyn std::error::Error>> {
     use regex::Regex;
-    use testcontainers::{ImageExt, core::ContainerPort, runners::AsyncRunner};
-    use testcontainers_modules::redis::Redis;
-
-    let _container = Redis::default()
-        .with_mapped_port(6379, ContainerPort::Tcp(6379))
-        .start()
-        .await
-        .unwrap();
+    let redis = crate::InProcessRedis::shared()
+        .listen("test_auto_transitions")
+        .await?;
 
     let log_target = "micro_sp_emulation::test_auto_transitions";
     micro_sp::initialize_env_logger();
@@ -71,7 +65,7 @@ async fn test_auto_transitions() -> Result<(), Box<dyn std::error::Error>> {
     let op_vars = generate_operation_state_variables(&model, coverability_tracking, "emulator");
     let state = state.extend(op_vars, true);
 
-    let connection_manager = ConnectionManager::new().await;
+    let connection_manager = redis.connection_manager().await;
     StateManager::set_state(&mut connection_manager.get_connection().await, &state).await;
     let con_arc = std::sync::Arc::new(connection_manager);
 
@@ -81,14 +75,14 @@ async fn test_auto_transitions() -> Result<(), Box<dyn std::error::Error>> {
 
     let con_clone = con_arc.clone();
     let robot_handle = tokio::task::spawn(async move {
-        crate::emulators::robot::robot_emulator(&con_clone)
+        crate::emulators::robot::robot_emulator("robot", &con_clone)
             .await
             .unwrap()
     });
 
     let con_clone = con_arc.clone();
     let gantry_handle = tokio::task::spawn(async move {
-        crate::emulators::gantry::gantry_emulator(&con_clone)
+        crate::emulators::gantry::gantry_emulator("gantry", &con_clone)
             .await
             .unwrap()
     });
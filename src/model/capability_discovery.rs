@@ -0,0 +1,119 @@
+use micro_sp::{running::goal_runner::goal_string_to_sp_value, *};
+use redis::aio::MultiplexedConnection;
+use std::error::Error;
+
+use crate::{
+    DONT_EMULATE_FAILURE, EMULATE_EXACT_EXECUTION_TIME, capability_values, get_string_array,
+};
+
+// The moves are generated from the position catalog of the gantry, and the goals by a goal
+// generator that only knows what the gantry publishes in its capabilities: it waits for them
+// and sends the gantry to every position it can move to, in order.
+pub fn model(sp_id: &str, state: &State) -> (Model, State) {
+    let state = state.clone();
+    let auto_transitions = vec![];
+    let sops = vec![];
+    let mut operations = vec![];
+
+    let positions = get_string_array(&state, "gantry_emulated_positions", "capability_discovery");
+
+    for pos in positions {
+        operations.push(Operation::new(
+            &format!("gantry_move_to_{pos}"),
+            None,
+            None,
+            None,
+            None,
+            false,
+            Vec::from([Transition::parse(
+                &format!("start_gantry_move_to_{pos}"),
+                &format!(
+                    "var:gantry_request_state == initial \
+                    && var:gantry_request_trigger == false \
+                    && var:gantry_position_estimated != {pos}"
+                ),
+                "true",
+                vec![
+                    "var:gantry_command_command <- move",
+                    &format!("var:gantry_position_command <- {pos}"),
+                    "var:gantry_speed_command <- 0.5",
+                    "var:gantry_request_trigger <- true",
+                ],
+                Vec::<&str>::new(),
+                &state,
+            )]),
+            Vec::from([Transition::parse(
+                &format!("complete_gantry_move_to_{pos}"),
+                "true",
+                "var:gantry_request_state == succeeded",
+                vec![
+                    "var:gantry_request_trigger <- false",
+                    "var:gantry_request_state <- initial",
+                    &format!("var:gantry_position_estimated <- {pos}"),
+                ],
+                Vec::<&str>::new(),
+                &state,
+            )]),
+            Vec::from([]),
+            Vec::from([]),
+            Vec::from([]),
+            Vec::from([]),
+        ));
+    }
+
+    let model = Model::new(sp_id, auto_transitions, vec![], sops, operations);
+
+    (model, state)
+}
+
+pub async fn run_emultaion(
+    sp_id: &str,
+    mut con: MultiplexedConnection,
+) -> Result<(), Box<dyn Error>> {
+    initialize_env_logger();
+    let log_target = "capability_discovery";
+
+    let capabilities = loop {
+        crate::emulated_sleep(std::time::Duration::from_millis(
+            crate::EMULATOR_TICK_INTERVAL,
+        ))
+        .await;
+        if let Some(state) = StateManager::get_full_state(&mut con).await {
+            let capabilities =
+                state.get_string_or_default_to_unknown("gantry_capabilities", log_target);
+            if capabilities != "unknown" {
+                break capabilities;
+            }
+        }
+    };
+
+    let scheduled_goals = capability_values(&capabilities, "move", "position_command")
+        .iter()
+        .map(|pos| {
+            goal_string_to_sp_value(
+                &format!("var:gantry_position_estimated == {pos}"),
+                running::goal_runner::GoalPriority::Normal,
+            )
+        })
+        .collect::<Vec<SPValue>>()
+        .to_spvalue();
+
+    if let Some(state) = StateManager::get_full_state(&mut con).await {
+        let new_state = state
+            .update(
+                "gantry_emulate_execution_time",
+                EMULATE_EXACT_EXECUTION_TIME.to_spvalue(),
+            )
+            .update("gantry_emulated_execution_time", 200.to_spvalue())
+            .update(
+                "gantry_emulate_failure_rate",
+                DONT_EMULATE_FAILURE.to_spvalue(),
+            )
+            .update(&format!("{sp_id}_scheduled_goals"), scheduled_goals);
+
+        let modified_state = state.get_diff_partial_state(&new_state);
+        StateManager::set_state(&mut con, &modified_state).await;
+    }
+
+    Ok(())
+}
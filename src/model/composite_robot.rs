@@ -0,0 +1,130 @@
+use micro_sp::{running::goal_runner::goal_string_to_sp_value, *};
+use redis::aio::MultiplexedConnection;
+use std::error::Error;
+
+use crate::{DONT_EMULATE_FAILURE, EMULATE_EXACT_EXECUTION_TIME};
+
+// The same task as a couple of fine-grained moves, but with two coarse commands in the driver:
+// move through a, b and c, then pick at c and place at d.
+pub fn model(sp_id: &str, state: &State) -> (Model, State) {
+    let state = state.clone();
+    let auto_transitions = vec![];
+    let sops = vec![];
+    let mut operations = vec![];
+
+    operations.push(Operation::new(
+        "robot_move_through_a_b_c",
+        None,
+        None,
+        None,
+        None,
+        false,
+        Vec::from([Transition::parse(
+            "start_robot_move_through_a_b_c",
+            "var:robot_request_state == initial \
+                && var:robot_request_trigger == false \
+                && var:robot_position_estimated != c",
+            "true",
+            vec![
+                "var:robot_command_command <- move_through",
+                "var:robot_speed_command <- 0.5",
+                "var:robot_request_trigger <- true",
+            ],
+            Vec::<&str>::new(),
+            &state,
+        )]),
+        Vec::from([Transition::parse(
+            "complete_robot_move_through_a_b_c",
+            "true",
+            "var:robot_request_state == succeeded",
+            vec![
+                "var:robot_request_trigger <- false",
+                "var:robot_request_state <- initial",
+                "var:robot_position_estimated <- c",
+            ],
+            Vec::<&str>::new(),
+            &state,
+        )]),
+        Vec::from([]),
+        Vec::from([]),
+        Vec::from([]),
+        Vec::from([]),
+    ));
+
+    operations.push(Operation::new(
+        "robot_pick_at_c_and_place_at_d",
+        None,
+        None,
+        None,
+        None,
+        false,
+        Vec::from([Transition::parse(
+            "start_robot_pick_at_c_and_place_at_d",
+            "var:robot_request_state == initial \
+                && var:robot_request_trigger == false \
+                && var:robot_position_estimated == c",
+            "true",
+            vec![
+                "var:robot_command_command <- pick_and_place",
+                "var:robot_from_command <- c",
+                "var:robot_to_command <- d",
+                "var:robot_speed_command <- 0.5",
+                "var:robot_request_trigger <- true",
+            ],
+            Vec::<&str>::new(),
+            &state,
+        )]),
+        Vec::from([Transition::parse(
+            "complete_robot_pick_at_c_and_place_at_d",
+            "true",
+            "var:robot_request_state == succeeded",
+            vec![
+                "var:robot_request_trigger <- false",
+                "var:robot_request_state <- initial",
+                "var:robot_position_estimated <- d",
+            ],
+            Vec::<&str>::new(),
+            &state,
+        )]),
+        Vec::from([]),
+        Vec::from([]),
+        Vec::from([]),
+        Vec::from([]),
+    ));
+
+    let model = Model::new(sp_id, auto_transitions, vec![], sops, operations);
+
+    (model, state)
+}
+
+pub async fn run_emultaion(
+    sp_id: &str,
+    mut con: MultiplexedConnection,
+) -> Result<(), Box<dyn Error>> {
+    initialize_env_logger();
+    let goal = "var:robot_position_estimated == d".to_string();
+    let uq_goal = goal_string_to_sp_value(&goal, running::goal_runner::GoalPriority::Normal);
+    let scheduled_goals = vec![uq_goal].to_spvalue();
+
+    crate::emulated_sleep(std::time::Duration::from_millis(500)).await;
+
+    if let Some(state) = StateManager::get_full_state(&mut con).await {
+        let new_state = state
+            .update(
+                "robot_emulate_execution_time",
+                EMULATE_EXACT_EXECUTION_TIME.to_spvalue(),
+            )
+            .update("robot_emulated_execution_time", 600.to_spvalue())
+            .update(
+                "robot_emulate_failure_rate",
+                DONT_EMULATE_FAILURE.to_spvalue(),
+            )
+            .update("robot_waypoints_command", vec!["a", "b", "c"].to_spvalue())
+            .update(&format!("{sp_id}_scheduled_goals"), scheduled_goals);
+
+        let modified_state = state.get_diff_partial_state(&new_state);
+        StateManager::set_state(&mut con, &modified_state).await;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,279 @@
+use micro_sp::{running::goal_runner::goal_string_to_sp_value, *};
+use redis::aio::MultiplexedConnection;
+use std::error::Error;
+
+use crate::{DONT_EMULATE_FAILURE, EMULATE_EXACT_EXECUTION_TIME};
+
+// Moving at full speed overloads the gantry, which continues in degraded mode: the next move
+// takes twice as long and locking is refused. Calibrating brings the gantry back to normal,
+// after which it can be locked.
+pub fn model(sp_id: &str, state: &State) -> (Model, State) {
+    let state = state.clone();
+    let auto_transitions = vec![];
+    let sops = vec![];
+    let mut operations = vec![];
+
+    let failed_fast = bv!(&&format!("gantry_failed_fast"));
+    let refused_lock = bv!(&&format!("gantry_refused_lock"));
+    let state = state.add(
+        assign!(failed_fast, SPValue::Bool(BoolOrUnknown::Bool(false))),
+        "emulator",
+    );
+    let state = state.add(
+        assign!(refused_lock, SPValue::Bool(BoolOrUnknown::Bool(false))),
+        "emulator",
+    );
+
+    operations.push(Operation::new(
+        "gantry_move_to_b_too_fast",
+        None,
+        None,
+        None,
+        None,
+        false,
+        Vec::from([Transition::parse(
+            "start_gantry_move_to_b_too_fast",
+            "var:gantry_request_state == initial \
+                && var:gantry_request_trigger == false \
+                && var:gantry_failed_fast == false",
+            "true",
+            vec![
+                "var:gantry_command_command <- move",
+                "var:gantry_position_command <- b",
+                "var:gantry_speed_command <- 1.0",
+                "var:gantry_request_trigger <- true",
+            ],
+            Vec::<&str>::new(),
+            &state,
+        )]),
+        // Failing is the expected outcome here
+        Vec::from([Transition::parse(
+            "complete_gantry_move_to_b_too_fast",
+            "true",
+            "var:gantry_request_state == failed",
+            vec![
+                "var:gantry_request_trigger <- false",
+                "var:gantry_request_state <- initial",
+                "var:gantry_failed_fast <- true",
+            ],
+            Vec::<&str>::new(),
+            &state,
+        )]),
+        Vec::from([]),
+        Vec::from([]),
+        Vec::from([]),
+        Vec::from([]),
+    ));
+
+    operations.push(Operation::new(
+        "gantry_move_to_a",
+        None,
+        None,
+        None,
+        None,
+        false,
+        Vec::from([Transition::parse(
+            "start_gantry_move_to_a",
+            "var:gantry_request_state == initial \
+                && var:gantry_request_trigger == false \
+                && var:gantry_failed_fast == true \
+                && var:gantry_position_estimated != a",
+            "true",
+            vec![
+                "var:gantry_command_command <- move",
+                "var:gantry_position_command <- a",
+                "var:gantry_speed_command <- 0.5",
+                "var:gantry_request_trigger <- true",
+            ],
+            Vec::<&str>::new(),
+            &state,
+        )]),
+        Vec::from([Transition::parse(
+            "complete_gantry_move_to_a",
+            "true",
+            "var:gantry_request_state == succeeded",
+            vec![
+                "var:gantry_request_trigger <- false",
+                "var:gantry_request_state <- initial",
+                "var:gantry_position_estimated <- a",
+            ],
+            Vec::<&str>::new(),
+            &state,
+        )]),
+        Vec::from([]),
+        Vec::from([]),
+        Vec::from([]),
+        Vec::from([]),
+    ));
+
+    operations.push(Operation::new(
+        "gantry_lock_while_degraded",
+        None,
+        None,
+        None,
+        None,
+        false,
+        Vec::from([Transition::parse(
+            "start_gantry_lock_while_degraded",
+            "var:gantry_request_state == initial \
+                && var:gantry_request_trigger == false \
+                && var:gantry_position_estimated == a \
+                && var:gantry_refused_lock == false",
+            "true",
+            vec![
+                "var:gantry_command_command <- lock",
+                "var:gantry_request_trigger <- true",
+            ],
+            Vec::<&str>::new(),
+            &state,
+        )]),
+        // Refused, since the gantry is still degraded
+        Vec::from([Transition::parse(
+            "complete_gantry_lock_while_degraded",
+            "true",
+            "var:gantry_request_state == failed",
+            vec![
+                "var:gantry_request_trigger <- false",
+                "var:gantry_request_state <- initial",
+                "var:gantry_refused_lock <- true",
+            ],
+            Vec::<&str>::new(),
+            &state,
+        )]),
+        Vec::from([]),
+        Vec::from([]),
+        Vec::from([]),
+        Vec::from([]),
+    ));
+
+    operations.push(Operation::new(
+        "gantry_calibrate",
+        None,
+        None,
+        None,
+        None,
+        false,
+        Vec::from([Transition::parse(
+            "start_gantry_calibrate",
+            "var:gantry_request_state == initial \
+                && var:gantry_request_trigger == false \
+                && var:gantry_refused_lock == true \
+                && var:gantry_calibrated_estimated == false",
+            "true",
+            vec![
+                "var:gantry_command_command <- calibrate",
+                "var:gantry_request_trigger <- true",
+            ],
+            Vec::<&str>::new(),
+            &state,
+        )]),
+        Vec::from([Transition::parse(
+            "complete_gantry_calibrate",
+            "true",
+            "var:gantry_request_state == succeeded",
+            vec![
+                "var:gantry_request_trigger <- false",
+                "var:gantry_request_state <- initial",
+                "var:gantry_calibrated_estimated <- true",
+            ],
+            Vec::<&str>::new(),
+            &state,
+        )]),
+        Vec::from([]),
+        Vec::from([]),
+        Vec::from([]),
+        Vec::from([]),
+    ));
+
+    operations.push(Operation::new(
+        "gantry_lock",
+        None,
+        None,
+        None,
+        None,
+        false,
+        Vec::from([Transition::parse(
+            "start_gantry_lock",
+            "var:gantry_request_state == initial \
+                && var:gantry_request_trigger == false \
+                && var:gantry_calibrated_estimated == true \
+                && var:gantry_locked_estimated == false",
+            "true",
+            vec![
+                "var:gantry_command_command <- lock",
+                "var:gantry_request_trigger <- true",
+            ],
+            Vec::<&str>::new(),
+            &state,
+        )]),
+        Vec::from([Transition::parse(
+            "complete_gantry_lock",
+            "true",
+            "var:gantry_request_state == succeeded",
+            vec![
+                "var:gantry_request_trigger <- false",
+                "var:gantry_request_state <- initial",
+                "var:gantry_locked_estimated <- true",
+            ],
+            Vec::<&str>::new(),
+            &state,
+        )]),
+        Vec::from([]),
+        Vec::from([]),
+        Vec::from([]),
+        Vec::from([]),
+    ));
+
+    let model = Model::new(sp_id, auto_transitions, vec![], sops, operations);
+
+    (model, state)
+}
+
+pub async fn run_emultaion(
+    sp_id: &str,
+    mut con: MultiplexedConnection,
+) -> Result<(), Box<dyn Error>> {
+    initialize_env_logger();
+    let scheduled_goals = vec![
+        "var:gantry_failed_fast == true",
+        "var:gantry_position_estimated == a",
+        "var:gantry_refused_lock == true",
+        "var:gantry_locked_estimated == true",
+    ]
+    .iter()
+    .map(|goal| goal_string_to_sp_value(goal, running::goal_runner::GoalPriority::Normal))
+    .collect::<Vec<SPValue>>()
+    .to_spvalue();
+
+    crate::emulated_sleep(std::time::Duration::from_millis(500)).await;
+
+    if let Some(state) = StateManager::get_full_state(&mut con).await {
+        let new_state = state
+            .update(
+                "gantry_emulate_execution_time",
+                EMULATE_EXACT_EXECUTION_TIME.to_spvalue(),
+            )
+            .update("gantry_emulated_execution_time", 1000.to_spvalue())
+            .update(
+                "gantry_emulate_failure_rate",
+                DONT_EMULATE_FAILURE.to_spvalue(),
+            )
+            // Moving at full speed always fails
+            .update("gantry_emulated_motion_failure_rate", 100.to_spvalue())
+            .update(
+                "gantry_emulated_degrading_causes",
+                vec!["high_speed"].to_spvalue(),
+            )
+            .update("gantry_emulated_degraded_time_factor", 2.0.to_spvalue())
+            .update(
+                "gantry_emulated_degraded_refused_commands",
+                vec!["lock"].to_spvalue(),
+            )
+            .update(&format!("{sp_id}_scheduled_goals"), scheduled_goals);
+
+        let modified_state = state.get_diff_partial_state(&new_state);
+        StateManager::set_state(&mut con, &modified_state).await;
+    }
+
+    Ok(())
+}
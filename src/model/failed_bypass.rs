@@ -143,7 +143,7 @@ pub async fn run_emultaion(
     let uq_goal = goal_string_to_sp_value(&goal, running::goal_runner::GoalPriority::Normal);
     let scheduled_goals = vec![uq_goal].to_spvalue();
 
-    tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+    crate::emulated_sleep(std::time::Duration::from_millis(500)).await;
 
     if let Some(state) = StateManager::get_full_state(&mut con).await {
         let new_state = state
@@ -175,17 +175,11 @@ pub async fn run_emultaion(
 }
 
 #[tokio::test]
-#[serial_test::serial]
 async fn test_failed_bypass() -> Result<(), Box<dyn Error>> {
     use regex::Regex;
-    use testcontainers::{ImageExt, core::ContainerPort, runners::AsyncRunner};
-    use testcontainers_modules::redis::Redis;
-
-    let _container = Redis::default()
-        .with_mapped_port(6379, ContainerPort::Tcp(6379))
-        .start()
-        .await
-        .unwrap();
+    let redis = crate::InProcessRedis::shared()
+        .listen("test_failed_bypass")
+        .await?;
 
     let log_target = "micro_sp_emulation::test_failed_bypass";
     micro_sp::initialize_env_logger();
@@ -204,7 +198,7 @@ async fn test_failed_bypass() -> Result<(), Box<dyn Error>> {
     let op_vars = generate_operation_state_variables(&model, coverability_tracking, "emulator");
     let state = state.extend(op_vars, true);
 
-    let connection_manager = ConnectionManager::new().await;
+    let connection_manager = redis.connection_manager().await;
     StateManager::set_state(&mut connection_manager.get_connection().await, &state).await;
     let con_arc = std::sync::Arc::new(connection_manager);
 
@@ -212,14 +206,14 @@ async fn test_failed_bypass() -> Result<(), Box<dyn Error>> {
 
     let con_clone = con_arc.clone();
     let robot_handle = tokio::task::spawn(async move {
-        crate::emulators::robot::robot_emulator(&con_clone)
+        crate::emulators::robot::robot_emulator("robot", &con_clone)
             .await
             .unwrap()
     });
 
     let con_clone = con_arc.clone();
     let gantry_handle = tokio::task::spawn(async move {
-        crate::emulators::gantry::gantry_emulator(&con_clone)
+        crate::emulators::gantry::gantry_emulator("gantry", &con_clone)
             .await
             .unwrap()
     });
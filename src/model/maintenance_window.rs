@@ -0,0 +1,149 @@
+use micro_sp::{running::goal_runner::goal_string_to_sp_value, *};
+use redis::aio::MultiplexedConnection;
+use std::error::Error;
+
+use crate::{DONT_EMULATE_FAILURE, EMULATE_DOWNTIME_MAINTENANCE, EMULATE_EXACT_EXECUTION_TIME};
+
+// The gantry is in maintenance for the first three seconds. The first move is refused, and
+// the second one waits until the maintenance window is over.
+pub fn model(sp_id: &str, state: &State) -> (Model, State) {
+    let state = state.clone();
+    let auto_transitions = vec![];
+    let sops = vec![];
+    let mut operations = vec![];
+
+    let refused = bv!(&&format!("gantry_refused_maintenance"));
+    let state = state.add(
+        assign!(refused, SPValue::Bool(BoolOrUnknown::Bool(false))),
+        "emulator",
+    );
+
+    operations.push(Operation::new(
+        "gantry_move_to_a_in_maintenance",
+        None,
+        None,
+        None,
+        None,
+        false,
+        Vec::from([Transition::parse(
+            "start_gantry_move_to_a_in_maintenance",
+            "var:gantry_request_state == initial \
+                && var:gantry_request_trigger == false \
+                && var:gantry_refused_maintenance == false",
+            "true",
+            vec![
+                "var:gantry_command_command <- move",
+                "var:gantry_position_command <- a",
+                "var:gantry_speed_command <- 0.5",
+                "var:gantry_request_trigger <- true",
+            ],
+            Vec::<&str>::new(),
+            &state,
+        )]),
+        // Failing is the expected outcome here
+        Vec::from([Transition::parse(
+            "complete_gantry_move_to_a_in_maintenance",
+            "true",
+            "var:gantry_request_state == failed",
+            vec![
+                "var:gantry_request_trigger <- false",
+                "var:gantry_request_state <- initial",
+                "var:gantry_refused_maintenance <- true",
+            ],
+            Vec::<&str>::new(),
+            &state,
+        )]),
+        Vec::from([]),
+        Vec::from([]),
+        Vec::from([]),
+        Vec::from([]),
+    ));
+
+    operations.push(Operation::new(
+        "gantry_move_to_a",
+        None,
+        None,
+        None,
+        None,
+        false,
+        Vec::from([Transition::parse(
+            "start_gantry_move_to_a",
+            "var:gantry_request_state == initial \
+                && var:gantry_request_trigger == false \
+                && var:gantry_refused_maintenance == true \
+                && var:gantry_position_estimated != a",
+            // Planned, but only started once the maintenance window is over
+            "var:gantry_downtime_estimated == false",
+            vec![
+                "var:gantry_command_command <- move",
+                "var:gantry_position_command <- a",
+                "var:gantry_speed_command <- 0.5",
+                "var:gantry_request_trigger <- true",
+            ],
+            Vec::<&str>::new(),
+            &state,
+        )]),
+        Vec::from([Transition::parse(
+            "complete_gantry_move_to_a",
+            "true",
+            "var:gantry_request_state == succeeded",
+            vec![
+                "var:gantry_request_trigger <- false",
+                "var:gantry_request_state <- initial",
+                "var:gantry_position_estimated <- a",
+            ],
+            Vec::<&str>::new(),
+            &state,
+        )]),
+        Vec::from([]),
+        Vec::from([]),
+        Vec::from([]),
+        Vec::from([]),
+    ));
+
+    let model = Model::new(sp_id, auto_transitions, vec![], sops, operations);
+
+    (model, state)
+}
+
+pub async fn run_emultaion(
+    sp_id: &str,
+    mut con: MultiplexedConnection,
+) -> Result<(), Box<dyn Error>> {
+    initialize_env_logger();
+    let goal_refused = "var:gantry_refused_maintenance == true".to_string();
+    let goal_a = "var:gantry_position_estimated == a".to_string();
+    let uq_goal_refused =
+        goal_string_to_sp_value(&goal_refused, running::goal_runner::GoalPriority::Normal);
+    let uq_goal_a = goal_string_to_sp_value(&goal_a, running::goal_runner::GoalPriority::Normal);
+    let scheduled_goals = vec![uq_goal_refused, uq_goal_a].to_spvalue();
+
+    crate::emulated_sleep(std::time::Duration::from_millis(500)).await;
+
+    if let Some(state) = StateManager::get_full_state(&mut con).await {
+        let new_state = state
+            .update(
+                "gantry_emulate_execution_time",
+                EMULATE_EXACT_EXECUTION_TIME.to_spvalue(),
+            )
+            .update("gantry_emulated_execution_time", 500.to_spvalue())
+            .update(
+                "gantry_emulate_failure_rate",
+                DONT_EMULATE_FAILURE.to_spvalue(),
+            )
+            .update(
+                "gantry_emulate_downtime",
+                EMULATE_DOWNTIME_MAINTENANCE.to_spvalue(),
+            )
+            .update(
+                "gantry_emulated_downtime_windows",
+                vec!["0-3000"].to_spvalue(),
+            )
+            .update(&format!("{sp_id}_scheduled_goals"), scheduled_goals);
+
+        let modified_state = state.get_diff_partial_state(&new_state);
+        StateManager::set_state(&mut con, &modified_state).await;
+    }
+
+    Ok(())
+}
@@ -1,17 +1,28 @@
 pub mod auto_operations;
 pub mod auto_operations_multi;
 pub mod auto_transitions;
+pub mod capability_discovery;
+pub mod composite_robot;
 pub mod counter_goal;
+pub mod degraded_mode;
 pub mod disabled;
 pub mod failed_bypass;
 pub mod failed_fatal;
 pub mod failed_retries;
 pub mod incoming_goals;
+pub mod maintenance_window;
+pub mod multi_robot;
+pub mod operator_recovery;
+pub mod position_catalog;
+pub mod progress_feedback;
+pub mod queued_jobs;
 pub mod replan;
 pub mod scheduled_goals;
+pub mod shared_gantry;
 pub mod sop_alternative;
 pub mod sop_parallel;
 pub mod sop_sequence;
+pub mod speed_payload;
 pub mod state;
 pub mod timeout_bypass;
 pub mod timeout_disabled;
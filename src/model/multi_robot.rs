@@ -0,0 +1,109 @@
+use micro_sp::{running::goal_runner::goal_string_to_sp_value, *};
+use redis::aio::MultiplexedConnection;
+use std::error::Error;
+
+use crate::{DONT_EMULATE_FAILURE, EMULATE_EXACT_EXECUTION_TIME};
+
+pub static ROBOTS: [&str; 2] = ["robot_1", "robot_2"];
+
+pub fn model(sp_id: &str, state: &State) -> (Model, State) {
+    // Two identical robots, each with its own request, estimated and emulation variables
+    let state = crate::model::state::generate_robot_variables(ROBOTS[0], state);
+    let state = crate::model::state::generate_robot_variables(ROBOTS[1], &state);
+    let auto_transitions = vec![];
+    let sops = vec![];
+    let mut operations = vec![];
+
+    for robot in ROBOTS {
+        for pos in vec!["a", "b", "c"] {
+            operations.push(Operation::new(
+                &format!("{robot}_move_to_{pos}"),
+                None,
+                None,
+                None,
+                None,
+                false,
+                Vec::from([Transition::parse(
+                    &format!("start_{robot}_move_to_{pos}"),
+                    &format!(
+                        "var:{robot}_request_state == initial \
+                    && var:{robot}_request_trigger == false \
+                    && var:{robot}_position_estimated != {pos}"
+                    ),
+                    "true",
+                    vec![
+                        &format!("var:{robot}_command_command <- move"),
+                        &format!("var:{robot}_position_command <- {pos}"),
+                        &format!("var:{robot}_speed_command <- 0.5"),
+                        &format!("var:{robot}_request_trigger <- true"),
+                    ],
+                    Vec::<&str>::new(),
+                    &state,
+                )]),
+                Vec::from([Transition::parse(
+                    &format!("complete_{robot}_move_to_{pos}"),
+                    "true",
+                    &format!("var:{robot}_request_state == succeeded"),
+                    vec![
+                        &format!("var:{robot}_request_trigger <- false"),
+                        &format!("var:{robot}_request_state <- initial"),
+                        &format!("var:{robot}_position_estimated <- {pos}"),
+                    ],
+                    Vec::<&str>::new(),
+                    &state,
+                )]),
+                Vec::from([]),
+                Vec::from([]),
+                Vec::from([]),
+                Vec::from([]),
+            ));
+        }
+    }
+
+    let model = Model::new(sp_id, auto_transitions, vec![], sops, operations);
+
+    (model, state)
+}
+
+pub async fn run_emultaion(
+    sp_id: &str,
+    mut con: MultiplexedConnection,
+) -> Result<(), Box<dyn Error>> {
+    initialize_env_logger();
+
+    // Both robots share one goal
+    let goal = "var:robot_1_position_estimated == a && var:robot_2_position_estimated == b";
+    let uq_goal = goal_string_to_sp_value(goal, running::goal_runner::GoalPriority::Normal);
+    let scheduled_goals = vec![uq_goal].to_spvalue();
+
+    crate::emulated_sleep(std::time::Duration::from_millis(500)).await;
+
+    if let Some(state) = StateManager::get_full_state(&mut con).await {
+        let new_state = state
+            // The robots are identical, but they don't have to behave the same
+            .update(
+                "robot_1_emulate_execution_time",
+                EMULATE_EXACT_EXECUTION_TIME.to_spvalue(),
+            )
+            .update("robot_1_emulated_execution_time", 300.to_spvalue())
+            .update(
+                "robot_1_emulate_failure_rate",
+                DONT_EMULATE_FAILURE.to_spvalue(),
+            )
+            .update(
+                "robot_2_emulate_execution_time",
+                EMULATE_EXACT_EXECUTION_TIME.to_spvalue(),
+            )
+            .update("robot_2_emulated_execution_time", 900.to_spvalue())
+            .update(
+                "robot_2_emulate_failure_rate",
+                DONT_EMULATE_FAILURE.to_spvalue(),
+            )
+            .update(&format!("{sp_id}_scheduled_goals"), scheduled_goals);
+
+        let modified_state = state.get_diff_partial_state(&new_state);
+        StateManager::set_state(&mut con, &modified_state).await;
+    }
+
+    Ok(())
+}
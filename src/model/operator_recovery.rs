@@ -0,0 +1,124 @@
+use micro_sp::{running::goal_runner::goal_string_to_sp_value, *};
+use redis::aio::MultiplexedConnection;
+use std::error::Error;
+
+use crate::{
+    EMULATE_EXACT_EXECUTION_TIME, EMULATE_EXACT_FAILURE_CAUSE, EMULATE_FAILURE_ALWAYS,
+    OPERATOR_BYPASS,
+};
+
+pub fn model(sp_id: &str, state: &State) -> (Model, State) {
+    let state = state.clone();
+    let auto_transitions = vec![];
+    let sops = vec![];
+    let mut operations = vec![];
+
+    let failed = bv!(&&format!("failed"));
+    let state = state.add(
+        assign!(failed, SPValue::Bool(BoolOrUnknown::Bool(false))),
+        "emulator",
+    );
+
+    operations.push(Operation::new(
+        "gantry_unlock",
+        None,
+        None,
+        None,  // If there is to be a failure retry, we need a failure transition
+        None,  // If there is to be a timeout retry, we need a timeout transition
+        false, // We can add bypass transitions, but usually not necessary to bypass
+        Vec::from([Transition::parse(
+            "start_gantry_unlock",
+            "var:gantry_request_state == initial \
+                && var:gantry_request_trigger == false",
+            "true",
+            vec![
+                &format!("var:gantry_command_command <- unlock"),
+                "var:gantry_request_trigger <- true",
+            ],
+            Vec::<&str>::new(),
+            &state,
+        )]),
+        Vec::from([Transition::parse(
+            "complete_gantry_unlock",
+            "true",
+            "var:gantry_request_state == succeeded",
+            vec![
+                "var:gantry_request_trigger <- false",
+                "var:gantry_request_state <- initial",
+                "var:gantry_locked_estimated <- false",
+            ],
+            Vec::<&str>::new(),
+            &state,
+        )]),
+        Vec::from([Transition::parse(
+            "failed_gantry_unlock",
+            "true",
+            "var:gantry_request_state == failed",
+            vec![
+                "var:gantry_request_trigger <- false",
+                "var:gantry_request_state <- initial",
+                "var:failed <- true",
+            ],
+            Vec::<&str>::new(),
+            &state,
+        )]),
+        Vec::from([]),
+        Vec::from([]),
+        Vec::from([]),
+    ));
+
+    let model = Model::new(sp_id, auto_transitions, vec![], sops, operations);
+
+    (model, state)
+}
+
+pub async fn run_emultaion(
+    sp_id: &str,
+    mut con: MultiplexedConnection,
+) -> Result<(), Box<dyn Error>> {
+    initialize_env_logger();
+    let goal = "var:gantry_locked_estimated == false".to_string();
+    let uq_goal = goal_string_to_sp_value(&goal, running::goal_runner::GoalPriority::Normal);
+    let scheduled_goals = vec![uq_goal].to_spvalue();
+
+    crate::emulated_sleep(std::time::Duration::from_millis(500)).await;
+
+    if let Some(state) = StateManager::get_full_state(&mut con).await {
+        let new_state = state
+            .update(
+                "gantry_emulate_execution_time",
+                EMULATE_EXACT_EXECUTION_TIME.to_spvalue(),
+            )
+            .update("gantry_emulated_execution_time", 300.to_spvalue())
+            .update(
+                "gantry_emulate_failure_rate",
+                EMULATE_FAILURE_ALWAYS.to_spvalue(),
+            )
+            .update(
+                "gantry_emulate_failure_cause",
+                EMULATE_EXACT_FAILURE_CAUSE.to_spvalue(),
+            )
+            .update(
+                "gantry_emulated_failure_cause",
+                vec!["collision"].to_spvalue(),
+            )
+            .update("gantry_locked_estimated", true.to_spvalue())
+            // The operator needs a second to walk up to the cell and bypass the failed unlock
+            .update(
+                "operator_watched_operations",
+                vec!["op_gantry_unlock"].to_spvalue(),
+            )
+            .update("operator_policy", OPERATOR_BYPASS.to_spvalue())
+            .update(
+                "operator_emulate_reaction_time",
+                EMULATE_EXACT_EXECUTION_TIME.to_spvalue(),
+            )
+            .update("operator_emulated_reaction_time", 1000.to_spvalue())
+            .update(&format!("{sp_id}_scheduled_goals"), scheduled_goals);
+
+        let modified_state = state.get_diff_partial_state(&new_state);
+        StateManager::set_state(&mut con, &modified_state).await;
+    }
+
+    Ok(())
+}
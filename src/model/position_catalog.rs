@@ -0,0 +1,151 @@
+use micro_sp::{running::goal_runner::goal_string_to_sp_value, *};
+use redis::aio::MultiplexedConnection;
+use std::error::Error;
+
+use crate::{DONT_EMULATE_FAILURE, EMULATE_EXACT_EXECUTION_TIME};
+
+// First try to move to a misspelled position, which the robot refuses, then move to the
+// right one. The moves are generated from the position catalog of the robot in the state.
+pub fn model(sp_id: &str, state: &State) -> (Model, State) {
+    let state = state.clone();
+    let auto_transitions = vec![];
+    let sops = vec![];
+    let mut operations = vec![];
+
+    let refused = bv!(&&format!("robot_refused_position"));
+    let state = state.add(
+        assign!(refused, SPValue::Bool(BoolOrUnknown::Bool(false))),
+        "emulator",
+    );
+
+    operations.push(Operation::new(
+        "robot_move_to_pipe_blu_box",
+        None,
+        None,
+        None,
+        None,
+        false,
+        Vec::from([Transition::parse(
+            "start_robot_move_to_pipe_blu_box",
+            "var:robot_request_state == initial \
+                && var:robot_request_trigger == false \
+                && var:robot_refused_position == false",
+            "true",
+            vec![
+                "var:robot_command_command <- move",
+                "var:robot_position_command <- pipe_blu_box",
+                "var:robot_speed_command <- 0.5",
+                "var:robot_request_trigger <- true",
+            ],
+            Vec::<&str>::new(),
+            &state,
+        )]),
+        // Failing is the expected outcome here
+        Vec::from([Transition::parse(
+            "complete_robot_move_to_pipe_blu_box",
+            "true",
+            "var:robot_request_state == failed",
+            vec![
+                "var:robot_request_trigger <- false",
+                "var:robot_request_state <- initial",
+                "var:robot_refused_position <- true",
+            ],
+            Vec::<&str>::new(),
+            &state,
+        )]),
+        Vec::from([]),
+        Vec::from([]),
+        Vec::from([]),
+        Vec::from([]),
+    ));
+
+    let positions: Vec<String> = state
+        .get_array_or_default_to_empty("robot_emulated_positions", "position_catalog")
+        .iter()
+        .filter(|val| val.is_string())
+        .map(|y| y.to_string())
+        .collect();
+
+    for pos in positions {
+        operations.push(Operation::new(
+            &format!("robot_move_to_{pos}"),
+            None,
+            None,
+            None,
+            None,
+            false,
+            Vec::from([Transition::parse(
+                &format!("start_robot_move_to_{pos}"),
+                &format!(
+                    "var:robot_request_state == initial \
+                    && var:robot_request_trigger == false \
+                    && var:robot_position_estimated != {pos}"
+                ),
+                "true",
+                vec![
+                    "var:robot_command_command <- move",
+                    &format!("var:robot_position_command <- {pos}"),
+                    "var:robot_speed_command <- 0.5",
+                    "var:robot_request_trigger <- true",
+                ],
+                Vec::<&str>::new(),
+                &state,
+            )]),
+            Vec::from([Transition::parse(
+                &format!("complete_robot_move_to_{pos}"),
+                "true",
+                "var:robot_request_state == succeeded",
+                vec![
+                    "var:robot_request_trigger <- false",
+                    "var:robot_request_state <- initial",
+                    &format!("var:robot_position_estimated <- {pos}"),
+                ],
+                Vec::<&str>::new(),
+                &state,
+            )]),
+            Vec::from([]),
+            Vec::from([]),
+            Vec::from([]),
+            Vec::from([]),
+        ));
+    }
+
+    let model = Model::new(sp_id, auto_transitions, vec![], sops, operations);
+
+    (model, state)
+}
+
+pub async fn run_emultaion(
+    sp_id: &str,
+    mut con: MultiplexedConnection,
+) -> Result<(), Box<dyn Error>> {
+    initialize_env_logger();
+    let goal_refused = "var:robot_refused_position == true".to_string();
+    let goal_right = "var:robot_position_estimated == pipe_blue_box".to_string();
+    let uq_goal_refused =
+        goal_string_to_sp_value(&goal_refused, running::goal_runner::GoalPriority::Normal);
+    let uq_goal_right =
+        goal_string_to_sp_value(&goal_right, running::goal_runner::GoalPriority::Normal);
+    let scheduled_goals = vec![uq_goal_refused, uq_goal_right].to_spvalue();
+
+    crate::emulated_sleep(std::time::Duration::from_millis(500)).await;
+
+    if let Some(state) = StateManager::get_full_state(&mut con).await {
+        let new_state = state
+            .update(
+                "robot_emulate_execution_time",
+                EMULATE_EXACT_EXECUTION_TIME.to_spvalue(),
+            )
+            .update("robot_emulated_execution_time", 1000.to_spvalue())
+            .update(
+                "robot_emulate_failure_rate",
+                DONT_EMULATE_FAILURE.to_spvalue(),
+            )
+            .update(&format!("{sp_id}_scheduled_goals"), scheduled_goals);
+
+        let modified_state = state.get_diff_partial_state(&new_state);
+        StateManager::set_state(&mut con, &modified_state).await;
+    }
+
+    Ok(())
+}
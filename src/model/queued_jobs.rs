@@ -0,0 +1,55 @@
+use micro_sp::*;
+use redis::aio::MultiplexedConnection;
+use std::error::Error;
+
+use crate::{DONT_EMULATE_FAILURE, EMULATE_EXACT_EXECUTION_TIME};
+
+// No operations here, the gantry gets a batch of jobs through its job queue instead,
+// the way a PLC job queue would be fed.
+pub fn model(sp_id: &str, state: &State) -> (Model, State) {
+    let state = state.clone();
+    let auto_transitions = vec![];
+    let sops = vec![];
+    let operations = vec![];
+
+    let model = Model::new(sp_id, auto_transitions, vec![], sops, operations);
+
+    (model, state)
+}
+
+pub async fn run_emultaion(
+    _sp_id: &str,
+    mut con: MultiplexedConnection,
+) -> Result<(), Box<dyn Error>> {
+    initialize_env_logger();
+
+    // Three jobs, but only room for two
+    let jobs = vec![
+        r#"{"id": "job_1", "command_command": "move", "position_command": "a", "speed_command": 0.5}"#,
+        r#"{"id": "job_2", "command_command": "calibrate"}"#,
+        r#"{"id": "job_3", "command_command": "move", "position_command": "b", "speed_command": 0.5}"#,
+    ];
+
+    crate::emulated_sleep(std::time::Duration::from_millis(500)).await;
+
+    if let Some(state) = StateManager::get_full_state(&mut con).await {
+        let new_state = state
+            .update(
+                "gantry_emulate_execution_time",
+                EMULATE_EXACT_EXECUTION_TIME.to_spvalue(),
+            )
+            .update("gantry_emulated_execution_time", 300.to_spvalue())
+            .update(
+                "gantry_emulate_failure_rate",
+                DONT_EMULATE_FAILURE.to_spvalue(),
+            )
+            .update("gantry_emulate_queue", true.to_spvalue())
+            .update("gantry_emulated_queue_capacity", 2.to_spvalue())
+            .update("gantry_queue_command", jobs.to_spvalue());
+
+        let modified_state = state.get_diff_partial_state(&new_state);
+        StateManager::set_state(&mut con, &modified_state).await;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,200 @@
+use micro_sp::{running::goal_runner::goal_string_to_sp_value, *};
+use redis::aio::MultiplexedConnection;
+use std::error::Error;
+
+use crate::{DONT_EMULATE_FAILURE, EMULATE_EXACT_EXECUTION_TIME};
+
+// The gantry is shared with another cell controller, "other_sp", which holds the
+// reservation when we start. We queue up, wait for the handover, use the gantry and release it.
+pub fn model(sp_id: &str, state: &State) -> (Model, State) {
+    let state = state.clone();
+    let auto_transitions = vec![];
+    let sops = vec![];
+    let mut operations = vec![];
+
+    let done = bv!(&&format!("gantry_done"));
+    let state = state.add(
+        assign!(done, SPValue::Bool(BoolOrUnknown::Bool(false))),
+        "emulator",
+    );
+
+    operations.push(Operation::new(
+        "gantry_reserve",
+        None,
+        None,
+        None,
+        None,
+        false,
+        Vec::from([Transition::parse(
+            "start_gantry_reserve",
+            &format!(
+                "var:gantry_request_state == initial \
+                && var:gantry_request_trigger == false \
+                && var:gantry_owner_estimated != {sp_id}"
+            ),
+            "true",
+            vec![
+                &format!("var:gantry_owner_command <- {sp_id}"),
+                "var:gantry_command_command <- reserve",
+                "var:gantry_request_trigger <- true",
+            ],
+            Vec::<&str>::new(),
+            &state,
+        )]),
+        // A rejected reservation still puts us in the queue, so we just wait to be handed over
+        Vec::from([Transition::parse(
+            "complete_gantry_reserve",
+            "true",
+            &format!(
+                "var:gantry_request_trigger == false \
+                && var:gantry_owner_estimated == {sp_id}"
+            ),
+            vec![
+                "var:gantry_request_state <- initial",
+                &format!("var:gantry_owner_estimated <- {sp_id}"),
+            ],
+            Vec::<&str>::new(),
+            &state,
+        )]),
+        Vec::from([]),
+        Vec::from([]),
+        Vec::from([]),
+        Vec::from([]),
+    ));
+
+    operations.push(Operation::new(
+        "gantry_move_to_a",
+        None,
+        None,
+        None,
+        None,
+        false,
+        Vec::from([Transition::parse(
+            "start_gantry_move_to_a",
+            &format!(
+                "var:gantry_request_state == initial \
+                && var:gantry_request_trigger == false \
+                && var:gantry_owner_estimated == {sp_id} \
+                && var:gantry_position_estimated != a"
+            ),
+            "true",
+            vec![
+                &format!("var:gantry_owner_command <- {sp_id}"),
+                "var:gantry_command_command <- move",
+                "var:gantry_position_command <- a",
+                "var:gantry_speed_command <- 0.5",
+                "var:gantry_request_trigger <- true",
+            ],
+            Vec::<&str>::new(),
+            &state,
+        )]),
+        Vec::from([Transition::parse(
+            "complete_gantry_move_to_a",
+            "true",
+            "var:gantry_request_state == succeeded",
+            vec![
+                "var:gantry_request_trigger <- false",
+                "var:gantry_request_state <- initial",
+                "var:gantry_position_estimated <- a",
+            ],
+            Vec::<&str>::new(),
+            &state,
+        )]),
+        Vec::from([]),
+        Vec::from([]),
+        Vec::from([]),
+        Vec::from([]),
+    ));
+
+    operations.push(Operation::new(
+        "gantry_release",
+        None,
+        None,
+        None,
+        None,
+        false,
+        Vec::from([Transition::parse(
+            "start_gantry_release",
+            &format!(
+                "var:gantry_request_state == initial \
+                && var:gantry_request_trigger == false \
+                && var:gantry_owner_estimated == {sp_id} \
+                && var:gantry_position_estimated == a"
+            ),
+            "true",
+            vec![
+                &format!("var:gantry_owner_command <- {sp_id}"),
+                "var:gantry_command_command <- release",
+                "var:gantry_request_trigger <- true",
+            ],
+            Vec::<&str>::new(),
+            &state,
+        )]),
+        Vec::from([Transition::parse(
+            "complete_gantry_release",
+            "true",
+            "var:gantry_request_state == succeeded",
+            vec![
+                "var:gantry_request_trigger <- false",
+                "var:gantry_request_state <- initial",
+                "var:gantry_done <- true",
+            ],
+            Vec::<&str>::new(),
+            &state,
+        )]),
+        Vec::from([]),
+        Vec::from([]),
+        Vec::from([]),
+        Vec::from([]),
+    ));
+
+    let model = Model::new(sp_id, auto_transitions, vec![], sops, operations);
+
+    (model, state)
+}
+
+pub async fn run_emultaion(
+    sp_id: &str,
+    mut con: MultiplexedConnection,
+) -> Result<(), Box<dyn Error>> {
+    initialize_env_logger();
+    let goal = "var:gantry_done == true".to_string();
+    let uq_goal = goal_string_to_sp_value(&goal, running::goal_runner::GoalPriority::Normal);
+    let scheduled_goals = vec![uq_goal].to_spvalue();
+
+    crate::emulated_sleep(std::time::Duration::from_millis(500)).await;
+
+    if let Some(state) = StateManager::get_full_state(&mut con).await {
+        let new_state = state
+            .update(
+                "gantry_emulate_execution_time",
+                EMULATE_EXACT_EXECUTION_TIME.to_spvalue(),
+            )
+            .update("gantry_emulated_execution_time", 300.to_spvalue())
+            .update(
+                "gantry_emulate_failure_rate",
+                DONT_EMULATE_FAILURE.to_spvalue(),
+            )
+            .update("gantry_owner_estimated", "other_sp".to_spvalue())
+            .update(&format!("{sp_id}_scheduled_goals"), scheduled_goals);
+
+        let modified_state = state.get_diff_partial_state(&new_state);
+        StateManager::set_state(&mut con, &modified_state).await;
+    }
+
+    // The other cell is done with the gantry after a while and releases it
+    crate::emulated_sleep(std::time::Duration::from_millis(3000)).await;
+
+    if let Some(state) = StateManager::get_full_state(&mut con).await {
+        let new_state = state
+            .update("gantry_owner_command", "other_sp".to_spvalue())
+            .update("gantry_command_command", "release".to_spvalue())
+            .update("gantry_request_state", "initial".to_spvalue())
+            .update("gantry_request_trigger", true.to_spvalue());
+
+        let modified_state = state.get_diff_partial_state(&new_state);
+        StateManager::set_state(&mut con, &modified_state).await;
+    }
+
+    Ok(())
+}
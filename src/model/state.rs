@@ -1,7 +1,4 @@
-use std::time::SystemTime;
-
 use micro_sp::*;
-use ordered_float::OrderedFloat;
 
 fn generate_basic_variables(name: &str, state: &State) -> State {
     let resource_online = bv!(&&format!("{}_resource_online", name));
@@ -10,6 +7,17 @@ fn generate_basic_variables(name: &str, state: &State) -> State {
     let total_fail_counter = iv!(&&format!("{}_total_fail_counter", name));
     let subsequent_fail_counter = iv!(&&format!("{}_subsequent_fail_counter", name));
     let ref_counter = iv!(&&format!("{}_ref_counter", name));
+    let owner_command = v!(&&format!("{}_owner_command", name));
+    let owner_estimated = v!(&&format!("{}_owner_estimated", name));
+    let owner_queue_estimated = av!(&&format!("{}_owner_queue_estimated", name));
+    let progress = iv!(&&format!("{}_progress", name));
+    let remaining_time = iv!(&&format!("{}_remaining_time", name));
+    let queue_command = av!(&&format!("{}_queue_command", name));
+    let queue_consumed_estimated = iv!(&&format!("{}_queue_consumed_estimated", name));
+    let queue_status_estimated = av!(&&format!("{}_queue_status_estimated", name));
+    let capabilities = v!(&&format!("{}_capabilities", name));
+    let downtime_estimated = bv!(&&format!("{}_downtime_estimated", name));
+    let mode_estimated = v!(&&format!("{}_mode_estimated", name));
 
     let state = state.add(assign!(resource_online, false.to_spvalue()), "emulator");
     let state = state.add(assign!(request_trigger, false.to_spvalue()), "emulator");
@@ -18,6 +26,56 @@ fn generate_basic_variables(name: &str, state: &State) -> State {
     let state = state.add(assign!(subsequent_fail_counter, 0.to_spvalue()), "emulator");
     let state = state.add(assign!(ref_counter, 1.to_spvalue()), "emulator");
 
+    // Nobody owns the resource until someone sends "reserve" with an owner_command
+    let state = state.add(
+        assign!(owner_command, SPValue::String(StringOrUnknown::UNKNOWN)),
+        "emulator",
+    );
+    let state = state.add(
+        assign!(owner_estimated, SPValue::String(StringOrUnknown::UNKNOWN)),
+        "emulator",
+    );
+    let state = state.add(
+        assign!(
+            owner_queue_estimated,
+            SPValue::Array(ArrayOrUnknown::Array(vec![]))
+        ),
+        "emulator",
+    );
+
+    // Progress of the running command, 0..100, and its remaining time in milliseconds
+    let state = state.add(assign!(progress, 0.to_spvalue()), "emulator");
+    let state = state.add(assign!(remaining_time, 0.to_spvalue()), "emulator");
+
+    // Only used when the resource is emulated with a job queue
+    let state = state.add(
+        assign!(queue_command, SPValue::Array(ArrayOrUnknown::Array(vec![]))),
+        "emulator",
+    );
+    let state = state.add(
+        assign!(queue_consumed_estimated, 0.to_spvalue()),
+        "emulator",
+    );
+    let state = state.add(
+        assign!(
+            queue_status_estimated,
+            SPValue::Array(ArrayOrUnknown::Array(vec![]))
+        ),
+        "emulator",
+    );
+
+    // Whether the resource is in one of its downtime windows, see emulators/downtime.rs
+    let state = state.add(assign!(downtime_estimated, false.to_spvalue()), "emulator");
+
+    // "normal" or "degraded", see emulators/degraded.rs
+    let state = state.add(assign!(mode_estimated, "normal".to_spvalue()), "emulator");
+
+    // Published by the emulator as a JSON string, see emulators/capabilities.rs
+    let state = state.add(
+        assign!(capabilities, SPValue::String(StringOrUnknown::UNKNOWN)),
+        "emulator",
+    );
+
     state
 }
 
@@ -73,6 +131,58 @@ fn generate_emulation_variables(name: &str, state: &State) -> State {
         "emulator",
     );
 
+    // # DONT_EMULATE_DOWNTIME: The downtime windows are ignored, the resource is always available
+    // # EMULATE_DOWNTIME_MAINTENANCE: During a downtime window, every command fails with "maintenance"
+    // # EMULATE_DOWNTIME_OFFLINE: During a downtime window, the resource goes offline and leaves requests pending
+    // uint8 emulate_downtime
+    // string[] emulated_downtime_windows # ms since the emulator started, "from-to" or "period/duration"
+    let emulate_downtime = iv!(&&format!("{}_emulate_downtime", name));
+    let emulated_downtime_windows = av!(&&format!("{}_emulated_downtime_windows", name));
+    let state = state.add(assign!(emulate_downtime, 0.to_spvalue()), "emulator");
+    let state = state.add(
+        assign!(
+            emulated_downtime_windows,
+            SPValue::Array(ArrayOrUnknown::Array(vec![]))
+        ),
+        "emulator",
+    );
+
+    // string[] emulated_degrading_causes # Failing with one of these causes puts the resource in degraded mode
+    // float32 emulated_degraded_time_factor # Execution times are multiplied with this in degraded mode
+    // string[] emulated_degraded_refused_commands # These commands fail with "degraded" in degraded mode
+    let emulated_degrading_causes = av!(&&format!("{}_emulated_degrading_causes", name));
+    let emulated_degraded_time_factor = fv!(&&format!("{}_emulated_degraded_time_factor", name));
+    let emulated_degraded_refused_commands =
+        av!(&&format!("{}_emulated_degraded_refused_commands", name));
+    let state = state.add(
+        assign!(
+            emulated_degrading_causes,
+            SPValue::Array(ArrayOrUnknown::Array(vec![]))
+        ),
+        "emulator",
+    );
+    let state = state.add(
+        assign!(emulated_degraded_time_factor, 2.0.to_spvalue()),
+        "emulator",
+    );
+    let state = state.add(
+        assign!(
+            emulated_degraded_refused_commands,
+            SPValue::Array(ArrayOrUnknown::Array(vec![]))
+        ),
+        "emulator",
+    );
+
+    // bool emulate_queue # Serve "queue_command" one job at a time through the request slot
+    // int32 emulated_queue_capacity # Jobs beyond the capacity are rejected
+    let emulate_queue = bv!(&&format!("{}_emulate_queue", name));
+    let emulated_queue_capacity = iv!(&&format!("{}_emulated_queue_capacity", name));
+    let state = state.add(assign!(emulate_queue, false.to_spvalue()), "emulator");
+    let state = state.add(
+        assign!(emulated_queue_capacity, 10.to_spvalue()),
+        "emulator",
+    );
+
     let emulate_mounted_tool = bv!(&&format!("{}_emulate_mounted_tool", name));
     let emulated_mounted_tool = v!(&&format!("{}_emulated_mounted_tool", name));
     let state = state.add(
@@ -90,9 +200,102 @@ fn generate_emulation_variables(name: &str, state: &State) -> State {
     state
 }
 
-pub fn state() -> State {
-    let state = State::new();
+fn generate_motion_variables(name: &str, positions: &[&str], state: &State) -> State {
+    // -----------------------------------------------------------------------
+    // Moves to positions outside of "emulated_positions" fail with "unknown_position",
+    // an empty catalog accepts every position.
+    // string[] emulated_positions
+    // Motions take "emulated_execution_time" at NOMINAL_SPEED without payload,
+    // faster motions take less time and a payload makes them take longer.
+    // float32 payload_estimated # kg, if known, 0.0 otherwise
+    // float32 emulated_max_payload # kg
+    // int32 emulated_motion_failure_rate # percentage 0..100 added at MAX_SPEED and at the max payload
+    // -----------------------------------------------------------------------
+
+    let emulated_positions = av!(&&format!("{}_emulated_positions", name));
+    let payload_estimated = fv!(&&format!("{}_payload_estimated", name));
+    let emulated_max_payload = fv!(&&format!("{}_emulated_max_payload", name));
+    let emulated_motion_failure_rate = iv!(&&format!("{}_emulated_motion_failure_rate", name));
+
+    let state = state.add(
+        assign!(emulated_positions, positions.to_vec().to_spvalue()),
+        "emulator",
+    );
+    let state = state.add(assign!(payload_estimated, 0.0.to_spvalue()), "emulator");
+    let state = state.add(assign!(emulated_max_payload, 10.0.to_spvalue()), "emulator");
+    let state = state.add(
+        assign!(emulated_motion_failure_rate, 0.to_spvalue()),
+        "emulator",
+    );
+
+    state
+}
+
+fn generate_measurement_variables(measured: &str, state: &State) -> State {
+    // -----------------------------------------------------------------------
+    // float32 emulated_noise # uniform noise in [-noise, noise] added to numeric readings
+    // int32 emulated_delay # milliseconds before the reading is published
+    // int32 emulated_dropout_rate # percentage 0..100 of readings that come out as UNKNOWN
+    // -----------------------------------------------------------------------
+
+    let emulated_noise = fv!(&&format!("{}_emulated_noise", measured));
+    let emulated_delay = iv!(&&format!("{}_emulated_delay", measured));
+    let emulated_dropout_rate = iv!(&&format!("{}_emulated_dropout_rate", measured));
+
+    let state = state.add(assign!(emulated_noise, 0.0.to_spvalue()), "emulator");
+    let state = state.add(assign!(emulated_delay, 0.to_spvalue()), "emulator");
+    let state = state.add(assign!(emulated_dropout_rate, 0.to_spvalue()), "emulator");
+
+    state
+}
 
+fn generate_operator_variables(state: &State) -> State {
+    // -----------------------------------------------------------------------
+    // Operator:
+    // string[] watched_operations # Operations the operator keeps an eye on
+    // int32 policy # OPERATOR_ACKNOWLEDGE, OPERATOR_BYPASS, OPERATOR_RETRY, OPERATOR_ABORT
+    // The reaction time uses the same modes as the execution time,
+    // and the mistake rate uses the same modes as the failure rate.
+    // -----------------------------------------------------------------------
+
+    let watched_operations = av!("operator_watched_operations");
+    let policy = iv!("operator_policy");
+    let intervention_counter = iv!("operator_intervention_counter");
+    let last_operation = v!("operator_last_operation");
+    let last_action = v!("operator_last_action");
+
+    let state = state.add(
+        assign!(
+            watched_operations,
+            SPValue::Array(ArrayOrUnknown::Array(vec![]))
+        ),
+        "emulator",
+    );
+    let state = state.add(assign!(policy, 0.to_spvalue()), "emulator");
+    let state = state.add(assign!(intervention_counter, 0.to_spvalue()), "emulator");
+    let state = state.add(
+        assign!(last_operation, SPValue::String(StringOrUnknown::UNKNOWN)),
+        "emulator",
+    );
+    let state = state.add(
+        assign!(last_action, SPValue::String(StringOrUnknown::UNKNOWN)),
+        "emulator",
+    );
+
+    let emulate_reaction_time = iv!("operator_emulate_reaction_time");
+    let emulated_reaction_time = iv!("operator_emulated_reaction_time");
+    let emulate_mistake_rate = iv!("operator_emulate_mistake_rate");
+    let emulated_mistake_rate = iv!("operator_emulated_mistake_rate");
+
+    let state = state.add(assign!(emulate_reaction_time, 0.to_spvalue()), "emulator");
+    let state = state.add(assign!(emulated_reaction_time, 0.to_spvalue()), "emulator");
+    let state = state.add(assign!(emulate_mistake_rate, 0.to_spvalue()), "emulator");
+    let state = state.add(assign!(emulated_mistake_rate, 0.to_spvalue()), "emulator");
+
+    state
+}
+
+pub fn generate_gantry_variables(name: &str, state: &State) -> State {
     // -----------------------------------------------------------------------
     // Gantry:
     // string command # move, calibrate, lock, unlock
@@ -100,13 +303,12 @@ pub fn state() -> State {
     // string position
     // -----------------------------------------------------------------------
 
-    let state = generate_basic_variables("gantry", &state);
+    let state = generate_basic_variables(name, state);
 
-    let gantry_command_command = v!("gantry_command_command");
-    let gantry_speed_command = fv!("gantry_speed_command");
-    let gantry_position_command = v!("gantry_position_command");
-    let gantry_light_indicator = bv!("gantry_light_indicator");
-    let blinked = bv!("blinked");
+    let gantry_command_command = v!(&&format!("{}_command_command", name));
+    let gantry_speed_command = fv!(&&format!("{}_speed_command", name));
+    let gantry_position_command = v!(&&format!("{}_position_command", name));
+    let gantry_light_indicator = bv!(&&format!("{}_light_indicator", name));
 
     let state = state.add(
         assign!(
@@ -131,16 +333,11 @@ pub fn state() -> State {
         "emulator",
     );
 
-    let state = state.add(
-        assign!(blinked, SPValue::Bool(BoolOrUnknown::Bool(false))),
-        "emulator",
-    );
-
     // We estimate (memory variables) the following, since we cannot directly measure
-    let gantry_speed_measured = fv!("gantry_speed_estimated");
-    let gantry_position_estimated = v!("gantry_position_estimated");
-    let gantry_calibrated_estimated = bv!("gantry_calibrated_estimated");
-    let gantry_locked_estimated = bv!("gantry_locked_estimated");
+    let gantry_speed_measured = fv!(&&format!("{}_speed_estimated", name));
+    let gantry_position_estimated = v!(&&format!("{}_position_estimated", name));
+    let gantry_calibrated_estimated = bv!(&&format!("{}_calibrated_estimated", name));
+    let gantry_locked_estimated = bv!(&&format!("{}_locked_estimated", name));
 
     let state = state.add(
         assign!(
@@ -172,20 +369,28 @@ pub fn state() -> State {
     );
 
     // Optional: emulate gantry failure and execution time
-    let state = generate_emulation_variables("gantry", &state);
+    let state = generate_emulation_variables(name, &state);
+    let state = generate_motion_variables(name, &crate::GANTRY_POSITIONS, &state);
 
+    state
+}
+
+pub fn generate_robot_variables(name: &str, state: &State) -> State {
     // -----------------------------------------------------------------------
     // Robot:
-    // string command # move, pick, place
+    // string command # move, pick, place, ..., move_through, pick_and_place
     // float32 speed
     // string position
+    // string[] waypoints # for move_through
+    // string from # for pick_and_place
+    // string to # for pick_and_place
     // -----------------------------------------------------------------------
 
-    let state = generate_basic_variables("robot", &state);
+    let state = generate_basic_variables(name, state);
 
-    let robot_command_command = v!("robot_command_command");
-    let robot_speed_command = fv!("robot_speed_command");
-    let robot_position_command = v!("robot_position_command");
+    let robot_command_command = v!(&&format!("{}_command_command", name));
+    let robot_speed_command = fv!(&&format!("{}_speed_command", name));
+    let robot_position_command = v!(&&format!("{}_position_command", name));
 
     let state = state.add(
         assign!(
@@ -203,38 +408,88 @@ pub fn state() -> State {
         "emulator",
     );
 
+    let robot_waypoints_command = av!(&&format!("{}_waypoints_command", name));
+    let robot_from_command = v!(&&format!("{}_from_command", name));
+    let robot_to_command = v!(&&format!("{}_to_command", name));
+    let robot_failed_step_estimated = v!(&&format!("{}_failed_step_estimated", name));
+    let state = state.add(
+        assign!(
+            robot_waypoints_command,
+            SPValue::Array(ArrayOrUnknown::Array(vec![]))
+        ),
+        "emulator",
+    );
+    let state = state.add(
+        assign!(
+            robot_from_command,
+            SPValue::String(StringOrUnknown::UNKNOWN)
+        ),
+        "emulator",
+    );
+    let state = state.add(
+        assign!(robot_to_command, SPValue::String(StringOrUnknown::UNKNOWN)),
+        "emulator",
+    );
+    // Which step of a composite command failed, as "index:command:position"
+    let state = state.add(
+        assign!(
+            robot_failed_step_estimated,
+            SPValue::String(StringOrUnknown::UNKNOWN)
+        ),
+        "emulator",
+    );
+
     // We estimate (memory variables) the following, since we cannot directly measure
-    let robot_speed_measured = fv!("robot_speed_estimated");
-    let robot_position_estimated = v!("robot_position_estimated");
-    let robot_mounted_estimated = v!("robot_mounted_estimated"); // gripper, vacuum
-    let robot_mounted_checked = bv!("robot_mounted_checked");
-    let robot_mounted_one_time_measured = v!("robot_mounted_one_time_measured");
+    let robot_speed_measured = fv!(&&format!("{}_speed_estimated", name));
+    let robot_position_estimated = v!(&&format!("{}_position_estimated", name));
+    let robot_mounted_estimated = v!(&&format!("{}_mounted_estimated", name)); // gripper, vacuum
+    let robot_mounted_checked = bv!(&&format!("{}_mounted_checked", name));
+    let robot_mounted_one_time_measured = v!(&&format!("{}_mounted_one_time_measured", name));
+
+    // The tool the emulated robot actually has mounted, "none" after an unmount. Stays unknown
+    // until the first mount or unmount, check_mounted_tool reports it from then on.
+    let robot_mounted_tool = v!(&&format!("{}_mounted_tool", name));
+    let state = state.add(
+        assign!(
+            robot_mounted_tool,
+            SPValue::String(StringOrUnknown::UNKNOWN)
+        ),
+        "emulator",
+    );
 
-    let test_frame_1 = tfv!("test_frame_1");
+    // The robot frame tree: world -> base -> tcp -> tool.
+    // The emulator moves tcp on every move and attaches or detaches the tool on mount and unmount.
+    let robot_base_frame = tfv!(&&format!("{}_base_frame", name));
+    let robot_tcp_frame = tfv!(&&format!("{}_tcp_frame", name));
+    let robot_tool_frame = tfv!(&&format!("{}_tool_frame", name));
+    let state = state.add(
+        assign!(
+            robot_base_frame,
+            crate::robot_frame("world", &format!("{}_base", name), (0.0, 0.0, 0.0), true)
+        ),
+        "emulator",
+    );
     let state = state.add(
         assign!(
-            test_frame_1,
-            SPValue::Transform(TransformOrUnknown::Transform(SPTransformStamped {
-                active_transform: true,
-                enable_transform: true,
-                time_stamp: SystemTime::now(),
-                parent_frame_id: "a".to_string(),
-                child_frame_id: "b".to_string(),
-                transform: SPTransform {
-                    translation: SPTranslation {
-                        x: OrderedFloat::from(1.23),
-                        y: OrderedFloat::from(1.23),
-                        z: OrderedFloat::from(1.23),
-                    },
-                    rotation: SPRotation {
-                        x: OrderedFloat::from(1.23),
-                        y: OrderedFloat::from(1.23),
-                        z: OrderedFloat::from(1.23),
-                        w: OrderedFloat::from(1.23),
-                    }
-                },
-                metadata: MapOrUnknown::UNKNOWN
-            }))
+            robot_tcp_frame,
+            crate::robot_frame(
+                &format!("{}_base", name),
+                &format!("{}_tcp", name),
+                crate::robot_position_to_translation("home"),
+                true
+            )
+        ),
+        "emulator",
+    );
+    let state = state.add(
+        assign!(
+            robot_tool_frame,
+            crate::robot_frame(
+                &format!("{}_tcp", name),
+                &format!("{}_tool", name),
+                (0.0, 0.0, 0.15),
+                false
+            )
         ),
         "emulator",
     );
@@ -274,12 +529,247 @@ pub fn state() -> State {
         ),
         "emulator",
     );
+    let state =
+        generate_measurement_variables(&format!("{}_mounted_one_time_measured", name), &state);
     // let state = state.add(assign!(asdf, SPValue::Bool(BoolOrUnknown::Bool(false))));
 
-    // let robot_mode_measured = v!("robot_mode_measured"); // safety_stop, emergency_stop, operational
+    // let robot_mode_measured = v!(&&format!("{}_mode_measured", name)); // safety_stop, emergency_stop, operational
 
     // Optional: emulate gantry failure and execution time
-    let state = generate_emulation_variables("robot", &state);
+    let state = generate_emulation_variables(name, &state);
+    let state = generate_motion_variables(name, &crate::ROBOT_POSITIONS, &state);
+
+    state
+}
+
+pub fn generate_nutrunner_variables(name: &str, state: &State) -> State {
+    // -----------------------------------------------------------------------
+    // Nutrunner:
+    // string command # tighten, loosen
+    // float32 target_torque # Nm
+    // float32 target_angle # degrees
+    // -----------------------------------------------------------------------
+
+    let state = generate_basic_variables(name, state);
+
+    let nutrunner_command_command = v!(&&format!("{}_command_command", name));
+    let nutrunner_target_torque_command = fv!(&&format!("{}_target_torque_command", name));
+    let nutrunner_target_angle_command = fv!(&&format!("{}_target_angle_command", name));
+
+    let state = state.add(
+        assign!(
+            nutrunner_command_command,
+            SPValue::String(StringOrUnknown::UNKNOWN)
+        ),
+        "emulator",
+    );
+    let state = state.add(
+        assign!(nutrunner_target_torque_command, 0.0.to_spvalue()),
+        "emulator",
+    );
+    let state = state.add(
+        assign!(nutrunner_target_angle_command, 0.0.to_spvalue()),
+        "emulator",
+    );
+
+    // The result record of the latest tightening or loosening, quality is "ok" or "nok"
+    let nutrunner_final_torque_measured = fv!(&&format!("{}_final_torque_measured", name));
+    let nutrunner_final_angle_measured = fv!(&&format!("{}_final_angle_measured", name));
+    let nutrunner_quality_measured = v!(&&format!("{}_quality_measured", name));
+    let nutrunner_torque_curve_measured = av!(&&format!("{}_torque_curve_measured", name));
+
+    let state = state.add(
+        assign!(
+            nutrunner_final_torque_measured,
+            SPValue::Float64(FloatOrUnknown::UNKNOWN)
+        ),
+        "emulator",
+    );
+    let state = state.add(
+        assign!(
+            nutrunner_final_angle_measured,
+            SPValue::Float64(FloatOrUnknown::UNKNOWN)
+        ),
+        "emulator",
+    );
+    let state = state.add(
+        assign!(
+            nutrunner_quality_measured,
+            SPValue::String(StringOrUnknown::UNKNOWN)
+        ),
+        "emulator",
+    );
+    let state = state.add(
+        assign!(
+            nutrunner_torque_curve_measured,
+            SPValue::Array(ArrayOrUnknown::Array(vec![]))
+        ),
+        "emulator",
+    );
+    let state = generate_measurement_variables(&format!("{}_final_torque_measured", name), &state);
+    let state = generate_measurement_variables(&format!("{}_final_angle_measured", name), &state);
+    let state = generate_measurement_variables(&format!("{}_quality_measured", name), &state);
+    let state = generate_measurement_variables(&format!("{}_torque_curve_measured", name), &state);
+
+    // Optional: emulate nutrunner failure and execution time
+    let state = generate_emulation_variables(name, &state);
+
+    // -----------------------------------------------------------------------
+    // # DONT_EMULATE_NOK: Every tightening is OK
+    // # EMULATE_NOK_ALWAYS: Every tightening is NOK
+    // # EMULATE_NOK_RANDOM_RATE: The tightening is randomly NOK with a "emulated_nok_rate" rate
+    // uint8 emulate_quality
+    // int32 emulated_nok_rate # percentage 0..100
+    // float32 emulated_torque_tolerance # percentage of the target torque, OK results are within it
+    // bool emulate_torque_curve # Also sample the torque curve
+    // int32 emulated_torque_curve_samples
+    // -----------------------------------------------------------------------
+
+    let nutrunner_emulate_quality = iv!(&&format!("{}_emulate_quality", name));
+    let nutrunner_emulated_nok_rate = iv!(&&format!("{}_emulated_nok_rate", name));
+    let nutrunner_emulated_torque_tolerance = fv!(&&format!("{}_emulated_torque_tolerance", name));
+    let nutrunner_emulate_torque_curve = bv!(&&format!("{}_emulate_torque_curve", name));
+    let nutrunner_emulated_torque_curve_samples =
+        iv!(&&format!("{}_emulated_torque_curve_samples", name));
+
+    let state = state.add(
+        assign!(nutrunner_emulate_quality, 0.to_spvalue()),
+        "emulator",
+    );
+    let state = state.add(
+        assign!(nutrunner_emulated_nok_rate, 0.to_spvalue()),
+        "emulator",
+    );
+    let state = state.add(
+        assign!(nutrunner_emulated_torque_tolerance, 5.0.to_spvalue()),
+        "emulator",
+    );
+    let state = state.add(
+        assign!(nutrunner_emulate_torque_curve, false.to_spvalue()),
+        "emulator",
+    );
+    let state = state.add(
+        assign!(nutrunner_emulated_torque_curve_samples, 20.to_spvalue()),
+        "emulator",
+    );
+
+    state
+}
+
+pub fn generate_agv_variables(name: &str, state: &State) -> State {
+    // -----------------------------------------------------------------------
+    // AGV:
+    // string command # go_to, dock
+    // string position
+    // -----------------------------------------------------------------------
+
+    let state = generate_basic_variables(name, state);
+
+    let agv_command_command = v!(&&format!("{}_command_command", name));
+    let agv_position_command = v!(&&format!("{}_position_command", name));
+
+    let state = state.add(
+        assign!(
+            agv_command_command,
+            SPValue::String(StringOrUnknown::UNKNOWN)
+        ),
+        "emulator",
+    );
+    let state = state.add(
+        assign!(
+            agv_position_command,
+            SPValue::String(StringOrUnknown::UNKNOWN)
+        ),
+        "emulator",
+    );
+
+    // The AGV starts docked and fully charged
+    let agv_position_estimated = v!(&&format!("{}_position_estimated", name));
+    let agv_docked_estimated = bv!(&&format!("{}_docked_estimated", name));
+    let agv_battery_level_measured = fv!(&&format!("{}_battery_level_measured", name));
+
+    let state = state.add(
+        assign!(agv_position_estimated, "charger".to_spvalue()),
+        "emulator",
+    );
+    let state = state.add(assign!(agv_docked_estimated, true.to_spvalue()), "emulator");
+    let state = state.add(
+        assign!(agv_battery_level_measured, 100.0.to_spvalue()),
+        "emulator",
+    );
+    let state = generate_measurement_variables(&format!("{}_battery_level_measured", name), &state);
+
+    // Optional: emulate AGV failure and execution time
+    let state = generate_emulation_variables(name, &state);
+
+    // -----------------------------------------------------------------------
+    // string[] emulated_routes # "from-to:travel_time_ms", can be driven in both directions, other stations are unknown positions
+    // string emulated_dock_station
+    // float32 emulated_battery_drain_rate # percent per second while driving
+    // float32 emulated_battery_charge_rate # percent per second while docked
+    // float32 emulated_low_battery_threshold # go_to fails with low_battery below this level
+    // float32 emulated_battery_level # the true level, which battery_level_measured reports
+    // -----------------------------------------------------------------------
+
+    let agv_emulated_routes = av!(&&format!("{}_emulated_routes", name));
+    let agv_emulated_dock_station = v!(&&format!("{}_emulated_dock_station", name));
+    let agv_emulated_battery_drain_rate = fv!(&&format!("{}_emulated_battery_drain_rate", name));
+    let agv_emulated_battery_charge_rate = fv!(&&format!("{}_emulated_battery_charge_rate", name));
+    let agv_emulated_low_battery_threshold =
+        fv!(&&format!("{}_emulated_low_battery_threshold", name));
+    let agv_emulated_battery_level = fv!(&&format!("{}_emulated_battery_level", name));
+
+    let state = state.add(
+        assign!(
+            agv_emulated_routes,
+            vec!["charger-a:4000", "a-b:3000", "b-c:3000", "c-charger:5000",].to_spvalue()
+        ),
+        "emulator",
+    );
+    let state = state.add(
+        assign!(agv_emulated_dock_station, "charger".to_spvalue()),
+        "emulator",
+    );
+    let state = state.add(
+        assign!(agv_emulated_battery_drain_rate, 1.0.to_spvalue()),
+        "emulator",
+    );
+    let state = state.add(
+        assign!(agv_emulated_battery_charge_rate, 5.0.to_spvalue()),
+        "emulator",
+    );
+    let state = state.add(
+        assign!(agv_emulated_low_battery_threshold, 20.0.to_spvalue()),
+        "emulator",
+    );
+    let state = state.add(
+        assign!(agv_emulated_battery_level, 100.0.to_spvalue()),
+        "emulator",
+    );
+
+    state
+}
+
+pub fn state() -> State {
+    let state = State::new();
+
+    let state = generate_gantry_variables("gantry", &state);
+
+    let blinked = bv!("blinked");
+    let state = state.add(
+        assign!(blinked, SPValue::Bool(BoolOrUnknown::Bool(false))),
+        "emulator",
+    );
+
+    let state = generate_robot_variables("robot", &state);
+    let state = generate_nutrunner_variables("nutrunner", &state);
+    let state = generate_agv_variables("agv", &state);
+
+    // -----------------------------------------------------------------------
+    // Operator: reacts to failed, timed out and fatal operations
+    // -----------------------------------------------------------------------
+
+    let state = generate_operator_variables(&state);
 
     state
 }
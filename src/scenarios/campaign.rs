@@ -0,0 +1,449 @@
+use micro_sp::*;
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::Duration;
+
+use crate::{Harness, HarnessOptions, NUMBER_OF_TEST_CASES, Scenario, Trace, parse_value};
+
+// Runs a scenario many times with emulation parameters drawn for every run, to see how
+// robust a model is before it goes to a real cell:
+//
+// let options = CampaignOptions {
+//     parameters: vec![CampaignParameter::parse("gantry_emulated_execution_time=100..3000")?],
+//     seed: Some(42),
+//     ..Default::default()
+// };
+// let report = run_campaign(scenario.as_ref(), &options).await;
+// println!("{report}");
+//
+// With a seed, run i draws its parameters with seed + i, and its emulators draw their
+// random failures and execution times from the same seed, so the same campaign runs the
+// same way again. The drawn parameters are applied once the injection of the scenario has
+// run, so that it doesn't overwrite them with its own emulation modes.
+
+// An emulation parameter that is drawn for every run.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CampaignParameter {
+    // One of the values, e.g. gantry_emulated_failure_cause=collision,violation
+    Choice(String, Vec<SPValue>),
+    // An integer in the range, bounds included, e.g. robot_emulated_execution_time=100..3000
+    IntRange(String, i64, i64),
+}
+
+impl CampaignParameter {
+    pub fn parse(parameter: &str) -> Result<CampaignParameter, String> {
+        let (key, values) = parameter
+            .split_once('=')
+            .map(|(key, values)| (key.trim(), values.trim()))
+            .filter(|(key, values)| !key.is_empty() && !values.is_empty())
+            .ok_or_else(|| format!("Invalid parameter '{parameter}', expected 'key=values'."))?;
+        if let Some((min, max)) = values.split_once("..") {
+            return match (min.trim().parse::<i64>(), max.trim().parse::<i64>()) {
+                (Ok(min), Ok(max)) if min <= max => {
+                    Ok(CampaignParameter::IntRange(key.to_string(), min, max))
+                }
+                _ => Err(format!(
+                    "Invalid range '{values}', expected e.g. 100..3000."
+                )),
+            };
+        }
+        Ok(CampaignParameter::Choice(
+            key.to_string(),
+            values
+                .split(',')
+                .map(|value| parse_value(value.trim()))
+                .collect(),
+        ))
+    }
+
+    pub fn draw(&self, rng: &mut StdRng) -> (String, SPValue) {
+        match self {
+            CampaignParameter::Choice(key, values) => {
+                (key.clone(), values[rng.gen_range(0..values.len())].clone())
+            }
+            CampaignParameter::IntRange(key, min, max) => {
+                (key.clone(), rng.gen_range(*min..=*max).to_spvalue())
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CampaignOptions {
+    pub runs: u64,
+    pub seed: Option<u64>,
+    pub parameters: Vec<CampaignParameter>,
+    // The harness of every run, the scenario's overrides and emulators by default.
+    pub harness: Option<HarnessOptions>,
+    // How much faster than real time the emulators of the runs are, see HarnessOptions.
+    pub time_scale: f64,
+}
+
+impl Default for CampaignOptions {
+    fn default() -> Self {
+        CampaignOptions {
+            runs: NUMBER_OF_TEST_CASES,
+            seed: None,
+            parameters: vec![],
+            harness: None,
+            time_scale: 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RunOutcome {
+    GoalReached,
+    Fatal,
+    TimedOut,
+}
+
+impl fmt::Display for RunOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RunOutcome::GoalReached => write!(f, "goal reached"),
+            RunOutcome::Fatal => write!(f, "fatal"),
+            RunOutcome::TimedOut => write!(f, "timed out"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RunResult {
+    pub run: u64,
+    pub seed: u64,
+    pub parameters: Vec<(String, SPValue)>,
+    pub outcome: RunOutcome,
+    // In emulated time.
+    pub duration: Duration,
+    pub retries: usize,
+    // "operation: info" of every failure, timeout or fatal state in the trace.
+    pub failure_causes: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct CampaignReport {
+    pub scenario: String,
+    pub time_scale: f64,
+    pub runs: Vec<RunResult>,
+}
+
+// Operations that ended up fatal, from the "op_*" state variables.
+fn fatal_operations(state: &State, log_target: &str) -> Vec<String> {
+    let fatal = OperationState::Fatal.to_string();
+    state
+        .state
+        .keys()
+        .filter(|key| key.starts_with("op_"))
+        .filter(|key| state.get_string_or_default_to_unknown(key, log_target) == fatal)
+        .cloned()
+        .collect()
+}
+
+fn failure_causes(trace: &Trace) -> Vec<String> {
+    let mut causes = vec![];
+    for operation in &trace.operations {
+        // Without the id, so that the same failure of different runs counts as one cause.
+        let name = match &operation.operation {
+            Some(operation) => format!("op_{operation}"),
+            None => operation.name.clone(),
+        };
+        let mut previous = "";
+        for entry in &operation.entries {
+            let failing = ["failed", "timedout", "fatal"]
+                .iter()
+                .any(|state| entry.state.eq_ignore_ascii_case(state));
+            if failing && !entry.state.eq_ignore_ascii_case(previous) {
+                causes.push(format!("{}: {}", name, entry.info));
+            }
+            previous = &entry.state;
+        }
+    }
+    causes
+}
+
+pub async fn run_once(
+    scenario: &dyn Scenario,
+    options: &CampaignOptions,
+    run: u64,
+    seed: u64,
+) -> RunResult {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let parameters: Vec<(String, SPValue)> = options
+        .parameters
+        .iter()
+        .map(|parameter| parameter.draw(&mut rng))
+        .collect();
+    let mut harness_options = options
+        .harness
+        .clone()
+        .unwrap_or_else(|| HarnessOptions::from_scenario(scenario));
+    harness_options
+        .overrides_after_injection
+        .extend(parameters.clone());
+    harness_options.time_scale = options.time_scale;
+    harness_options.seed = Some(seed);
+
+    let log_target = format!("micro_sp_emulation::campaign::{}", scenario.name());
+    let mut harness = Harness::start_scenario(scenario, harness_options).await;
+    let started = harness.now();
+    // Decided on the state that ended the wait, a run that neither reached the goal nor
+    // has a fatal operation timed out.
+    let mut outcome = RunOutcome::TimedOut;
+    let _ = harness
+        .wait_until(
+            |state| {
+                if scenario.is_complete(&harness.sp_id, state) {
+                    outcome = RunOutcome::GoalReached;
+                } else if !fatal_operations(state, &log_target).is_empty() {
+                    outcome = RunOutcome::Fatal;
+                }
+                outcome != RunOutcome::TimedOut
+            },
+            scenario.timeout(),
+        )
+        .await;
+    let duration = started.elapsed();
+    if outcome == RunOutcome::GoalReached {
+        harness.sleep(scenario.settle_time()).await;
+    }
+    harness.stop();
+
+    let trace = Trace::new(
+        &harness.trace(scenario.trace_kind()).await,
+        &harness.operations,
+    );
+    let run_result = RunResult {
+        run,
+        seed,
+        parameters,
+        outcome,
+        duration,
+        retries: trace
+            .operations
+            .iter()
+            .map(|operation| operation.retries())
+            .sum(),
+        failure_causes: failure_causes(&trace),
+    };
+    log::info!(
+        target: &log_target,
+        "Run {} (seed {}): {} after {:.1} s with {} retries.",
+        run + 1,
+        seed,
+        run_result.outcome,
+        run_result.duration.as_secs_f64(),
+        run_result.retries
+    );
+    run_result
+}
+
+pub async fn run_campaign(scenario: &dyn Scenario, options: &CampaignOptions) -> CampaignReport {
+    let log_target = format!("micro_sp_emulation::campaign::{}", scenario.name());
+    log::info!(
+        target: &log_target,
+        "Running {} {} times, emulators at {}x real time.",
+        scenario.name(),
+        options.runs,
+        options.time_scale
+    );
+    let mut runs = vec![];
+    for run in 0..options.runs {
+        let seed = match options.seed {
+            Some(seed) => seed.wrapping_add(run),
+            None => rand::random(),
+        };
+        runs.push(run_once(scenario, options, run, seed).await);
+    }
+    log::warn!(target: &log_target, "All tests are finished. Generating report...");
+    CampaignReport {
+        scenario: scenario.name().to_string(),
+        time_scale: options.time_scale,
+        runs,
+    }
+}
+
+impl CampaignReport {
+    pub fn count(&self, outcome: RunOutcome) -> usize {
+        self.runs
+            .iter()
+            .filter(|run| run.outcome == outcome)
+            .count()
+    }
+
+    // Failure causes and how many runs ran into them, most frequent first.
+    pub fn failure_causes(&self) -> Vec<(String, usize)> {
+        let mut causes: BTreeMap<String, usize> = BTreeMap::new();
+        for run in &self.runs {
+            let mut run_causes = run.failure_causes.clone();
+            run_causes.sort();
+            run_causes.dedup();
+            for cause in run_causes {
+                *causes.entry(cause).or_default() += 1;
+            }
+        }
+        let mut causes: Vec<(String, usize)> = causes.into_iter().collect();
+        causes.sort_by(|a, b| b.1.cmp(&a.1));
+        causes
+    }
+}
+
+impl fmt::Display for CampaignReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let total = self.runs.len().max(1) as f64;
+        writeln!(
+            f,
+            "Campaign {}: {} runs, emulators at {}x real time",
+            self.scenario,
+            self.runs.len(),
+            self.time_scale
+        )?;
+        for outcome in [
+            RunOutcome::GoalReached,
+            RunOutcome::Fatal,
+            RunOutcome::TimedOut,
+        ] {
+            let count = self.count(outcome);
+            writeln!(
+                f,
+                "  {:<13} {:>4} ({:.1}%)",
+                outcome.to_string(),
+                count,
+                100.0 * count as f64 / total
+            )?;
+        }
+        let durations: Vec<f64> = self
+            .runs
+            .iter()
+            .filter(|run| run.outcome == RunOutcome::GoalReached)
+            .map(|run| run.duration.as_secs_f64())
+            .collect();
+        if !durations.is_empty() {
+            writeln!(
+                f,
+                "  Duration to goal: min {:.1} s, mean {:.1} s, max {:.1} s",
+                durations.iter().cloned().fold(f64::INFINITY, f64::min),
+                durations.iter().sum::<f64>() / durations.len() as f64,
+                durations.iter().cloned().fold(0.0, f64::max)
+            )?;
+        }
+        let retries: usize = self.runs.iter().map(|run| run.retries).sum();
+        writeln!(
+            f,
+            "  Retries: {} in total, {:.2} per run",
+            retries,
+            retries as f64 / total
+        )?;
+        let causes = self.failure_causes();
+        if !causes.is_empty() {
+            writeln!(f, "  Failure causes (runs):")?;
+            for (cause, count) in causes {
+                writeln!(f, "    {:>4}  {}", count, cause)?;
+            }
+        }
+        for run in self
+            .runs
+            .iter()
+            .filter(|run| run.outcome != RunOutcome::GoalReached)
+        {
+            writeln!(
+                f,
+                "  Run {} (seed {}) {}, parameters: {:?}",
+                run.run + 1,
+                run.seed,
+                run.outcome,
+                run.parameters
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_campaign_report() {
+    assert_eq!(
+        CampaignParameter::parse("robot_emulated_execution_time=100..3000"),
+        Ok(CampaignParameter::IntRange(
+            "robot_emulated_execution_time".to_string(),
+            100,
+            3000
+        ))
+    );
+    let choice =
+        CampaignParameter::parse("gantry_emulated_failure_cause=collision,violation").unwrap();
+    assert!(CampaignParameter::parse("robot_emulated_execution_time=3000..100").is_err());
+
+    // The same seed draws the same parameters.
+    let draw = |seed| choice.draw(&mut StdRng::seed_from_u64(seed));
+    assert_eq!(draw(7), draw(7));
+
+    let run = |outcome, seconds, retries, causes: &[&str]| RunResult {
+        run: 0,
+        seed: 0,
+        parameters: vec![],
+        outcome,
+        duration: Duration::from_secs(seconds),
+        retries,
+        failure_causes: causes.iter().map(|cause| cause.to_string()).collect(),
+    };
+    let report = CampaignReport {
+        scenario: "failed_retries".to_string(),
+        time_scale: 1.0,
+        runs: vec![
+            run(RunOutcome::GoalReached, 4, 0, &[]),
+            run(
+                RunOutcome::GoalReached,
+                8,
+                1,
+                &["op_gantry_calibrate: collision"],
+            ),
+            run(
+                RunOutcome::Fatal,
+                6,
+                2,
+                &[
+                    "op_gantry_calibrate: collision",
+                    "op_gantry_calibrate: collision",
+                ],
+            ),
+            run(RunOutcome::TimedOut, 30, 0, &[]),
+        ],
+    };
+    assert_eq!(report.count(RunOutcome::GoalReached), 2);
+    assert_eq!(
+        report.failure_causes(),
+        vec![("op_gantry_calibrate: collision".to_string(), 2)]
+    );
+    let printed = report.to_string();
+    assert!(printed.contains("goal reached     2 (50.0%)"));
+    assert!(printed.contains("min 4.0 s, mean 6.0 s, max 8.0 s"));
+    assert!(printed.contains("Retries: 3 in total, 0.75 per run"));
+}
+
+// With a seed, the runs draw the same parameters and the emulators the same random
+// failures, so a campaign ends the same way again.
+#[tokio::test(start_paused = true)]
+async fn test_run_campaign_with_seed() {
+    let scenario = crate::find_scenario("failed_retries").unwrap();
+    let options = CampaignOptions {
+        runs: 3,
+        seed: Some(7),
+        parameters: vec![
+            CampaignParameter::parse("gantry_emulate_failure_rate=2").unwrap(),
+            CampaignParameter::parse("gantry_emulated_failure_rate=0..100").unwrap(),
+        ],
+        ..Default::default()
+    };
+    let outcomes = |report: CampaignReport| {
+        report
+            .runs
+            .into_iter()
+            .map(|run| (run.parameters, run.outcome, run.retries))
+            .collect::<Vec<(Vec<(String, SPValue)>, RunOutcome, usize)>>()
+    };
+    let first = outcomes(run_campaign(scenario.as_ref(), &options).await);
+    let second = outcomes(run_campaign(scenario.as_ref(), &options).await);
+    assert_eq!(first.len(), 3);
+    assert_eq!(first, second);
+}
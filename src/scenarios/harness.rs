@@ -0,0 +1,646 @@
+use micro_sp::*;
+use std::sync::Arc;
+use std::time::Duration;
+use testcontainers::{ContainerAsync, runners::AsyncRunner};
+use testcontainers_modules::redis::Redis;
+use tokio::task::{AbortHandle, JoinHandle};
+
+use crate::{
+    InProcessRedis, InProcessRedisListener, Scenario, ScenarioEmulator, StateBackend, Trace,
+    TraceKind, connection_manager_for, operation_names, run_script,
+};
+
+// Where the harness keeps the state.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HarnessBackend {
+    // Start a Redis container on a free port.
+    RedisContainer,
+    // Use a Redis that is already running.
+    Redis,
+    // Keep the state in memory, in a Redis-compatible server inside the test process, so
+    // the runner, the injections and the emulators all run without Redis or Docker.
+    InMemory,
+}
+
+// How to set up a run: the runner, the initial state and what to spawn next to it.
+#[derive(Debug, Clone)]
+pub struct HarnessOptions {
+    pub sp_id: String,
+    pub number_of_timers: u64,
+    pub coverability_tracking: bool,
+    pub overrides: Vec<(String, SPValue)>,
+    // Applied once the injection of a scenario has run, so that it doesn't overwrite them,
+    // e.g. the emulation parameters drawn by a campaign.
+    pub overrides_after_injection: Vec<(String, SPValue)>,
+    pub emulators: Vec<ScenarioEmulator>,
+    pub backend: HarnessBackend,
+    // The keyspace of the run in the in-process server, a fresh one if not set. The runner,
+    // the injections and the emulators all see the plain keys of their own namespace, so
+    // runs with the same sp_id and variables don't see each other and can run in parallel.
+    // A container is a keyspace of its own anyway, see redis_database for a running Redis.
+    pub namespace: Option<String>,
+    // The database of the running Redis (HarnessBackend::Redis) that the run uses, the one
+    // in REDIS_HOST and REDIS_PORT. Runs against one shared Redis keep apart by using
+    // different databases, 0 to 15 with the default configuration. Database 0 if not set.
+    pub redis_database: Option<u8>,
+    // How much faster than real time the emulators, the injections and the timeouts and
+    // settle times of the harness run. The runner's ticks and operation timeouts are not
+    // scaled, so keep it at 1.0 for scenarios with wall_clock_timeouts.
+    pub time_scale: f64,
+    // Seeds the random failures, execution times etc. of the emulators. Emulator i draws
+    // with seed + i, so a seeded run draws the same again, see emulators/random.rs.
+    pub seed: Option<u64>,
+}
+
+impl Default for HarnessOptions {
+    fn default() -> Self {
+        HarnessOptions {
+            sp_id: "micro_sp".to_string(),
+            number_of_timers: 1,
+            coverability_tracking: false,
+            overrides: vec![],
+            overrides_after_injection: vec![],
+            emulators: vec![
+                ScenarioEmulator::Robot("robot".to_string()),
+                ScenarioEmulator::Gantry("gantry".to_string()),
+            ],
+            backend: HarnessBackend::InMemory,
+            namespace: None,
+            redis_database: None,
+            time_scale: 1.0,
+            seed: None,
+        }
+    }
+}
+
+impl HarnessOptions {
+    pub fn from_scenario(scenario: &dyn Scenario) -> HarnessOptions {
+        let sp_id = HarnessOptions::default().sp_id;
+        HarnessOptions {
+            number_of_timers: scenario.number_of_timers(),
+            overrides: scenario.overrides(&sp_id),
+            emulators: scenario.emulators(),
+            sp_id,
+            ..Default::default()
+        }
+    }
+}
+
+// A running model with its emulators, i.e. what every model test sets up by hand.
+// Everything spawned is aborted and the Redis container removed when the harness is
+// dropped, also when a test panics halfway.
+pub struct Harness {
+    pub sp_id: String,
+    pub backend: Arc<dyn StateBackend>,
+    pub connection_manager: Arc<ConnectionManager>,
+    pub time_scale: f64,
+    // The names of the operations of the model, planned, automatic and in SOPs, see
+    // trace::operation_names.
+    pub operations: Vec<String>,
+    handles: Vec<AbortHandle>,
+    _container: Option<ContainerAsync<Redis>>,
+    _in_process_redis: Option<InProcessRedisListener>,
+    log_target: String,
+}
+
+pub fn spawn_emulator(
+    emulator: &ScenarioEmulator,
+    backend: &Arc<dyn StateBackend>,
+) -> JoinHandle<()> {
+    let con = backend.clone();
+    match emulator.clone() {
+        ScenarioEmulator::Robot(name) => crate::spawn_emulated(async move {
+            crate::emulators::robot::robot_emulator(&name, &con)
+                .await
+                .unwrap()
+        }),
+        ScenarioEmulator::Gantry(name) => crate::spawn_emulated(async move {
+            crate::emulators::gantry::gantry_emulator(&name, &con)
+                .await
+                .unwrap()
+        }),
+        ScenarioEmulator::Nutrunner(name) => crate::spawn_emulated(async move {
+            crate::emulators::nutrunner::nutrunner_emulator(&name, &con)
+                .await
+                .unwrap()
+        }),
+        ScenarioEmulator::Agv(name) => crate::spawn_emulated(async move {
+            crate::emulators::agv::agv_emulator(&name, &con)
+                .await
+                .unwrap()
+        }),
+        ScenarioEmulator::Queue(name) => crate::spawn_emulated(async move {
+            crate::emulators::queue::queue_emulator(&name, &con)
+                .await
+                .unwrap()
+        }),
+        ScenarioEmulator::Operator => crate::spawn_emulated(async move {
+            crate::emulators::operator::operator_emulator(&con)
+                .await
+                .unwrap()
+        }),
+    }
+}
+
+// Sends a request to a spawned emulator the way an operation would, i.e. sets the command
+// variables and the trigger, and waits until the request succeeded or failed.
+#[cfg(test)]
+pub async fn complete_request(
+    backend: &Arc<dyn StateBackend>,
+    name: &str,
+    command: &[(&str, SPValue)],
+) -> State {
+    let state = backend.get_full_state().await.unwrap();
+    let new_state = command
+        .iter()
+        .fold(state.clone(), |new_state, (key, value)| {
+            new_state.update(&format!("{name}_{key}"), value.clone())
+        })
+        .update(&format!("{name}_request_trigger"), true.to_spvalue())
+        .update(
+            &format!("{name}_request_state"),
+            ServiceRequestState::Initial.to_string().to_spvalue(),
+        );
+    backend
+        .set_state(&state.get_diff_partial_state(&new_state))
+        .await;
+
+    let done = [
+        ServiceRequestState::Succeeded.to_string(),
+        ServiceRequestState::Failed.to_string(),
+    ];
+    let waiting = async {
+        loop {
+            crate::emulated_sleep(Duration::from_millis(crate::EMULATOR_TICK_INTERVAL)).await;
+            let state = backend.get_full_state().await.unwrap();
+            let request_state = state.get_string_or_default_to_unknown(
+                &format!("{name}_request_state"),
+                "complete_request",
+            );
+            if done.contains(&request_state) {
+                return state;
+            }
+        }
+    };
+    tokio::time::timeout(Duration::from_secs(60), waiting)
+        .await
+        .unwrap_or_else(|_| panic!("The {name} request never finished."))
+}
+
+impl Harness {
+    pub async fn start(
+        options: HarnessOptions,
+        model: impl FnOnce(&str, &State) -> (Model, State),
+    ) -> Harness {
+        let log_target = format!("micro_sp_emulation::harness::{}", options.sp_id);
+        micro_sp::initialize_env_logger();
+
+        assert!(
+            options.namespace.is_none() || options.backend == HarnessBackend::InMemory,
+            "A namespace needs HarnessBackend::InMemory, use redis_database for a running Redis."
+        );
+        assert!(
+            options.redis_database.is_none() || options.backend == HarnessBackend::Redis,
+            "A redis_database needs HarnessBackend::Redis."
+        );
+        let container = match options.backend {
+            HarnessBackend::RedisContainer => Some(Redis::default().start().await.unwrap()),
+            HarnessBackend::Redis | HarnessBackend::InMemory => None,
+        };
+
+        let sp_id = options.sp_id.clone();
+        let state = crate::model::state::state();
+
+        let runner_vars =
+            generate_runner_state_variables(&sp_id, options.number_of_timers, "emulator");
+        let state = state.extend(runner_vars, true);
+
+        let (model, state) = model(&sp_id, &state);
+        let operations = operation_names(&model);
+
+        let op_vars =
+            generate_operation_state_variables(&model, options.coverability_tracking, "emulator");
+        let mut state = state.extend(op_vars, true);
+
+        for (key, value) in options.overrides {
+            state = state.update(&key, value);
+        }
+
+        let in_process_redis = match options.backend {
+            HarnessBackend::InMemory => {
+                let namespace = options
+                    .namespace
+                    .clone()
+                    .unwrap_or_else(|| format!("{}_{}", sp_id, nanoid::nanoid!()));
+                Some(InProcessRedis::shared().listen(&namespace).await.unwrap())
+            }
+            HarnessBackend::Redis | HarnessBackend::RedisContainer => None,
+        };
+        let connection_manager = Arc::new(match (&in_process_redis, &container) {
+            (Some(listener), _) => listener.connection_manager().await,
+            (None, Some(container)) => {
+                let host = container.get_host().await.unwrap().to_string();
+                let port = container.get_host_port_ipv4(6379).await.unwrap();
+                connection_manager_for(&host, port, 0).await
+            }
+            (None, None) => match options.redis_database {
+                Some(database) => {
+                    let host = std::env::var("REDIS_HOST").unwrap_or("127.0.0.1".to_string());
+                    let port = std::env::var("REDIS_PORT")
+                        .ok()
+                        .and_then(|port| port.parse().ok())
+                        .unwrap_or(6379);
+                    connection_manager_for(&host, port, database).await
+                }
+                None => ConnectionManager::new().await,
+            },
+        });
+        let backend: Arc<dyn StateBackend> = connection_manager.clone();
+        backend.set_state(&state).await;
+
+        let time_scale = crate::valid_time_scale(options.time_scale);
+        log::info!(target: &log_target, "Spawning emulators at {}x real time.", time_scale);
+        let mut handles: Vec<AbortHandle> = vec![];
+        for (index, emulator) in options.emulators.iter().enumerate() {
+            let spawn = crate::with_time_scale(time_scale, async {
+                spawn_emulator(emulator, &backend).abort_handle()
+            });
+            handles.push(match options.seed {
+                Some(seed) => crate::with_seed(seed.wrapping_add(index as u64), spawn).await,
+                None => spawn.await,
+            });
+        }
+
+        // The runner waits on tokio's clock directly, so it runs in real time either way.
+        log::info!(target: &log_target, "Spawning Micro SP.");
+        let con_clone = connection_manager.clone();
+        let sp_id_clone = sp_id.clone();
+        let number_of_timers = options.number_of_timers;
+        handles.push(
+            tokio::task::spawn(async move {
+                main_runner(&sp_id_clone, model, number_of_timers, &con_clone).await
+            })
+            .abort_handle(),
+        );
+
+        Harness {
+            sp_id,
+            backend,
+            connection_manager,
+            time_scale,
+            operations,
+            handles,
+            _container: container,
+            _in_process_redis: in_process_redis,
+            log_target,
+        }
+    }
+
+    // Starts the model of the scenario and spawns its injection.
+    pub async fn start_scenario(scenario: &dyn Scenario, mut options: HarnessOptions) -> Harness {
+        if scenario.wall_clock_timeouts() && options.time_scale != 1.0 {
+            log::warn!(
+                target: "micro_sp_emulation::harness",
+                "{} relies on operation timeouts, which are not scaled to {}x.",
+                scenario.name(),
+                options.time_scale
+            );
+        }
+        let overrides_after_injection = std::mem::take(&mut options.overrides_after_injection);
+        let mut harness =
+            Harness::start(options, |sp_id, state| scenario.model(sp_id, state)).await;
+        let injection = match scenario.injection() {
+            Some(injection) => {
+                log::info!(target: &harness.log_target, "Spawning injection.");
+                let con = harness.connection_manager.get_connection().await;
+                Some(injection(harness.sp_id.clone(), con))
+            }
+            None => None,
+        };
+        let backend = harness.backend.clone();
+        harness.spawn(async move {
+            if let Some(injection) = injection {
+                injection.await;
+            }
+            if overrides_after_injection.is_empty() {
+                return;
+            }
+            if let Some(state) = backend.get_full_state().await {
+                let mut new_state = state.clone();
+                for (key, value) in overrides_after_injection {
+                    new_state = new_state.update(&key, value);
+                }
+                backend
+                    .set_state(&state.get_diff_partial_state(&new_state))
+                    .await;
+            }
+        });
+        if let Some(script) = scenario.script(&harness.sp_id) {
+            log::info!(target: &harness.log_target, "Spawning injection script.");
+            let (sp_id, backend) = (harness.sp_id.clone(), harness.backend.clone());
+            let log_target = format!("{}_script", harness.log_target);
+            harness.spawn(async move { run_script(&sp_id, script, &backend, &log_target).await });
+        }
+        harness
+    }
+
+    // Runs something next to the model, it is aborted together with everything else.
+    pub fn spawn(&mut self, task: impl Future<Output = ()> + Send + 'static) {
+        let task = crate::with_time_scale(self.time_scale, task);
+        self.handles.push(tokio::task::spawn(task).abort_handle());
+    }
+
+    pub async fn state(&self) -> Option<State> {
+        self.backend.get_full_state().await
+    }
+
+    // Polls the state until the predicate holds and returns that state.
+    pub async fn wait_until(
+        &self,
+        mut predicate: impl FnMut(&State) -> bool,
+        timeout: Duration,
+    ) -> Result<State, String> {
+        let polling_logic = async {
+            loop {
+                match self.state().await {
+                    Some(state) if predicate(&state) => break state,
+                    Some(_) => (),
+                    None => log::error!(target: &self.log_target, "Failed to get full state."),
+                }
+                self.sleep(Duration::from_millis(crate::EMULATOR_TICK_INTERVAL))
+                    .await;
+            }
+        };
+        tokio::time::timeout(timeout.div_f64(self.time_scale), polling_logic)
+            .await
+            .map_err(|_| format!("Timed out after {:?} waiting for condition.", timeout))
+    }
+
+    // Sleeps for an emulated duration at the time scale of the harness.
+    pub async fn sleep(&self, duration: Duration) {
+        tokio::time::sleep(duration.div_f64(self.time_scale)).await
+    }
+
+    // The start of something measured in emulated time, e.g. the duration of a run.
+    pub fn now(&self) -> crate::EmulatedInstant {
+        crate::EmulatedInstant::at_time_scale(self.time_scale)
+    }
+
+    // Writes the variables of a (partial) state, e.g. to change emulation modes mid-run.
+    pub async fn inject(&self, state: &State) {
+        self.backend.set_state(state).await;
+    }
+
+    pub async fn trace(&self, kind: TraceKind) -> Vec<Vec<OperationLog>> {
+        match self.backend.get_sp_value(&kind.key(&self.sp_id)).await {
+            Some(SPValue::String(StringOrUnknown::String(logger_string))) => {
+                serde_json::from_str::<Vec<Vec<OperationLog>>>(&logger_string).unwrap_or_else(|e| {
+                    log::error!(target: &self.log_target, "Failed to parse the trace: {}", e);
+                    vec![]
+                })
+            }
+            _ => vec![],
+        }
+    }
+
+    pub fn stop(&mut self) {
+        self.handles.drain(..).for_each(|handle| handle.abort());
+    }
+
+    // Waits until the scenario is complete, lets it settle and stops. Returns the final state.
+    pub async fn complete(&mut self, scenario: &dyn Scenario) -> Result<State, String> {
+        self.complete_observing(scenario, |_| ()).await
+    }
+
+    // Like complete, but hands every state polled until completion to observe, for the
+    // values that are gone by the end of the run, e.g. the progress of a move.
+    pub async fn complete_observing(
+        &mut self,
+        scenario: &dyn Scenario,
+        mut observe: impl FnMut(&State),
+    ) -> Result<State, String> {
+        let state = self
+            .wait_until(
+                |state| {
+                    observe(state);
+                    scenario.is_complete(&self.sp_id, state)
+                },
+                scenario.timeout(),
+            )
+            .await?;
+        self.sleep(scenario.settle_time()).await;
+        self.stop();
+        Ok(self.state().await.unwrap_or(state))
+    }
+}
+
+impl Drop for Harness {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+// Runs a scenario until it is complete and lets it settle. Returns the final state and
+// the operation trace.
+pub async fn complete_scenario(
+    scenario: &dyn Scenario,
+    options: HarnessOptions,
+) -> Result<(State, Vec<Vec<OperationLog>>), String> {
+    let mut harness = Harness::start_scenario(scenario, options).await;
+    let state = harness.complete(scenario).await?;
+    let trace = harness.trace(scenario.trace_kind()).await;
+    Ok((state, trace))
+}
+
+// Runs a scenario to completion and checks the outcome.
+pub async fn run_scenario(scenario: &dyn Scenario, options: HarnessOptions) -> Result<(), String> {
+    run_scenario_observing(scenario, options, |_| ()).await
+}
+
+// Like run_scenario, observe gets every state polled until completion.
+pub async fn run_scenario_observing(
+    scenario: &dyn Scenario,
+    options: HarnessOptions,
+    observe: impl FnMut(&State),
+) -> Result<(), String> {
+    let mut harness = Harness::start_scenario(scenario, options).await;
+    let state = harness.complete_observing(scenario, observe).await?;
+    let trace = Trace::new(
+        &harness.trace(scenario.trace_kind()).await,
+        &harness.operations,
+    );
+    scenario.check_outcome(&harness.sp_id, &state, &trace)
+}
+
+#[tokio::test]
+async fn test_harness_counter_goal() {
+    let scenario = crate::find_scenario("counter_goal").unwrap();
+    let options = HarnessOptions::from_scenario(scenario.as_ref());
+    let harness = Harness::start_scenario(scenario.as_ref(), options).await;
+
+    let state = harness
+        .wait_until(
+            |state| scenario.is_complete(&harness.sp_id, state),
+            scenario.timeout(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        state.get_int_or_default_to_zero("counter", "micro_sp_emulation::test_harness"),
+        5
+    );
+
+    harness.sleep(scenario.settle_time()).await;
+    let trace = harness.trace(scenario.trace_kind()).await;
+    assert!(!trace.is_empty());
+}
+
+#[tokio::test]
+async fn test_harness_in_memory_gantry() {
+    let options = HarnessOptions {
+        emulators: vec![ScenarioEmulator::Gantry("gantry".to_string())],
+        overrides: vec![(
+            "gantry_emulate_failure_rate".to_string(),
+            crate::DONT_EMULATE_FAILURE.to_spvalue(),
+        )],
+        backend: HarnessBackend::InMemory,
+        ..Default::default()
+    };
+    let harness = Harness::start(options, |sp_id, state| {
+        (
+            Model::new(sp_id, vec![], vec![], vec![], vec![]),
+            state.clone(),
+        )
+    })
+    .await;
+
+    let state = harness.state().await.unwrap();
+    let new_state = state
+        .update("gantry_command_command", "calibrate".to_spvalue())
+        .update("gantry_request_trigger", true.to_spvalue());
+    harness
+        .inject(&state.get_diff_partial_state(&new_state))
+        .await;
+
+    let state = harness
+        .wait_until(
+            |state| {
+                state.get_string_or_default_to_unknown("gantry_request_state", "test_harness")
+                    == "succeeded"
+            },
+            Duration::from_secs(10),
+        )
+        .await
+        .unwrap();
+    assert!(state.get_bool_or_default_to_false("gantry_calibrated_estimated", "test_harness"));
+}
+
+#[tokio::test]
+async fn test_harness_namespaces() {
+    let start = |namespace: &str| {
+        let options = HarnessOptions {
+            namespace: Some(namespace.to_string()),
+            ..Default::default()
+        };
+        Harness::start(options, |sp_id, state| {
+            (
+                Model::new(sp_id, vec![], vec![], vec![], vec![]),
+                state.clone(),
+            )
+        })
+    };
+    let harness_1 = start("test_harness_namespaces_1").await;
+    let harness_2 = start("test_harness_namespaces_2").await;
+
+    let state = harness_1.state().await.unwrap();
+    let new_state = state.update("robot_position_estimated", "d".to_spvalue());
+    harness_1
+        .inject(&state.get_diff_partial_state(&new_state))
+        .await;
+
+    let state_1 = harness_1.state().await.unwrap();
+    let state_2 = harness_2.state().await.unwrap();
+    assert_eq!(
+        state_1.get_string_or_default_to_unknown("robot_position_estimated", "test_harness"),
+        "d"
+    );
+    assert_ne!(
+        state_2.get_string_or_default_to_unknown("robot_position_estimated", "test_harness"),
+        "d"
+    );
+    // Both runners write their own plain runner keys.
+    assert_eq!(state_1.state.len(), state_2.state.len());
+}
+
+// The runner, the emulators and the injections all wait on tokio's clock, so in paused
+// time the failure, retry and goal scenarios take milliseconds and run the same way every
+// time. Scenarios with operation timeouts run in real time, see wall_clock_timeouts.
+#[tokio::test(start_paused = true)]
+async fn test_scenarios_in_paused_time() {
+    let mut failures = vec![];
+    for scenario in crate::scenarios() {
+        if scenario.wall_clock_timeouts() {
+            continue;
+        }
+        let options = HarnessOptions::from_scenario(scenario.as_ref());
+        if let Err(e) = run_scenario(scenario.as_ref(), options).await {
+            failures.push(format!("{}: {}", scenario.name(), e));
+        }
+    }
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+// With paused time, tokio jumps ahead whenever every task waits, so a calibration that
+// takes a minute of emulated time is done right away.
+#[tokio::test(start_paused = true)]
+async fn test_harness_in_memory_paused_time() {
+    let options = HarnessOptions {
+        emulators: vec![ScenarioEmulator::Gantry("gantry".to_string())],
+        overrides: vec![
+            (
+                "gantry_emulate_failure_rate".to_string(),
+                crate::DONT_EMULATE_FAILURE.to_spvalue(),
+            ),
+            (
+                "gantry_emulate_execution_time".to_string(),
+                crate::EMULATE_EXACT_EXECUTION_TIME.to_spvalue(),
+            ),
+            (
+                "gantry_emulated_execution_time".to_string(),
+                60000.to_spvalue(),
+            ),
+        ],
+        backend: HarnessBackend::InMemory,
+        ..Default::default()
+    };
+    let harness = Harness::start(options, |sp_id, state| {
+        (
+            Model::new(sp_id, vec![], vec![], vec![], vec![]),
+            state.clone(),
+        )
+    })
+    .await;
+
+    let real_start = std::time::Instant::now();
+    let emulated_start = crate::EmulatedInstant::now();
+    let state = harness.state().await.unwrap();
+    let new_state = state
+        .update("gantry_command_command", "calibrate".to_spvalue())
+        .update("gantry_request_trigger", true.to_spvalue());
+    harness
+        .inject(&state.get_diff_partial_state(&new_state))
+        .await;
+
+    harness
+        .wait_until(
+            |state| {
+                state.get_string_or_default_to_unknown("gantry_request_state", "test_harness")
+                    == "succeeded"
+            },
+            Duration::from_secs(120),
+        )
+        .await
+        .unwrap();
+    assert!(emulated_start.elapsed() >= Duration::from_millis(60000));
+    assert!(real_start.elapsed() < Duration::from_secs(10));
+}
@@ -0,0 +1,7 @@
+pub mod campaign;
+pub mod harness;
+pub mod registry;
+pub mod scenario;
+pub mod script;
+pub mod snapshot;
+pub mod trace;
@@ -0,0 +1,925 @@
+use micro_sp::{running::goal_runner::GoalPriority, *};
+use std::time::Duration;
+
+use crate::{
+    InjectionScript, ModelScenario, Scenario, ScenarioEmulator, ScriptAction, Trace, TraceKind,
+};
+
+static LOG_TARGET: &str = "micro_sp_emulation::scenarios";
+
+fn robot(name: &str) -> ScenarioEmulator {
+    ScenarioEmulator::Robot(name.to_string())
+}
+
+fn gantry(name: &str) -> ScenarioEmulator {
+    ScenarioEmulator::Gantry(name.to_string())
+}
+
+fn counter_is(state: &State, value: i64) -> bool {
+    state.get_int_or_default_to_zero("counter", LOG_TARGET) == value
+}
+
+fn flag_is_set(state: &State, key: &str) -> bool {
+    state.get_bool_or_default_to_false(key, LOG_TARGET)
+}
+
+fn estimated(state: &State, key: &str) -> String {
+    state.get_string_or_default_to_unknown(key, LOG_TARGET)
+}
+
+fn int(state: &State, key: &str) -> i64 {
+    state.get_int_or_default_to_zero(key, LOG_TARGET)
+}
+
+// A variable of the final state has the value the model test expects.
+fn expect<T: PartialEq + std::fmt::Debug>(key: &str, actual: T, expected: T) -> Result<(), String> {
+    match actual == expected {
+        true => Ok(()),
+        false => Err(format!(
+            "Expected {key} to be {expected:?}, it was {actual:?}."
+        )),
+    }
+}
+
+// The latest run of each operation went through the usual states and completed.
+fn completed(trace: &Trace, operations: &[&str]) -> Result<(), String> {
+    for operation in operations {
+        trace.went_through(operation, &["Initial", "Executing", "Completed"])?;
+        trace.ended(operation, "Completed")?;
+    }
+    Ok(())
+}
+
+// The test of the disabled model enables the operation a while after it started.
+fn enable_later(_sp_id: &str) -> InjectionScript {
+    InjectionScript::new().at(
+        Duration::from_secs(3),
+        ScriptAction::set("enabled", true.to_spvalue()),
+    )
+}
+
+// The test of the incoming goals model adds normal and then high priority goals while
+// the first ones are being worked on.
+fn push_later_goals(_sp_id: &str) -> InjectionScript {
+    let goal = |position: &str| format!("var:robot_position_estimated == {position}");
+    InjectionScript::new()
+        .at(
+            Duration::from_secs(5),
+            ScriptAction::push_goal(&goal("d"), GoalPriority::Normal),
+        )
+        .at(
+            Duration::from_secs(5),
+            ScriptAction::push_goal(&goal("e"), GoalPriority::Normal),
+        )
+        .at(
+            Duration::from_secs(10),
+            ScriptAction::push_goal(&goal("f"), GoalPriority::High),
+        )
+        .at(
+            Duration::from_secs(10),
+            ScriptAction::push_goal(&goal("g"), GoalPriority::High),
+        )
+}
+
+// All scenarios, one per model in src/model/, in the same order. The completion
+// conditions, settle times and timeouts are the ones of the model tests.
+pub fn scenarios() -> Vec<Box<dyn Scenario>> {
+    vec![
+        Box::new(
+            ModelScenario::new(
+                "auto_operations",
+                crate::model::auto_operations::model,
+                |_, state| counter_is(state, 5),
+            )
+            .deterministic()
+            .settle_time(Duration::from_secs(2))
+            .trace_kind(TraceKind::AutomaticOperations)
+            .check_outcome(|_, _, trace| {
+                completed(trace, &["robot_move_to_a", "robot_move_to_b"])?;
+                trace.completed_before_started("robot_move_to_a", "robot_move_to_b")?;
+                Ok(())
+            }),
+        ),
+        Box::new(
+            ModelScenario::new(
+                "auto_operations_multi",
+                crate::model::auto_operations_multi::model,
+                |_, state| state.get_int_or_default_to_zero("counter", LOG_TARGET) > 3,
+            )
+            .number_of_timers(3)
+            .deterministic()
+            .settle_time(Duration::from_secs(2))
+            .timeout(Duration::from_secs(15))
+            .trace_kind(TraceKind::AutomaticOperations)
+            .check_outcome(|_, _, trace| {
+                let timers = [
+                    "emulate_auto_timer_1",
+                    "emulate_auto_timer_2",
+                    "emulate_auto_timer_3",
+                ];
+                completed(trace, &timers)
+            }),
+        ),
+        Box::new(
+            ModelScenario::new(
+                "auto_transitions",
+                crate::model::auto_transitions::model,
+                |_, state| counter_is(state, 3),
+            )
+            .deterministic()
+            .settle_time(Duration::from_secs(2))
+            .check_outcome(|_, state, trace| {
+                // Only transitions, so the runner doesn't plan any operations.
+                match flag_is_set(state, "lights_on") || !trace.operations.is_empty() {
+                    true => Err("Expected the lights off and no planned operations.".to_string()),
+                    false => Ok(()),
+                }
+            }),
+        ),
+        Box::new(
+            ModelScenario::new(
+                "capability_discovery",
+                crate::model::capability_discovery::model,
+                |_, state| estimated(state, "gantry_position_estimated") == "plate_pipe_box",
+            )
+            .emulators(vec![gantry("gantry"), robot("robot")])
+            .injection(|sp_id, con| {
+                Box::pin(async move {
+                    crate::model::capability_discovery::run_emultaion(&sp_id, con)
+                        .await
+                        .unwrap()
+                })
+            })
+            .deterministic()
+            .check_outcome(|_, state, _| {
+                let capabilities = |key: &str| {
+                    serde_json::from_str::<serde_json::Value>(&estimated(state, key))
+                        .map_err(|e| format!("Failed to parse {key}: {e}."))
+                };
+
+                let gantry_capabilities = capabilities("gantry_capabilities")?;
+                for command in ["calibrate", "reserve"] {
+                    expect(
+                        &format!("gantry command {command}"),
+                        gantry_capabilities["commands"][command].is_object(),
+                        true,
+                    )?;
+                }
+                expect(
+                    "gantry positions",
+                    &gantry_capabilities["positions"],
+                    &serde_json::json!(crate::GANTRY_POSITIONS),
+                )?;
+                expect(
+                    "gantry emulate_failure_rate",
+                    &gantry_capabilities["emulation_modes"]["emulate_failure_rate"],
+                    &serde_json::json!([0, 1, 2]),
+                )?;
+
+                let robot_capabilities = capabilities("robot_capabilities")?;
+                expect(
+                    "robot tools",
+                    &robot_capabilities["tools"],
+                    &serde_json::json!(["gripper_tool", "suction_tool"]),
+                )?;
+                expect(
+                    "robot failure cause unknown_position",
+                    robot_capabilities["failure_causes"]
+                        .as_array()
+                        .is_some_and(|causes| {
+                            causes.contains(&serde_json::json!("unknown_position"))
+                        }),
+                    true,
+                )?;
+
+                // The gantry went everywhere it can go, without a single failure
+                expect(
+                    "gantry_total_fail_counter",
+                    int(state, "gantry_total_fail_counter"),
+                    0,
+                )?;
+                Ok(())
+            }),
+        ),
+        Box::new(
+            ModelScenario::new(
+                "composite_robot",
+                crate::model::composite_robot::model,
+                |_, state| {
+                    estimated(state, "robot_position_estimated") == "d"
+                        && estimated(state, "robot_request_state") == "initial"
+                },
+            )
+            .emulators(vec![robot("robot")])
+            .injection(|sp_id, con| {
+                Box::pin(async move {
+                    crate::model::composite_robot::run_emultaion(&sp_id, con)
+                        .await
+                        .unwrap()
+                })
+            })
+            .deterministic()
+            .check_outcome(|_, state, _| {
+                expect(
+                    "robot_total_fail_counter",
+                    int(state, "robot_total_fail_counter"),
+                    0,
+                )?;
+                let failed_step = estimated(state, "robot_failed_step_estimated");
+                expect(
+                    "robot_failed_step_estimated",
+                    failed_step.as_str(),
+                    "unknown",
+                )?;
+                Ok(())
+            }),
+        ),
+        Box::new(
+            ModelScenario::new(
+                "counter_goal",
+                crate::model::counter_goal::model,
+                |_, state| counter_is(state, 5),
+            )
+            .injection(|sp_id, con| {
+                Box::pin(async move {
+                    crate::model::counter_goal::run_emultaion(&sp_id, con)
+                        .await
+                        .unwrap()
+                })
+            })
+            .deterministic()
+            .settle_time(Duration::from_secs(2))
+            .timeout(Duration::from_secs(20))
+            .check_outcome(|_, _, trace| completed(trace, &["emulate_counter_goals"])),
+        ),
+        Box::new(
+            ModelScenario::new(
+                "degraded_mode",
+                crate::model::degraded_mode::model,
+                |_, state| flag_is_set(state, "gantry_locked_estimated"),
+            )
+            .emulators(vec![gantry("gantry")])
+            .injection(|sp_id, con| {
+                Box::pin(async move {
+                    crate::model::degraded_mode::run_emultaion(&sp_id, con)
+                        .await
+                        .unwrap()
+                })
+            })
+            .deterministic()
+            .check_outcome(|_, state, _| {
+                expect(
+                    "gantry_mode_estimated",
+                    estimated(state, "gantry_mode_estimated").as_str(),
+                    "normal",
+                )?;
+                expect(
+                    "gantry_refused_lock",
+                    flag_is_set(state, "gantry_refused_lock"),
+                    true,
+                )?;
+                // The move at full speed and the lock while degraded
+                expect(
+                    "gantry_total_fail_counter",
+                    int(state, "gantry_total_fail_counter"),
+                    2,
+                )?;
+                Ok(())
+            }),
+        ),
+        Box::new(
+            ModelScenario::new("disabled", crate::model::disabled::model, |_, state| {
+                flag_is_set(state, "done")
+            })
+            .injection(|sp_id, con| {
+                Box::pin(async move {
+                    crate::model::disabled::run_emultaion(&sp_id, con)
+                        .await
+                        .unwrap()
+                })
+            })
+            .script(enable_later)
+            .deterministic()
+            .settle_time(Duration::from_secs(2))
+            .check_outcome(|_, _, trace| {
+                trace.went_through("emulate_disabled", &["Disabled", "Executing", "Completed"])?;
+                trace.ended("emulate_disabled", "Completed")?;
+                Ok(())
+            }),
+        ),
+        Box::new(
+            ModelScenario::new(
+                "failed_bypass",
+                crate::model::failed_bypass::model,
+                |_, state| flag_is_set(state, "failed"),
+            )
+            .injection(|sp_id, con| {
+                Box::pin(async move {
+                    crate::model::failed_bypass::run_emultaion(&sp_id, con)
+                        .await
+                        .unwrap()
+                })
+            })
+            .deterministic()
+            .settle_time(Duration::from_secs(2))
+            .check_outcome(|_, _, trace| {
+                trace.went_through("gantry_unlock", &["Executing", "Failed", "Bypassed"])?;
+                trace.retried("gantry_unlock", 0)?;
+                trace.went_through("gantry_calibrate", &["Executing", "Failed", "Fatal"])?;
+                trace.ended("gantry_calibrate", "Fatal")?;
+                Ok(())
+            }),
+        ),
+        Box::new(
+            ModelScenario::new(
+                "failed_fatal",
+                crate::model::failed_fatal::model,
+                |_, state| flag_is_set(state, "failed"),
+            )
+            .injection(|sp_id, con| {
+                Box::pin(async move {
+                    crate::model::failed_fatal::run_emultaion(&sp_id, con)
+                        .await
+                        .unwrap()
+                })
+            })
+            .deterministic()
+            .settle_time(Duration::from_secs(2))
+            .check_outcome(|_, _, trace| {
+                trace.went_through("gantry_unlock", &["Executing", "Failed", "Fatal"])?;
+                trace.retried("gantry_unlock", 0)?;
+                trace.ended("gantry_unlock", "Fatal")?;
+                Ok(())
+            }),
+        ),
+        Box::new(
+            ModelScenario::new(
+                "failed_retries",
+                crate::model::failed_retries::model,
+                |_, state| flag_is_set(state, "failed"),
+            )
+            .injection(|sp_id, con| {
+                Box::pin(async move {
+                    crate::model::failed_retries::run_emultaion(&sp_id, con)
+                        .await
+                        .unwrap()
+                })
+            })
+            .deterministic()
+            .settle_time(Duration::from_secs(4))
+            .check_outcome(|_, _, trace| {
+                trace.retried("gantry_unlock", 2)?;
+                trace.ended("gantry_unlock", "Bypassed")?;
+                trace.completed_before_started("gantry_unlock", "gantry_calibrate")?;
+                trace.retried("gantry_calibrate", 2)?;
+                trace.ended("gantry_calibrate", "Fatal")?;
+                Ok(())
+            }),
+        ),
+        Box::new(
+            ModelScenario::new(
+                "incoming_goals",
+                crate::model::incoming_goals::model,
+                |_, state| counter_is(state, 10),
+            )
+            .injection(|sp_id, con| {
+                Box::pin(async move {
+                    crate::model::incoming_goals::run_emultaion(&sp_id, con)
+                        .await
+                        .unwrap()
+                })
+            })
+            .script(push_later_goals)
+            .deterministic()
+            .settle_time(Duration::from_secs(2))
+            .timeout(Duration::from_secs(60))
+            .check_outcome(|_, _, trace| {
+                for position in ["a", "b", "c", "d", "e", "f", "g"] {
+                    completed(trace, &[&format!("robot_move_to_{position}")])?;
+                }
+                // The high priority goals come before the normal ones pushed earlier.
+                trace.completed_before_started("robot_move_to_f", "robot_move_to_d")?;
+                trace.completed_before_started("robot_move_to_g", "robot_move_to_d")?;
+                trace.completed_before_started("robot_move_to_d", "robot_move_to_e")?;
+                Ok(())
+            }),
+        ),
+        Box::new(
+            ModelScenario::new(
+                "maintenance_window",
+                crate::model::maintenance_window::model,
+                |_, state| estimated(state, "gantry_position_estimated") == "a",
+            )
+            .emulators(vec![gantry("gantry")])
+            .injection(|sp_id, con| {
+                Box::pin(async move {
+                    crate::model::maintenance_window::run_emultaion(&sp_id, con)
+                        .await
+                        .unwrap()
+                })
+            })
+            .deterministic()
+            .check_outcome(|_, state, _| {
+                expect(
+                    "gantry_refused_maintenance",
+                    flag_is_set(state, "gantry_refused_maintenance"),
+                    true,
+                )?;
+                // Only the move during the maintenance window failed
+                expect(
+                    "gantry_total_fail_counter",
+                    int(state, "gantry_total_fail_counter"),
+                    1,
+                )?;
+                expect(
+                    "gantry_downtime_estimated",
+                    flag_is_set(state, "gantry_downtime_estimated"),
+                    false,
+                )?;
+                expect(
+                    "gantry_resource_online",
+                    flag_is_set(state, "gantry_resource_online"),
+                    true,
+                )?;
+                Ok(())
+            }),
+        ),
+        Box::new(
+            ModelScenario::new(
+                "multi_robot",
+                crate::model::multi_robot::model,
+                |_, state| {
+                    estimated(state, "robot_1_position_estimated") == "a"
+                        && estimated(state, "robot_2_position_estimated") == "b"
+                },
+            )
+            .emulators(
+                crate::model::multi_robot::ROBOTS
+                    .iter()
+                    .map(|name| robot(name))
+                    .collect(),
+            )
+            .injection(|sp_id, con| {
+                Box::pin(async move {
+                    crate::model::multi_robot::run_emultaion(&sp_id, con)
+                        .await
+                        .unwrap()
+                })
+            })
+            .deterministic()
+            .check_outcome(|_, state, _| {
+                // Each robot only ever executed its own requests
+                for robot in crate::model::multi_robot::ROBOTS {
+                    let fail_counter = format!("{robot}_total_fail_counter");
+                    expect(&fail_counter, int(state, &fail_counter), 0)?;
+                    let request_state = format!("{robot}_request_state");
+                    expect(
+                        &request_state,
+                        estimated(state, &request_state).as_str(),
+                        "initial",
+                    )?;
+                }
+                Ok(())
+            }),
+        ),
+        // Not deterministic: the operator and the runner both write the state of the failed
+        // operation, and the trace depends on which of them gets there first.
+        Box::new(
+            ModelScenario::new(
+                "operator_recovery",
+                crate::model::operator_recovery::model,
+                |_, state| estimated(state, "operator_last_action") == "bypass",
+            )
+            .emulators(vec![
+                robot("robot"),
+                gantry("gantry"),
+                ScenarioEmulator::Operator,
+            ])
+            .injection(|sp_id, con| {
+                Box::pin(async move {
+                    crate::model::operator_recovery::run_emultaion(&sp_id, con)
+                        .await
+                        .unwrap()
+                })
+            })
+            .settle_time(Duration::from_secs(2))
+            .check_outcome(|_, state, trace| {
+                let bypassed = OperationState::Bypassed.to_string();
+                expect(
+                    "op_gantry_unlock",
+                    estimated(state, "op_gantry_unlock"),
+                    bypassed,
+                )?;
+                let last_operation = estimated(state, "operator_last_operation");
+                expect(
+                    "operator_last_operation",
+                    last_operation.as_str(),
+                    "op_gantry_unlock",
+                )?;
+                expect(
+                    "operator_intervention_counter",
+                    int(state, "operator_intervention_counter"),
+                    1,
+                )?;
+                trace.ended("gantry_unlock", "Bypassed")?;
+                Ok(())
+            }),
+        ),
+        Box::new(
+            ModelScenario::new(
+                "position_catalog",
+                crate::model::position_catalog::model,
+                |_, state| estimated(state, "robot_position_estimated") == "pipe_blue_box",
+            )
+            .emulators(vec![robot("robot")])
+            .injection(|sp_id, con| {
+                Box::pin(async move {
+                    crate::model::position_catalog::run_emultaion(&sp_id, con)
+                        .await
+                        .unwrap()
+                })
+            })
+            .deterministic()
+            .check_outcome(|_, state, _| {
+                expect(
+                    "robot_refused_position",
+                    flag_is_set(state, "robot_refused_position"),
+                    true,
+                )?;
+                // The robot never went to pipe_blu_box
+                expect(
+                    "robot_total_fail_counter",
+                    int(state, "robot_total_fail_counter"),
+                    1,
+                )?;
+                Ok(())
+            }),
+        ),
+        Box::new(
+            ModelScenario::new(
+                "progress_feedback",
+                crate::model::progress_feedback::model,
+                |_, state| estimated(state, "gantry_position_estimated") == "a",
+            )
+            .emulators(vec![gantry("gantry")])
+            .injection(|sp_id, con| {
+                Box::pin(async move {
+                    crate::model::progress_feedback::run_emultaion(&sp_id, con)
+                        .await
+                        .unwrap()
+                })
+            })
+            .deterministic()
+            .check_outcome(|_, state, _| {
+                expect("gantry_progress", int(state, "gantry_progress"), 100)?;
+                expect(
+                    "gantry_remaining_time",
+                    int(state, "gantry_remaining_time"),
+                    0,
+                )?;
+                Ok(())
+            }),
+        ),
+        Box::new(
+            ModelScenario::new(
+                "queued_jobs",
+                crate::model::queued_jobs::model,
+                |_, state| {
+                    let statuses =
+                        crate::get_string_array(state, "gantry_queue_status_estimated", LOG_TARGET);
+                    crate::job_status(&statuses, "job_2") == Some("succeeded".to_string())
+                },
+            )
+            .emulators(vec![
+                gantry("gantry"),
+                ScenarioEmulator::Queue("gantry".to_string()),
+            ])
+            .injection(|sp_id, con| {
+                Box::pin(async move {
+                    crate::model::queued_jobs::run_emultaion(&sp_id, con)
+                        .await
+                        .unwrap()
+                })
+            })
+            .deterministic()
+            .check_outcome(|_, state, _| {
+                let statuses =
+                    crate::get_string_array(state, "gantry_queue_status_estimated", LOG_TARGET);
+                let job_1 = crate::job_status(&statuses, "job_1");
+                expect("job_1", job_1.as_deref(), Some("succeeded"))?;
+                let job_3 = crate::job_status(&statuses, "job_3");
+                expect("job_3", job_3.as_deref(), Some("rejected"))?;
+                // The jobs were executed in order, and the rejected one never reached the gantry
+                let position = estimated(state, "gantry_position_estimated");
+                expect("gantry_position_estimated", position.as_str(), "a")?;
+                let calibrated = flag_is_set(state, "gantry_calibrated_estimated");
+                expect("gantry_calibrated_estimated", calibrated, true)?;
+                // All three jobs were taken over, the list itself is left as the caller wrote it
+                let consumed = int(state, "gantry_queue_consumed_estimated");
+                expect("gantry_queue_consumed_estimated", consumed, 3)?;
+                let request_state = estimated(state, "gantry_request_state");
+                expect("gantry_request_state", request_state.as_str(), "initial")?;
+                Ok(())
+            }),
+        ),
+        Box::new(
+            ModelScenario::new("replan", crate::model::replan::model, |_, state| {
+                estimated(state, "robot_mounted_estimated") == "suction_tool"
+            })
+            .injection(|sp_id, con| {
+                Box::pin(async move {
+                    crate::model::replan::run_emultaion(&sp_id, con)
+                        .await
+                        .unwrap()
+                })
+            })
+            .deterministic()
+            .settle_time(Duration::from_secs(2))
+            .check_outcome(|_, _, trace| {
+                let operations = [
+                    "gantry_unlock",
+                    "gantry_calibrate",
+                    "gantry_lock",
+                    "robot_mount_suction_tool",
+                ];
+                completed(trace, &operations)?;
+                trace.completed_before_started("gantry_calibrate", "gantry_lock")?;
+                trace.completed_before_started("gantry_lock", "robot_mount_suction_tool")?;
+                Ok(())
+            }),
+        ),
+        Box::new(
+            ModelScenario::new(
+                "scheduled_goals",
+                crate::model::scheduled_goals::model,
+                |_, state| counter_is(state, 5),
+            )
+            .injection(|sp_id, con| {
+                Box::pin(async move {
+                    crate::model::scheduled_goals::run_emultaion(&sp_id, con)
+                        .await
+                        .unwrap()
+                })
+            })
+            .deterministic()
+            .settle_time(Duration::from_secs(2))
+            .check_outcome(|_, _, trace| {
+                // The goals are b, a, c, b, c in that order.
+                completed(
+                    trace,
+                    &["robot_move_to_a", "robot_move_to_b", "robot_move_to_c"],
+                )?;
+                trace.completed_before_started("robot_move_to_b", "robot_move_to_a")?;
+                trace.completed_before_started("robot_move_to_a", "robot_move_to_c")?;
+                Ok(())
+            }),
+        ),
+        Box::new(
+            ModelScenario::new(
+                "shared_gantry",
+                crate::model::shared_gantry::model,
+                |_, state| flag_is_set(state, "gantry_done"),
+            )
+            .emulators(vec![gantry("gantry")])
+            .injection(|sp_id, con| {
+                Box::pin(async move {
+                    crate::model::shared_gantry::run_emultaion(&sp_id, con)
+                        .await
+                        .unwrap()
+                })
+            })
+            .deterministic()
+            .check_outcome(|_, state, _| {
+                let position = estimated(state, "gantry_position_estimated");
+                expect("gantry_position_estimated", position.as_str(), "a")?;
+                // Released by us, and nobody else is waiting
+                let owner = estimated(state, "gantry_owner_estimated");
+                expect("gantry_owner_estimated", owner.as_str(), "unknown")?;
+                let queue =
+                    state.get_array_or_default_to_empty("gantry_owner_queue_estimated", LOG_TARGET);
+                expect("gantry_owner_queue_estimated", queue.len(), 0)?;
+                // The only failure is our first reservation, rejected while "other_sp" held the gantry
+                expect(
+                    "gantry_total_fail_counter",
+                    int(state, "gantry_total_fail_counter"),
+                    1,
+                )?;
+                Ok(())
+            }),
+        ),
+        Box::new(
+            ModelScenario::new(
+                "sop_alternative",
+                crate::model::sop_alternative::model,
+                |_, state| flag_is_set(state, "done"),
+            )
+            .injection(|_, con| {
+                Box::pin(async move {
+                    crate::model::sop_alternative::run_emultaion(con)
+                        .await
+                        .unwrap()
+                })
+            })
+            .deterministic()
+            .settle_time(Duration::from_secs(2))
+            .timeout(Duration::from_secs(15))
+            .trace_kind(TraceKind::SopOperations)
+            .check_outcome(|_, _, trace| completed(trace, &["robot_move_to_b"])),
+        ),
+        // Not deterministic: the branches run on two emulators in the same ticks, and the order
+        // of their entries in the trace depends on which of them answers first.
+        Box::new(
+            ModelScenario::new(
+                "sop_parallel",
+                crate::model::sop_parallel::model,
+                |_, state| flag_is_set(state, "done"),
+            )
+            .injection(|_, con| {
+                Box::pin(async move {
+                    crate::model::sop_parallel::run_emultaion(con)
+                        .await
+                        .unwrap()
+                })
+            })
+            .settle_time(Duration::from_secs(2))
+            .timeout(Duration::from_secs(15))
+            .trace_kind(TraceKind::SopOperations)
+            .check_outcome(|_, _, trace| {
+                completed(trace, &["robot_move_to_a", "gantry_move_to_b"])
+            }),
+        ),
+        Box::new(
+            ModelScenario::new(
+                "sop_sequence",
+                crate::model::sop_sequence::model,
+                |_, state| flag_is_set(state, "done"),
+            )
+            .injection(|_, con| {
+                Box::pin(async move {
+                    crate::model::sop_sequence::run_emultaion(con)
+                        .await
+                        .unwrap()
+                })
+            })
+            .deterministic()
+            .settle_time(Duration::from_secs(2))
+            .timeout(Duration::from_secs(20))
+            .trace_kind(TraceKind::SopOperations)
+            .check_outcome(|_, _, trace| {
+                completed(trace, &["robot_move_to_a", "robot_move_to_b"])?;
+                trace.ran("robot_move_to_a", 3)?;
+                trace.ran("robot_move_to_b", 2)?;
+                Ok(())
+            }),
+        ),
+        Box::new(
+            ModelScenario::new(
+                "speed_payload",
+                crate::model::speed_payload::model,
+                |_, state| estimated(state, "gantry_position_estimated") == "a",
+            )
+            .emulators(vec![gantry("gantry")])
+            .injection(|sp_id, con| {
+                Box::pin(async move {
+                    crate::model::speed_payload::run_emultaion(&sp_id, con)
+                        .await
+                        .unwrap()
+                })
+            })
+            .deterministic()
+            .check_outcome(|_, state, _| {
+                expect(
+                    "gantry_refused_speed",
+                    flag_is_set(state, "gantry_refused_speed"),
+                    true,
+                )?;
+                // The gantry never went to b
+                expect(
+                    "gantry_total_fail_counter",
+                    int(state, "gantry_total_fail_counter"),
+                    1,
+                )?;
+                Ok(())
+            }),
+        ),
+        // Not deterministic, the runner checks the timeouts against the system time.
+        Box::new(
+            ModelScenario::new(
+                "timeout_bypass",
+                crate::model::timeout_bypass::model,
+                |_, state| flag_is_set(state, "timeout"),
+            )
+            .injection(|sp_id, con| {
+                Box::pin(async move {
+                    crate::model::timeout_bypass::run_emultaion(&sp_id, con)
+                        .await
+                        .unwrap()
+                })
+            })
+            .wall_clock_timeouts()
+            .settle_time(Duration::from_secs(2))
+            .check_outcome(|_, _, trace| {
+                for operation in ["emulate_timeout_bypass", "emulate_timeout_bypass_2"] {
+                    trace.went_through(operation, &["Executing", "Timedout", "Bypassed"])?;
+                    trace.ended(operation, "Bypassed")?;
+                }
+                Ok(())
+            }),
+        ),
+        // Not deterministic, the runner checks the timeouts against the system time.
+        Box::new(
+            ModelScenario::new(
+                "timeout_disabled",
+                crate::model::timeout_disabled::model,
+                |_, state| flag_is_set(state, "timeout"),
+            )
+            .injection(|sp_id, con| {
+                Box::pin(async move {
+                    crate::model::timeout_disabled::run_emultaion(&sp_id, con)
+                        .await
+                        .unwrap()
+                })
+            })
+            .wall_clock_timeouts()
+            .settle_time(Duration::from_secs(2))
+            .timeout(Duration::from_secs(10))
+            .check_outcome(|_, _, trace| {
+                trace.went_through(
+                    "emulate_timeout_disabled",
+                    &["Disabled", "Timedout", "Fatal"],
+                )?;
+                trace.ended("emulate_timeout_disabled", "Fatal")?;
+                Ok(())
+            }),
+        ),
+        // Not deterministic, the runner checks the timeouts against the system time.
+        Box::new(
+            ModelScenario::new(
+                "timeout_fatal",
+                crate::model::timeout_fatal::model,
+                |_, state| flag_is_set(state, "timeout"),
+            )
+            .injection(|sp_id, con| {
+                Box::pin(async move {
+                    crate::model::timeout_fatal::run_emultaion(&sp_id, con)
+                        .await
+                        .unwrap()
+                })
+            })
+            .wall_clock_timeouts()
+            .settle_time(Duration::from_secs(2))
+            .check_outcome(|_, _, trace| {
+                trace.went_through("emulate_timeout", &["Executing", "Timedout", "Fatal"])?;
+                trace.retried("emulate_timeout", 0)?;
+                trace.ended("emulate_timeout", "Fatal")?;
+                Ok(())
+            }),
+        ),
+        // Not deterministic, the runner checks the timeouts against the system time.
+        Box::new(
+            ModelScenario::new(
+                "timeout_retries",
+                crate::model::timeout_retries::model,
+                |_, state| flag_is_set(state, "timeout"),
+            )
+            .injection(|sp_id, con| {
+                Box::pin(async move {
+                    crate::model::timeout_retries::run_emultaion(&sp_id, con)
+                        .await
+                        .unwrap()
+                })
+            })
+            .wall_clock_timeouts()
+            .settle_time(Duration::from_secs(2))
+            .check_outcome(|_, _, trace| {
+                trace.retried("emulate_timeout_retries", 2)?;
+                trace.ended("emulate_timeout_retries", "Fatal")?;
+                Ok(())
+            }),
+        ),
+    ]
+}
+
+pub fn scenario_names() -> Vec<String> {
+    scenarios()
+        .iter()
+        .map(|scenario| scenario.name().to_string())
+        .collect()
+}
+
+pub fn find_scenario(name: &str) -> Option<Box<dyn Scenario>> {
+    scenarios()
+        .into_iter()
+        .find(|scenario| scenario.name() == name)
+}
+
+#[test]
+fn test_scenario_names_are_unique() {
+    let mut names = scenario_names();
+    let number_of_scenarios = names.len();
+    names.sort();
+    names.dedup();
+    assert_eq!(names.len(), number_of_scenarios);
+    assert!(find_scenario("sop_sequence").is_some());
+    assert!(find_scenario("no_such_scenario").is_none());
+}
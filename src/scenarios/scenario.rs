@@ -0,0 +1,276 @@
+use futures::future::BoxFuture;
+use micro_sp::*;
+use redis::aio::MultiplexedConnection;
+use std::time::Duration;
+
+use crate::{InjectionScript, Trace};
+
+// The emulators that have to run next to the runner for a scenario.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScenarioEmulator {
+    Robot(String),
+    Gantry(String),
+    Nutrunner(String),
+    Agv(String),
+    Queue(String),
+    Operator,
+}
+
+// Where the runner logs the operations of a scenario.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TraceKind {
+    PlannedOperations,
+    SopOperations,
+    AutomaticOperations,
+}
+
+impl TraceKind {
+    pub fn key(&self, sp_id: &str) -> String {
+        match self {
+            TraceKind::PlannedOperations => format!("{sp_id}_logger_planned_operations"),
+            TraceKind::SopOperations => format!("{sp_id}_logger_sop_operations"),
+            TraceKind::AutomaticOperations => format!("{sp_id}_logger_automatic_operations"),
+        }
+    }
+}
+
+// What happens in the cell while the runner works, i.e. the run_emultaion of a model:
+// setting goals, changing emulation modes, etc. Gets the sp_id and its own connection.
+pub type Injection = fn(String, MultiplexedConnection) -> BoxFuture<'static, ()>;
+
+// A model together with everything needed to run it and judge the run:
+// the emulators to spawn, changes to the initial state, what to inject while it runs,
+// when it is done, and what the state and the operation trace should look like then.
+pub trait Scenario: Send + Sync {
+    fn name(&self) -> &str;
+
+    fn model(&self, sp_id: &str, state: &State) -> (Model, State);
+
+    fn emulators(&self) -> Vec<ScenarioEmulator> {
+        vec![
+            ScenarioEmulator::Robot("robot".to_string()),
+            ScenarioEmulator::Gantry("gantry".to_string()),
+        ]
+    }
+
+    fn number_of_timers(&self) -> u64 {
+        1
+    }
+
+    // Applied to the initial state after the model variables are added, e.g.
+    // ("gantry_emulate_failure_rate", EMULATE_FAILURE_ALWAYS.to_spvalue()).
+    fn overrides(&self, _sp_id: &str) -> Vec<(String, SPValue)> {
+        vec![]
+    }
+
+    fn injection(&self) -> Option<Injection> {
+        None
+    }
+
+    // Declarative injection, run next to the injection above, see scenarios/script.rs.
+    fn script(&self, _sp_id: &str) -> Option<InjectionScript> {
+        None
+    }
+
+    fn is_complete(&self, sp_id: &str, state: &State) -> bool;
+
+    // How long to keep running after completion, so that the operations can cycle
+    // through all their states before the trace is fetched.
+    fn settle_time(&self) -> Duration {
+        Duration::from_secs(0)
+    }
+
+    fn timeout(&self) -> Duration {
+        Duration::from_secs(30)
+    }
+
+    fn trace_kind(&self) -> TraceKind {
+        TraceKind::PlannedOperations
+    }
+
+    // Whether the model relies on operation timeouts. The runner checks those against the
+    // system time, not tokio's clock, so such a scenario doesn't run in paused time.
+    fn wall_clock_timeouts(&self) -> bool {
+        false
+    }
+
+    // Whether the scenario gives the same trace every run, i.e. nothing in it is random,
+    // so that its trace can be compared with a snapshot, see scenarios/snapshot.rs.
+    fn deterministic(&self) -> bool {
+        false
+    }
+
+    // Checked once the scenario is complete, an Err explains what was wrong.
+    fn check_outcome(&self, _sp_id: &str, _state: &State, _trace: &Trace) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+// A scenario built from the functions of a model module, so that the existing
+// models don't have to implement the trait themselves.
+pub struct ModelScenario {
+    pub name: String,
+    pub model: fn(&str, &State) -> (Model, State),
+    pub emulators: Vec<ScenarioEmulator>,
+    pub number_of_timers: u64,
+    pub overrides: Vec<(String, SPValue)>,
+    pub injection: Option<Injection>,
+    pub script: Option<fn(&str) -> InjectionScript>,
+    pub is_complete: fn(&str, &State) -> bool,
+    pub settle_time: Duration,
+    pub timeout: Duration,
+    pub trace_kind: TraceKind,
+    pub wall_clock_timeouts: bool,
+    pub deterministic: bool,
+    pub check_outcome: Option<fn(&str, &State, &Trace) -> Result<(), String>>,
+}
+
+impl ModelScenario {
+    pub fn new(
+        name: &str,
+        model: fn(&str, &State) -> (Model, State),
+        is_complete: fn(&str, &State) -> bool,
+    ) -> ModelScenario {
+        ModelScenario {
+            name: name.to_string(),
+            model,
+            emulators: vec![
+                ScenarioEmulator::Robot("robot".to_string()),
+                ScenarioEmulator::Gantry("gantry".to_string()),
+            ],
+            number_of_timers: 1,
+            overrides: vec![],
+            injection: None,
+            script: None,
+            is_complete,
+            settle_time: Duration::from_secs(0),
+            timeout: Duration::from_secs(30),
+            trace_kind: TraceKind::PlannedOperations,
+            wall_clock_timeouts: false,
+            deterministic: false,
+            check_outcome: None,
+        }
+    }
+
+    pub fn emulators(mut self, emulators: Vec<ScenarioEmulator>) -> ModelScenario {
+        self.emulators = emulators;
+        self
+    }
+
+    pub fn number_of_timers(mut self, number_of_timers: u64) -> ModelScenario {
+        self.number_of_timers = number_of_timers;
+        self
+    }
+
+    pub fn overrides(mut self, overrides: Vec<(&str, SPValue)>) -> ModelScenario {
+        self.overrides = overrides
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value))
+            .collect();
+        self
+    }
+
+    pub fn injection(mut self, injection: Injection) -> ModelScenario {
+        self.injection = Some(injection);
+        self
+    }
+
+    pub fn script(mut self, script: fn(&str) -> InjectionScript) -> ModelScenario {
+        self.script = Some(script);
+        self
+    }
+
+    pub fn settle_time(mut self, settle_time: Duration) -> ModelScenario {
+        self.settle_time = settle_time;
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> ModelScenario {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn trace_kind(mut self, trace_kind: TraceKind) -> ModelScenario {
+        self.trace_kind = trace_kind;
+        self
+    }
+
+    pub fn wall_clock_timeouts(mut self) -> ModelScenario {
+        self.wall_clock_timeouts = true;
+        self
+    }
+
+    pub fn deterministic(mut self) -> ModelScenario {
+        self.deterministic = true;
+        self
+    }
+
+    pub fn check_outcome(
+        mut self,
+        check_outcome: fn(&str, &State, &Trace) -> Result<(), String>,
+    ) -> ModelScenario {
+        self.check_outcome = Some(check_outcome);
+        self
+    }
+}
+
+impl Scenario for ModelScenario {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn model(&self, sp_id: &str, state: &State) -> (Model, State) {
+        (self.model)(sp_id, state)
+    }
+
+    fn emulators(&self) -> Vec<ScenarioEmulator> {
+        self.emulators.clone()
+    }
+
+    fn number_of_timers(&self) -> u64 {
+        self.number_of_timers
+    }
+
+    fn overrides(&self, _sp_id: &str) -> Vec<(String, SPValue)> {
+        self.overrides.clone()
+    }
+
+    fn injection(&self) -> Option<Injection> {
+        self.injection
+    }
+
+    fn script(&self, sp_id: &str) -> Option<InjectionScript> {
+        self.script.map(|script| script(sp_id))
+    }
+
+    fn is_complete(&self, sp_id: &str, state: &State) -> bool {
+        (self.is_complete)(sp_id, state)
+    }
+
+    fn settle_time(&self) -> Duration {
+        self.settle_time
+    }
+
+    fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    fn trace_kind(&self) -> TraceKind {
+        self.trace_kind
+    }
+
+    fn wall_clock_timeouts(&self) -> bool {
+        self.wall_clock_timeouts
+    }
+
+    fn deterministic(&self) -> bool {
+        self.deterministic
+    }
+
+    fn check_outcome(&self, sp_id: &str, state: &State, trace: &Trace) -> Result<(), String> {
+        match self.check_outcome {
+            Some(check_outcome) => check_outcome(sp_id, state, trace),
+            None => Ok(()),
+        }
+    }
+}
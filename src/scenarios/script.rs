@@ -0,0 +1,411 @@
+use micro_sp::{running::goal_runner::GoalPriority, *};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::{EMULATOR_TICK_INTERVAL, EmulatedInstant, StateBackend, emulated_interval};
+
+// What happens in the cell while the runner works, written down as steps instead of a
+// hand-written run_emultaion. A step fires once, either at a time after the script started
+// or the first time a condition holds on the state:
+//
+// InjectionScript::new()
+//     .at(Duration::from_secs(2), ScriptAction::set("gantry_emulate_failure_rate", 1.to_spvalue()))
+//     .at(Duration::from_secs(5), ScriptAction::push_goal("var:robot_position_estimated == f", GoalPriority::High))
+//     .when(ScriptCondition::operation_enters("gantry_lock", "executing"), ScriptAction::set("robot_resource_online", false.to_spvalue()))
+//
+// or the same as text, one step per line, see InjectionScript::parse:
+//
+// at 2s set gantry_emulate_failure_rate = 1
+// at 5s push goal var:robot_position_estimated == f with high priority
+// when op_gantry_lock enters executing set robot_resource_online = false
+
+pub enum ScriptCondition {
+    // The operation state variable, e.g. "op_gantry_lock", has this value.
+    OperationEnters { operation: String, state: String },
+    // The variable has this value.
+    Equals { key: String, value: SPValue },
+}
+
+impl ScriptCondition {
+    pub fn operation_enters(operation: &str, state: &str) -> ScriptCondition {
+        let operation = match operation.starts_with("op_") {
+            true => operation.to_string(),
+            false => format!("op_{operation}"),
+        };
+        ScriptCondition::OperationEnters {
+            operation,
+            state: state.to_string(),
+        }
+    }
+
+    pub fn equals(key: &str, value: SPValue) -> ScriptCondition {
+        ScriptCondition::Equals {
+            key: key.to_string(),
+            value,
+        }
+    }
+
+    pub fn holds(&self, state: &State, log_target: &str) -> bool {
+        match self {
+            ScriptCondition::OperationEnters {
+                operation,
+                state: operation_state,
+            } => state
+                .get_string_or_default_to_unknown(operation, log_target)
+                .eq_ignore_ascii_case(operation_state),
+            ScriptCondition::Equals { key, value } => state
+                .state
+                .get(key)
+                .is_some_and(|assignment| &assignment.val == value),
+        }
+    }
+}
+
+pub enum ScriptAction {
+    Set(Vec<(String, SPValue)>),
+    // Appended to "{sp_id}_incoming_goals".
+    PushGoal {
+        goal: String,
+        priority: GoalPriority,
+    },
+}
+
+impl ScriptAction {
+    pub fn set(key: &str, value: SPValue) -> ScriptAction {
+        ScriptAction::Set(vec![(key.to_string(), value)])
+    }
+
+    pub fn push_goal(goal: &str, priority: GoalPriority) -> ScriptAction {
+        ScriptAction::PushGoal {
+            goal: goal.to_string(),
+            priority,
+        }
+    }
+}
+
+pub enum ScriptTrigger {
+    At(Duration),
+    When(ScriptCondition),
+}
+
+pub struct ScriptStep {
+    pub trigger: ScriptTrigger,
+    pub action: ScriptAction,
+}
+
+#[derive(Default)]
+pub struct InjectionScript {
+    pub steps: Vec<ScriptStep>,
+    // How long run_script waits for the steps at most, e.g. for a condition that might
+    // never hold. Without one it waits until all steps fired or the task is aborted.
+    pub deadline: Option<Duration>,
+}
+
+// Values in a script are bools, integers, floats or else strings.
+pub fn parse_value(value: &str) -> SPValue {
+    if let Ok(value) = value.parse::<bool>() {
+        value.to_spvalue()
+    } else if let Ok(value) = value.parse::<i64>() {
+        value.to_spvalue()
+    } else if let Ok(value) = value.parse::<f64>() {
+        value.to_spvalue()
+    } else {
+        value.to_spvalue()
+    }
+}
+
+fn parse_duration(duration: &str) -> Result<Duration, String> {
+    let parsed = match duration.strip_suffix("ms") {
+        Some(ms) => ms.parse::<u64>().map(Duration::from_millis).ok(),
+        None => duration
+            .strip_suffix('s')
+            .and_then(|s| s.parse::<f64>().ok())
+            .filter(|s| s.is_finite() && *s >= 0.0)
+            .map(Duration::from_secs_f64),
+    };
+    parsed.ok_or_else(|| format!("Invalid duration '{duration}', expected e.g. 500ms or 2s."))
+}
+
+fn parse_assignment(assignment: &str) -> Result<(String, SPValue), String> {
+    match assignment.split_once('=') {
+        Some((key, value)) if !key.trim().is_empty() && !value.trim().is_empty() => {
+            Ok((key.trim().to_string(), parse_value(value.trim())))
+        }
+        _ => Err(format!(
+            "Invalid assignment '{assignment}', expected 'key = value'."
+        )),
+    }
+}
+
+fn parse_action(action: &str) -> Result<ScriptAction, String> {
+    if let Some(assignment) = action.strip_prefix("set ") {
+        parse_assignment(assignment).map(|(key, value)| ScriptAction::Set(vec![(key, value)]))
+    } else if let Some(goal) = action.strip_prefix("push goal ") {
+        let (goal, priority) = match goal.rsplit_once(" with ") {
+            Some((goal, priority)) => match priority.trim().to_lowercase().as_str() {
+                "normal priority" => (goal, GoalPriority::Normal),
+                "high priority" => (goal, GoalPriority::High),
+                _ => return Err(format!("Unknown priority '{priority}'.")),
+            },
+            None => (goal, GoalPriority::Normal),
+        };
+        Ok(ScriptAction::push_goal(goal.trim(), priority))
+    } else {
+        Err(format!(
+            "Unknown action '{action}', expected 'set ...' or 'push goal ...'."
+        ))
+    }
+}
+
+// Splits "<condition> set ..." or "<condition> push goal ..." at the action.
+fn split_action(step: &str) -> Result<(&str, ScriptAction), String> {
+    let position = [" set ", " push goal "]
+        .iter()
+        .filter_map(|keyword| step.find(keyword))
+        .min()
+        .ok_or_else(|| format!("No action in '{step}'."))?;
+    Ok((&step[..position], parse_action(step[position..].trim())?))
+}
+
+fn parse_step(line: &str) -> Result<ScriptStep, String> {
+    if let Some(step) = line.strip_prefix("at ") {
+        let (duration, action) = split_action(step)?;
+        Ok(ScriptStep {
+            trigger: ScriptTrigger::At(parse_duration(duration.trim())?),
+            action,
+        })
+    } else if let Some(step) = line.strip_prefix("when ") {
+        let (condition, action) = split_action(step)?;
+        let condition = match condition.split_once(" enters ") {
+            Some((operation, state)) => {
+                ScriptCondition::operation_enters(operation.trim(), state.trim())
+            }
+            None => match condition.split_once("==") {
+                Some((key, value)) => {
+                    ScriptCondition::equals(key.trim(), parse_value(value.trim()))
+                }
+                None => {
+                    return Err(format!(
+                        "Invalid condition '{condition}', expected 'op_x enters state' or 'key == value'."
+                    ));
+                }
+            },
+        };
+        Ok(ScriptStep {
+            trigger: ScriptTrigger::When(condition),
+            action,
+        })
+    } else {
+        Err(format!("Steps start with 'at' or 'when', got '{line}'."))
+    }
+}
+
+impl InjectionScript {
+    pub fn new() -> InjectionScript {
+        InjectionScript::default()
+    }
+
+    pub fn at(mut self, time: Duration, action: ScriptAction) -> InjectionScript {
+        self.steps.push(ScriptStep {
+            trigger: ScriptTrigger::At(time),
+            action,
+        });
+        self
+    }
+
+    pub fn when(mut self, condition: ScriptCondition, action: ScriptAction) -> InjectionScript {
+        self.steps.push(ScriptStep {
+            trigger: ScriptTrigger::When(condition),
+            action,
+        });
+        self
+    }
+
+    pub fn deadline(mut self, deadline: Duration) -> InjectionScript {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    // One step per line, empty lines and lines starting with # are skipped.
+    pub fn parse(script: &str) -> Result<InjectionScript, String> {
+        let mut steps = vec![];
+        for (number, line) in script.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            steps.push(parse_step(line).map_err(|e| format!("Line {}: {}", number + 1, e))?);
+        }
+        Ok(InjectionScript {
+            steps,
+            deadline: None,
+        })
+    }
+
+    pub fn from_file(path: &std::path::Path) -> Result<InjectionScript, String> {
+        let script = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        InjectionScript::parse(&script)
+    }
+}
+
+async fn apply_action<B: StateBackend + ?Sized>(
+    sp_id: &str,
+    action: ScriptAction,
+    state: &State,
+    backend: &Arc<B>,
+    log_target: &str,
+) {
+    let new_state = match action {
+        ScriptAction::Set(assignments) => {
+            let mut new_state = state.clone();
+            for (key, value) in assignments {
+                match state.state.contains_key(&key) {
+                    true => {
+                        log::info!(target: log_target, "Setting {} to {:?}.", key, value);
+                        new_state = new_state.update(&key, value);
+                    }
+                    false => log::warn!(target: log_target, "Key '{}' is not in the state.", key),
+                }
+            }
+            new_state
+        }
+        ScriptAction::PushGoal { goal, priority } => {
+            let key = format!("{sp_id}_incoming_goals");
+            let mut goals = state.get_array_or_default_to_empty(&key, log_target);
+            log::info!(target: log_target, "Pushing goal: {}.", goal);
+            goals.push(goal_string_to_sp_value(&goal, priority));
+            state.update(&key, goals.to_spvalue())
+        }
+    };
+    backend
+        .set_state(&state.get_diff_partial_state(&new_state))
+        .await;
+}
+
+// Runs the steps of the script against the state until all of them fired, or until its
+// deadline. Times are in emulated time, see emulators/clock.rs.
+pub async fn run_script<B: StateBackend + ?Sized>(
+    sp_id: &str,
+    script: InjectionScript,
+    backend: &Arc<B>,
+    log_target: &str,
+) {
+    let mut interval = emulated_interval(Duration::from_millis(EMULATOR_TICK_INTERVAL));
+    let started = EmulatedInstant::now();
+    let mut pending: Vec<Option<ScriptStep>> = script.steps.into_iter().map(Some).collect();
+
+    while pending.iter().any(|step| step.is_some()) {
+        interval.tick().await;
+        if script
+            .deadline
+            .is_some_and(|deadline| started.elapsed() >= deadline)
+        {
+            log::warn!(target: log_target,
+                "Script deadline passed, {} steps never fired.",
+                pending.iter().filter(|step| step.is_some()).count()
+            );
+            return;
+        }
+        let Some(mut state) = backend.get_full_state().await else {
+            log::error!(target: log_target, "Failed to get full state.");
+            continue;
+        };
+        for step in pending.iter_mut() {
+            let fires = match step.as_ref().map(|step| &step.trigger) {
+                Some(ScriptTrigger::At(time)) => started.elapsed() >= *time,
+                Some(ScriptTrigger::When(condition)) => condition.holds(&state, log_target),
+                None => false,
+            };
+            if fires {
+                if let Some(step) = step.take() {
+                    apply_action(sp_id, step.action, &state, backend, log_target).await;
+                    // Fetched again, so that the next steps see what this one changed.
+                    match backend.get_full_state().await {
+                        Some(new_state) => state = new_state,
+                        None => {
+                            log::error!(target: log_target, "Failed to get full state.");
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+    }
+    log::info!(target: log_target, "Script done.");
+}
+
+#[test]
+fn test_parse_script() {
+    let script = InjectionScript::parse(
+        "# Break the gantry and then the robot\n\
+         at 2s set gantry_emulate_failure_rate = 1\n\
+         at 500ms push goal var:robot_position_estimated == f with high priority\n\
+         \n\
+         when op_gantry_lock enters executing set robot_resource_online = false\n\
+         when gantry_request_state == failed set robot_emulated_execution_time = 3000",
+    )
+    .unwrap();
+    assert_eq!(script.steps.len(), 4);
+    assert!(matches!(
+        &script.steps[0].trigger,
+        ScriptTrigger::At(time) if *time == Duration::from_secs(2)
+    ));
+    assert!(matches!(
+        &script.steps[1].action,
+        ScriptAction::PushGoal { goal, .. } if goal == "var:robot_position_estimated == f"
+    ));
+    assert!(matches!(
+        &script.steps[2].trigger,
+        ScriptTrigger::When(ScriptCondition::OperationEnters { operation, state })
+            if operation == "op_gantry_lock" && state == "executing"
+    ));
+    assert!(matches!(
+        &script.steps[3].action,
+        ScriptAction::Set(assignments) if assignments[0].1 == 3000.to_spvalue()
+    ));
+
+    assert!(InjectionScript::parse("at soon set x = 1").is_err());
+    assert!(InjectionScript::parse("when x == 1 wait").is_err());
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_run_script() {
+    let state = crate::model::state::state();
+    let backend: Arc<dyn StateBackend> = Arc::new(crate::InMemoryBackend::new(&state));
+    let script = InjectionScript::new()
+        .at(
+            Duration::from_secs(2),
+            ScriptAction::set("gantry_request_state", "failed".to_spvalue()),
+        )
+        .when(
+            ScriptCondition::equals("gantry_request_state", "failed".to_spvalue()),
+            ScriptAction::set("robot_resource_online", true.to_spvalue()),
+        );
+
+    let started = EmulatedInstant::now();
+    run_script("sp1", script, &backend, "test_run_script").await;
+    assert!(started.elapsed() >= Duration::from_secs(2));
+    let state = backend.get_full_state().await.unwrap();
+    assert!(state.get_bool_or_default_to_false("robot_resource_online", "test_run_script"));
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_run_script_deadline() {
+    let state = crate::model::state::state();
+    let backend: Arc<dyn StateBackend> = Arc::new(crate::InMemoryBackend::new(&state));
+    let script = InjectionScript::new()
+        .when(
+            ScriptCondition::equals("gantry_request_state", "never".to_spvalue()),
+            ScriptAction::set("robot_resource_online", true.to_spvalue()),
+        )
+        .deadline(Duration::from_secs(5));
+
+    let started = EmulatedInstant::now();
+    run_script("sp1", script, &backend, "test_run_script_deadline").await;
+    assert!(started.elapsed() >= Duration::from_secs(5));
+    let state = backend.get_full_state().await.unwrap();
+    assert!(
+        !state.get_bool_or_default_to_false("robot_resource_online", "test_run_script_deadline")
+    );
+}
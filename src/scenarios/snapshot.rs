@@ -0,0 +1,140 @@
+use regex::Regex;
+use std::path::PathBuf;
+
+use crate::{Harness, HarnessOptions, Scenario, Trace, TraceMismatch, TracedOperation, line_diff};
+
+// Golden-file testing of operation traces. A trace is normalized, i.e. the parts that
+// change from run to run are replaced with placeholders:
+// op_gantry_unlock_<id>
+//   Initial | Starting
+//   Executing | Executing
+//   Executing | Failing after <duration>
+//   Failed | Retrying 1/2
+// and compared with tests/snapshots/<scenario>.trace. Set BLESS_SNAPSHOTS=1 to write the
+// current traces as the new snapshots instead. Scenarios with random execution times or
+// failures, timeouts on the system time or races between tasks don't give the same trace
+// every run, so only the ones marked deterministic have a snapshot.
+pub static BLESS_SNAPSHOTS: &str = "BLESS_SNAPSHOTS";
+
+pub fn snapshot_path(scenario: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("snapshots")
+        .join(format!("{scenario}.trace"))
+}
+
+// The id of a run is whatever follows the name of its operation, see trace::operation_of.
+fn normalize_name(traced: &TracedOperation) -> String {
+    match &traced.operation {
+        Some(operation) => format!("op_{operation}_<id>"),
+        None => traced.name.clone(),
+    }
+}
+
+pub fn normalize_trace(trace: &Trace) -> String {
+    let time = Regex::new(r"\d{2}:\d{2}:\d{2}(\.\d+)?").unwrap();
+    let duration = Regex::new(r"\d+(\.\d+)?\s*(ms|milliseconds|s|sec|seconds)\b").unwrap();
+    let mut lines = vec![];
+    for operation in &trace.operations {
+        lines.push(normalize_name(operation));
+        for entry in &operation.entries {
+            let info = time.replace_all(&entry.info, "<time>");
+            let info = duration.replace_all(&info, "<duration>");
+            lines.push(format!("  {} | {}", entry.state, info));
+        }
+    }
+    lines.join("\n")
+}
+
+fn is_blessing() -> bool {
+    std::env::var(BLESS_SNAPSHOTS).is_ok_and(|value| value == "1" || value == "true")
+}
+
+pub fn assert_snapshot(scenario: &str, trace: &Trace) -> Result<(), TraceMismatch> {
+    let path = snapshot_path(scenario);
+    let actual = normalize_trace(trace);
+    if is_blessing() {
+        std::fs::create_dir_all(path.parent().unwrap())
+            .and_then(|_| std::fs::write(&path, format!("{actual}\n")))
+            .map_err(|e| TraceMismatch(format!("Failed to write {}: {}", path.display(), e)))?;
+        log::info!(target: "micro_sp_emulation::snapshot", "Blessed {}.", path.display());
+        return Ok(());
+    }
+    let expected = std::fs::read_to_string(&path).map_err(|_| {
+        TraceMismatch(format!(
+            "No snapshot in {}, run with {}=1 to record it. The trace was:\n{}",
+            path.display(),
+            BLESS_SNAPSHOTS,
+            actual
+        ))
+    })?;
+    let expected: Vec<String> = expected.trim_end().lines().map(|l| l.to_string()).collect();
+    let actual: Vec<String> = actual.lines().map(|l| l.to_string()).collect();
+    match expected == actual {
+        true => Ok(()),
+        false => Err(TraceMismatch(format!(
+            "The trace of {} doesn't match {} (- snapshot, + trace), run with {}=1 to update it.\n{}",
+            scenario,
+            path.display(),
+            BLESS_SNAPSHOTS,
+            line_diff(&expected, &actual)
+        ))),
+    }
+}
+
+// Runs the scenario to completion and compares its trace with the snapshot.
+pub async fn snapshot_scenario(scenario: &dyn Scenario) -> Result<(), String> {
+    let options = HarnessOptions::from_scenario(scenario);
+    let mut harness = Harness::start_scenario(scenario, options).await;
+    harness.complete(scenario).await?;
+    let trace = Trace::new(
+        &harness.trace(scenario.trace_kind()).await,
+        &harness.operations,
+    );
+    assert_snapshot(scenario.name(), &trace)?;
+    Ok(())
+}
+
+#[test]
+fn test_normalize_trace() {
+    use micro_sp::OperationState::*;
+
+    let operations = vec!["gantry".to_string(), "gantry_unlock".to_string()];
+    let trace = Trace::new(
+        &[vec![
+            crate::scenarios::trace::operation_log(
+                "op_gantry_unlock_V1_tG-R8",
+                1,
+                &[
+                    (Initial, "Starting"),
+                    (Executing, "Failing after 3000 ms at 12:01:02.345"),
+                ],
+            ),
+            crate::scenarios::trace::operation_log(
+                "op_robot_move_x_1",
+                2,
+                &[(Initial, "Starting")],
+            ),
+        ]],
+        &operations,
+    );
+    assert_eq!(
+        normalize_trace(&trace),
+        "op_gantry_unlock_<id>\n  Initial | Starting\n  Executing | Failing after <duration> at <time>\n\
+         op_robot_move_x_1\n  Initial | Starting"
+    );
+}
+
+// Runs the deterministic scenarios in paused time. Update their snapshots with
+// BLESS_SNAPSHOTS=1 cargo test test_scenario_snapshots
+#[tokio::test(start_paused = true)]
+async fn test_scenario_snapshots() {
+    let mut mismatches = vec![];
+    let scenarios = crate::scenarios().into_iter();
+    for scenario in scenarios.filter(|scenario| scenario.deterministic()) {
+        if let Err(mismatch) = snapshot_scenario(scenario.as_ref()).await {
+            mismatches.push(format!("{}:\n{}", scenario.name(), mismatch));
+        }
+    }
+    assert!(mismatches.is_empty(), "{}", mismatches.join("\n\n"));
+}
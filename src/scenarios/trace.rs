@@ -0,0 +1,446 @@
+use micro_sp::{Model, OperationLog, SOP};
+use std::fmt;
+
+// Assertions on the operation logs of the runner, i.e. the deserialized
+// "{sp_id}_logger_*" variables, instead of regexes over the formatted tables:
+//
+// let trace = Trace::new(
+//     &harness.trace(TraceKind::PlannedOperations).await,
+//     &harness.operations,
+// );
+// trace.went_through("gantry_unlock", &["Initial", "Executing", "Completed"])?;
+// trace.completed_before_started("gantry_unlock", "gantry_calibrate")?;
+// trace.retried("gantry_calibrate", 2)?;
+// trace.ended("gantry_calibrate", "Fatal")?;
+//
+// Operations are named like in Operation::new, the logs name them "op_{name}_{id}", so the
+// trace gets the operation names of the model to tell which operation a run belongs to.
+// If an operation ran more than once, the checks look at the latest run.
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceEntry {
+    pub state: String,
+    pub info: String,
+    pub timestamp_ms: i64,
+    // Position in the whole trace, to order entries logged in the same millisecond.
+    pub index: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TracedOperation {
+    pub name: String,
+    // The operation of the model that this is a run of, see operation_of.
+    pub operation: Option<String>,
+    pub entries: Vec<TraceEntry>,
+}
+
+impl TracedOperation {
+    pub fn states(&self) -> Vec<String> {
+        self.entries
+            .iter()
+            .map(|entry| entry.state.clone())
+            .collect()
+    }
+
+    // Every restart after the first one is a retry.
+    pub fn retries(&self) -> usize {
+        self.states()
+            .windows(2)
+            .filter(|pair| {
+                pair[1].eq_ignore_ascii_case("initial") && !pair[0].eq_ignore_ascii_case("initial")
+            })
+            .count()
+    }
+}
+
+// Why a trace assertion failed. Debug prints the message as it is, so that the diff
+// stays readable when a test unwraps the result.
+pub struct TraceMismatch(pub String);
+
+impl fmt::Display for TraceMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl fmt::Debug for TraceMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for TraceMismatch {}
+
+impl From<TraceMismatch> for String {
+    fn from(mismatch: TraceMismatch) -> String {
+        mismatch.0
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Trace {
+    pub operations: Vec<TracedOperation>,
+}
+
+impl Trace {
+    pub fn new(logger: &[Vec<OperationLog>], operations: &[String]) -> Trace {
+        let mut index = 0;
+        let mut traced = vec![];
+        for log in logger.iter().flatten() {
+            let mut entries = vec![];
+            for entry in &log.log {
+                entries.push(TraceEntry {
+                    state: entry.state.to_string(),
+                    info: entry.info.clone(),
+                    timestamp_ms: entry.timestamp.timestamp_millis(),
+                    index,
+                });
+                index += 1;
+            }
+            traced.push(TracedOperation {
+                name: log.operation_name.clone(),
+                operation: operation_of(&log.operation_name, operations).map(str::to_string),
+                entries,
+            });
+        }
+        Trace { operations: traced }
+    }
+
+    // All runs of an operation, in the order they were logged.
+    pub fn runs(&self, operation: &str) -> Vec<&TracedOperation> {
+        let operation = operation.strip_prefix("op_").unwrap_or(operation);
+        self.operations
+            .iter()
+            .filter(|traced| traced.operation.as_deref() == Some(operation))
+            .collect()
+    }
+
+    pub fn latest(&self, operation: &str) -> Result<&TracedOperation, TraceMismatch> {
+        self.runs(operation).last().copied().ok_or_else(|| {
+            TraceMismatch(format!(
+                "Operation {} is not in the trace. Logged operations:\n{}",
+                operation,
+                self.operations
+                    .iter()
+                    .map(|traced| format!("  {}", traced.name))
+                    .collect::<Vec<String>>()
+                    .join("\n")
+            ))
+        })
+    }
+
+    pub fn ran(&self, operation: &str, times: usize) -> Result<(), TraceMismatch> {
+        let runs = self.runs(operation).len();
+        match runs == times {
+            true => Ok(()),
+            false => Err(TraceMismatch(format!(
+                "Operation {} ran {} times, expected {}.",
+                operation, runs, times
+            ))),
+        }
+    }
+
+    // The states follow each other in this order, possibly with other states in between.
+    pub fn went_through(&self, operation: &str, states: &[&str]) -> Result<(), TraceMismatch> {
+        let actual = self.latest(operation)?.states();
+        let mut expected = states.iter().peekable();
+        for state in &actual {
+            if expected
+                .peek()
+                .is_some_and(|next| next.eq_ignore_ascii_case(state))
+            {
+                expected.next();
+            }
+        }
+        match expected.peek() {
+            None => Ok(()),
+            Some(_) => Err(TraceMismatch(format!(
+                "Operation {} did not go through {}.\n{}",
+                operation,
+                states.join(" -> "),
+                line_diff(
+                    &states
+                        .iter()
+                        .map(|s| s.to_string())
+                        .collect::<Vec<String>>(),
+                    &actual
+                )
+            ))),
+        }
+    }
+
+    pub fn retried(&self, operation: &str, times: usize) -> Result<(), TraceMismatch> {
+        let latest = self.latest(operation)?;
+        let (actual, retries) = (latest.states(), latest.retries());
+        match retries == times {
+            true => Ok(()),
+            false => Err(TraceMismatch(format!(
+                "Operation {} was retried {} times, expected {}. States:\n{}",
+                operation,
+                retries,
+                times,
+                actual.join(" -> ")
+            ))),
+        }
+    }
+
+    pub fn ended(&self, operation: &str, state: &str) -> Result<(), TraceMismatch> {
+        let actual = self.latest(operation)?.states();
+        match actual.last() {
+            Some(last) if last.eq_ignore_ascii_case(state) => Ok(()),
+            last => Err(TraceMismatch(format!(
+                "Operation {} ended {}, expected {}.\n{}",
+                operation,
+                last.cloned()
+                    .unwrap_or_else(|| "without any state".to_string()),
+                state,
+                line_diff(&[state.to_string()], &actual)
+            ))),
+        }
+    }
+
+    // The first completion of one operation came before the first start of the other.
+    pub fn completed_before_started(
+        &self,
+        completed: &str,
+        started: &str,
+    ) -> Result<(), TraceMismatch> {
+        let completion = self
+            .runs(completed)
+            .into_iter()
+            .flat_map(|traced| traced.entries.iter())
+            .find(|entry| entry.state.eq_ignore_ascii_case("completed"))
+            .ok_or_else(|| TraceMismatch(format!("Operation {} never completed.", completed)))?;
+        let start = self
+            .runs(started)
+            .into_iter()
+            .flat_map(|traced| traced.entries.iter())
+            .next()
+            .ok_or_else(|| TraceMismatch(format!("Operation {} never started.", started)))?;
+        match (completion.timestamp_ms, completion.index) < (start.timestamp_ms, start.index) {
+            true => Ok(()),
+            false => Err(TraceMismatch(format!(
+                "Operation {} started before {} completed.",
+                started, completed
+            ))),
+        }
+    }
+}
+
+// The operation of the model that a logged run belongs to. The logs name a run
+// "op_{name}_{id}" and the nanoid ids can contain "_" and "-" as well, so it is the longest
+// operation name that the logged name starts with, e.g. "emulate_timeout_bypass_2" and not
+// "emulate_timeout_bypass" for "op_emulate_timeout_bypass_2_V1_tG-R8".
+pub fn operation_of<'a>(logged_name: &str, operations: &'a [String]) -> Option<&'a str> {
+    let logged_name = logged_name.strip_prefix("op_").unwrap_or(logged_name);
+    operations
+        .iter()
+        .map(|name| name.strip_prefix("op_").unwrap_or(name))
+        .filter(|name| {
+            logged_name
+                .strip_prefix(name)
+                .is_some_and(|id| id.is_empty() || id.starts_with('_'))
+        })
+        .max_by_key(|name| name.len())
+}
+
+// The names of all operations of a model that runs can be logged under: the planned ones,
+// the automatic ones and the ones in its SOPs.
+pub fn operation_names(model: &Model) -> Vec<String> {
+    let mut names: Vec<String> = model
+        .operations
+        .iter()
+        .chain(model.auto_operations.iter())
+        .map(|operation| operation.name.clone())
+        .collect();
+    for sop in &model.sops {
+        sop_operation_names(&sop.sop, &mut names);
+    }
+    names.sort();
+    names.dedup();
+    names
+}
+
+fn sop_operation_names(sop: &SOP, names: &mut Vec<String>) {
+    match sop {
+        SOP::Operation(operation) => names.push(operation.name.clone()),
+        SOP::Sequence(sops) | SOP::Parallel(sops) | SOP::Alternative(sops) => {
+            sops.iter().for_each(|sop| sop_operation_names(sop, names))
+        }
+    }
+}
+
+// A line by line diff, "-" for expected lines that are missing and "+" for actual lines
+// that were not expected.
+pub fn line_diff(expected: &[String], actual: &[String]) -> String {
+    let (n, m) = (expected.len(), actual.len());
+    let mut common = vec![vec![0; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            common[i][j] = match expected[i] == actual[j] {
+                true => common[i + 1][j + 1] + 1,
+                false => common[i + 1][j].max(common[i][j + 1]),
+            };
+        }
+    }
+    let mut lines = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && expected[i] == actual[j] {
+            lines.push(format!("  {}", expected[i]));
+            i += 1;
+            j += 1;
+        } else if j < m && (i == n || common[i][j + 1] >= common[i + 1][j]) {
+            lines.push(format!("+ {}", actual[j]));
+            j += 1;
+        } else {
+            lines.push(format!("- {}", expected[i]));
+            i += 1;
+        }
+    }
+    lines.join("\n")
+}
+
+// An operation log like the runner writes it, one entry per millisecond from start_ms.
+#[cfg(test)]
+pub(crate) fn operation_log(
+    operation_name: &str,
+    start_ms: i64,
+    entries: &[(micro_sp::OperationState, &str)],
+) -> OperationLog {
+    use chrono::TimeZone;
+    OperationLog {
+        operation_name: operation_name.to_string(),
+        log: entries
+            .iter()
+            .enumerate()
+            .map(|(i, (state, info))| micro_sp::OperationProcessingLog {
+                timestamp: chrono::Utc
+                    .timestamp_millis_opt(start_ms + i as i64)
+                    .unwrap(),
+                state: state.clone(),
+                info: info.to_string(),
+            })
+            .collect(),
+    }
+}
+
+#[test]
+fn test_trace_assertions() {
+    use micro_sp::OperationState::*;
+
+    let logger = vec![vec![
+        operation_log(
+            "op_gantry_unlock_Xa1",
+            0,
+            &[
+                (Initial, "Starting"),
+                (Executing, "Executing"),
+                (Executing, "Completing"),
+                (Completed, "Completed"),
+            ],
+        ),
+        operation_log(
+            "op_gantry_calibrate_Yb2",
+            4,
+            &[
+                (Initial, "Starting"),
+                (Executing, "Failing"),
+                (Failed, "Retrying 1/2"),
+                (Initial, "Starting"),
+                (Executing, "Failing"),
+                (Failed, "Retrying 2/2"),
+                (Initial, "Starting"),
+                (Executing, "Failing"),
+                (Failed, "Fatal failure"),
+                (Fatal, "Unrecoverable"),
+            ],
+        ),
+    ]];
+    // The same way as the harness reads the logger variable.
+    let logger: Vec<Vec<OperationLog>> =
+        serde_json::from_str(&serde_json::to_string(&logger).unwrap()).unwrap();
+    let operations = vec!["gantry_unlock".to_string(), "gantry_calibrate".to_string()];
+    let trace = Trace::new(&logger, &operations);
+    assert_eq!(trace.operations[0].name, "op_gantry_unlock_Xa1");
+    assert_eq!(
+        trace.operations[1].operation.as_deref(),
+        Some("gantry_calibrate")
+    );
+    assert_eq!(trace.operations[1].entries[2].info, "Retrying 1/2");
+    assert_eq!(trace.operations[1].entries[2].timestamp_ms, 6);
+
+    assert!(
+        trace
+            .went_through("gantry_unlock", &["Initial", "Executing", "Completed"])
+            .is_ok()
+    );
+    assert!(
+        trace
+            .completed_before_started("gantry_unlock", "gantry_calibrate")
+            .is_ok()
+    );
+    assert!(trace.retried("gantry_calibrate", 2).is_ok());
+    assert!(trace.ended("gantry_calibrate", "Fatal").is_ok());
+    assert!(trace.ran("gantry_unlock", 1).is_ok());
+
+    let mismatch = trace
+        .went_through("gantry_calibrate", &["Initial", "Executing", "Completed"])
+        .unwrap_err();
+    assert!(mismatch.0.contains("- Completed"));
+    assert!(mismatch.0.contains("+ Fatal"));
+    assert!(
+        trace
+            .completed_before_started("gantry_calibrate", "gantry_unlock")
+            .is_err()
+    );
+    assert!(trace.latest("gantry_move").is_err());
+}
+
+#[test]
+fn test_operation_of() {
+    use micro_sp::OperationState::*;
+
+    let operations = vec![
+        "emulate_timeout_bypass".to_string(),
+        "emulate_timeout_bypass_2".to_string(),
+    ];
+    assert_eq!(
+        operation_of("op_emulate_timeout_bypass_2_V1_tG-R8", &operations),
+        Some("emulate_timeout_bypass_2")
+    );
+    assert_eq!(
+        operation_of("op_emulate_timeout_bypass_x_tG-R8", &operations),
+        Some("emulate_timeout_bypass")
+    );
+    assert_eq!(operation_of("op_emulate_timeout_2_a", &operations), None);
+
+    let trace = Trace::new(
+        &[vec![
+            operation_log(
+                "op_emulate_timeout_bypass_Xa1",
+                0,
+                &[(Initial, "Starting"), (Bypassed, "Bypassed")],
+            ),
+            operation_log(
+                "op_emulate_timeout_bypass_2_Yb2",
+                2,
+                &[(Initial, "Starting"), (Completed, "Completed")],
+            ),
+        ]],
+        &operations,
+    );
+    assert!(trace.ran("emulate_timeout_bypass", 1).is_ok());
+    assert!(trace.ended("emulate_timeout_bypass", "Bypassed").is_ok());
+    assert!(trace.ended("emulate_timeout_bypass_2", "Completed").is_ok());
+}
+
+#[test]
+fn test_operation_names() {
+    let state = crate::model::state::state();
+    let (model, _) = crate::model::sop_sequence::model("micro_sp", &state);
+    assert_eq!(
+        operation_names(&model),
+        vec!["robot_move_to_a", "robot_move_to_b", "sop_robot_move_ababa"]
+    );
+}